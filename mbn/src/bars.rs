@@ -0,0 +1,483 @@
+//! OHLCV bars built from trade-bearing records.
+//!
+//! `OhlcvBuilder` keeps one open bar per `instrument_id` and closes it when a trade for a later
+//! interval arrives or on `flush`. Bars are stamped with the start of their interval, aligned to
+//! the UNIX epoch or, when a `Session` is set, to the session start.
+use crate::enums::{Action, Schema};
+use crate::error::{Error, Result};
+use crate::record_enum::{RecordEnum, RecordEnumRef};
+use crate::records::{OhlcvMsg, RecordHeader};
+use std::collections::{HashMap, VecDeque};
+
+#[cfg(feature = "python")]
+use pyo3::pyclass;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const NANOS_PER_DAY: u64 = 86_400 * NANOS_PER_SECOND;
+
+/// How intervals without trades are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyBars {
+    /// Emit nothing for intervals without trades.
+    #[default]
+    Skip,
+    /// Emit a zero volume bar at the previous close for every interval inside the session.
+    Fill,
+}
+
+/// Daily trading window as nanoseconds since midnight UTC. `start` may be after `end` for
+/// sessions that span midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Session {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Session {
+    pub fn new(start: u64, end: u64) -> Result<Self> {
+        if start >= NANOS_PER_DAY || end > NANOS_PER_DAY || start == end {
+            return Err(Error::Conversion(format!(
+                "Invalid session window {}..{}",
+                start, end
+            )));
+        }
+        Ok(Self { start, end })
+    }
+
+    pub fn contains(&self, ts: u64) -> bool {
+        let time = ts % NANOS_PER_DAY;
+        if self.start < self.end {
+            time >= self.start && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Bar {
+    start: u64,
+    open: i64,
+    high: i64,
+    low: i64,
+    close: i64,
+    volume: u64,
+}
+
+impl Bar {
+    fn new(start: u64, price: i64, size: u32) -> Self {
+        Self {
+            start,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: size as u64,
+        }
+    }
+
+    fn add(&mut self, price: i64, size: u32) {
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+        self.volume += size as u64;
+    }
+
+    fn to_msg(&self, instrument_id: u32) -> OhlcvMsg {
+        OhlcvMsg {
+            hd: RecordHeader::new::<OhlcvMsg>(instrument_id, self.start),
+            open: self.open,
+            high: self.high,
+            low: self.low,
+            close: self.close,
+            volume: self.volume,
+        }
+    }
+}
+
+/// Streaming resampler from trades to OHLCV bars for one of the `Ohlcv*` schemas.
+#[cfg_attr(feature = "python", pyclass(module = "mbn"))]
+#[derive(Debug, Clone)]
+pub struct OhlcvBuilder {
+    interval: u64,
+    empty_bars: EmptyBars,
+    session: Option<Session>,
+    bars: HashMap<u32, Bar>,
+}
+
+impl OhlcvBuilder {
+    pub fn new(schema: Schema) -> Result<Self> {
+        let interval = match schema {
+            Schema::Ohlcv1S => NANOS_PER_SECOND,
+            Schema::Ohlcv1M => 60 * NANOS_PER_SECOND,
+            Schema::Ohlcv1H => 3_600 * NANOS_PER_SECOND,
+            Schema::Ohlcv1D => NANOS_PER_DAY,
+            _ => {
+                return Err(Error::Conversion(format!(
+                    "Schema {} is not an OHLCV schema",
+                    schema
+                )))
+            }
+        };
+        Ok(Self {
+            interval,
+            empty_bars: EmptyBars::default(),
+            session: None,
+            bars: HashMap::new(),
+        })
+    }
+
+    pub fn with_empty_bars(mut self, empty_bars: EmptyBars) -> Self {
+        self.empty_bars = empty_bars;
+        self
+    }
+
+    /// Drops trades outside `session` and aligns bars to its start, e.g. daily bars that open
+    /// at the exchange session start rather than midnight UTC.
+    pub fn with_session(mut self, session: Session) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Adds a record and returns the bars it closed. Only `TradeMsg` and MBP-1/TBBO records with
+    /// `Action::Trade` contribute, other records are ignored. A trade older than the open bar of
+    /// its instrument is added to that bar.
+    pub fn update(&mut self, record: RecordEnumRef) -> Vec<OhlcvMsg> {
+        let Some((instrument_id, ts, price, size)) = trade_of(&record) else {
+            return Vec::new();
+        };
+        if !self.in_session(ts) {
+            return Vec::new();
+        }
+
+        let start = self.bar_start(ts);
+        let (interval, session, empty_bars) = (self.interval, self.session, self.empty_bars);
+        let mut closed = Vec::new();
+        match self.bars.get_mut(&instrument_id) {
+            Some(bar) if start <= bar.start => bar.add(price, size),
+            Some(bar) => {
+                closed.push(bar.to_msg(instrument_id));
+                if empty_bars == EmptyBars::Fill {
+                    let mut empty_start = bar.start + interval;
+                    while empty_start < start {
+                        if session.is_none_or(|session| session.contains(empty_start)) {
+                            closed.push(Bar::new(empty_start, bar.close, 0).to_msg(instrument_id));
+                        }
+                        empty_start += interval;
+                    }
+                }
+                *bar = Bar::new(start, price, size);
+            }
+            None => {
+                self.bars
+                    .insert(instrument_id, Bar::new(start, price, size));
+            }
+        }
+        closed
+    }
+
+    /// Closes and returns every open bar, ordered by start then instrument.
+    pub fn flush(&mut self) -> Vec<OhlcvMsg> {
+        let mut closed: Vec<OhlcvMsg> = self
+            .bars
+            .drain()
+            .map(|(instrument_id, bar)| bar.to_msg(instrument_id))
+            .collect();
+        closed.sort_by_key(|msg| (msg.hd.ts_event, msg.hd.instrument_id));
+        closed
+    }
+
+    /// Wraps a record iterator, e.g. `DecoderIterator`, yielding bars as they close and the
+    /// remaining open bars once the records run out.
+    pub fn bars<I>(self, records: I) -> OhlcvBars<I>
+    where
+        I: Iterator<Item = std::io::Result<RecordEnum>>,
+    {
+        OhlcvBars {
+            builder: self,
+            records,
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    fn in_session(&self, ts: u64) -> bool {
+        self.session.is_none_or(|session| session.contains(ts))
+    }
+
+    fn bar_start(&self, ts: u64) -> u64 {
+        let anchor = self.session.map_or(0, |session| session.start) % self.interval;
+        let offset = (ts % self.interval + self.interval - anchor) % self.interval;
+        ts.saturating_sub(offset)
+    }
+}
+
+/// Returns instrument_id, ts_event, price and size of a trade-bearing record.
+fn trade_of(record: &RecordEnumRef) -> Option<(u32, u64, i64, u32)> {
+    match record {
+        RecordEnumRef::Trade(msg) => {
+            Some((msg.hd.instrument_id, msg.hd.ts_event, msg.price, msg.size))
+        }
+        RecordEnumRef::Mbp1(msg) | RecordEnumRef::Tbbo(msg)
+            if msg.action == Action::Trade as i8 =>
+        {
+            Some((msg.hd.instrument_id, msg.hd.ts_event, msg.price, msg.size))
+        }
+        _ => None,
+    }
+}
+
+/// Iterator adapter returned by `OhlcvBuilder::bars`.
+pub struct OhlcvBars<I> {
+    builder: OhlcvBuilder,
+    records: I,
+    pending: VecDeque<OhlcvMsg>,
+    done: bool,
+}
+
+impl<I> Iterator for OhlcvBars<I>
+where
+    I: Iterator<Item = std::io::Result<RecordEnum>>,
+{
+    type Item = std::io::Result<OhlcvMsg>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(bar) = self.pending.pop_front() {
+                return Some(Ok(bar));
+            }
+            if self.done {
+                return None;
+            }
+            match self.records.next() {
+                Some(Ok(record)) => self.pending.extend(self.builder.update(record.to_ref())),
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    self.done = true;
+                    self.pending.extend(self.builder.flush());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::Side;
+    use crate::records::{BidAskPair, Mbp1Msg, TradeMsg};
+
+    const T0: u64 = 1_700_000_000 * NANOS_PER_SECOND;
+
+    fn trade(instrument_id: u32, ts_event: u64, price: i64, size: u32) -> TradeMsg {
+        TradeMsg {
+            hd: RecordHeader::new::<TradeMsg>(instrument_id, ts_event),
+            price,
+            size,
+            action: Action::Trade.into(),
+            side: Side::Bid.into(),
+            depth: 0,
+            flags: 0,
+            ts_recv: ts_event,
+            ts_in_delta: 0,
+            sequence: 0,
+        }
+    }
+
+    fn feed(builder: &mut OhlcvBuilder, trades: &[TradeMsg]) -> Vec<OhlcvMsg> {
+        let mut bars = Vec::new();
+        for trade in trades {
+            bars.extend(builder.update(RecordEnumRef::Trade(trade)));
+        }
+        bars.extend(builder.flush());
+        bars
+    }
+
+    #[test]
+    fn test_one_second_bars() -> Result<()> {
+        let mut builder = OhlcvBuilder::new(Schema::Ohlcv1S)?;
+
+        // Test
+        let bars = feed(
+            &mut builder,
+            &[
+                trade(1, T0 + 100, 10, 1),
+                trade(1, T0 + 200, 12, 2),
+                trade(1, T0 + 300, 9, 3),
+                trade(1, T0 + 400, 11, 4),
+                trade(1, T0 + NANOS_PER_SECOND + 5, 20, 5),
+            ],
+        );
+
+        // Validate
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[0].hd.ts_event, T0);
+        assert_eq!(bars[0].hd.instrument_id, 1);
+        assert_eq!(
+            (
+                bars[0].open,
+                bars[0].high,
+                bars[0].low,
+                bars[0].close,
+                bars[0].volume
+            ),
+            (10, 12, 9, 11, 10)
+        );
+        assert_eq!(bars[1].hd.ts_event, T0 + NANOS_PER_SECOND);
+        assert_eq!((bars[1].open, bars[1].volume), (20, 5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_minute_alignment_and_instruments() -> Result<()> {
+        let mut builder = OhlcvBuilder::new(Schema::Ohlcv1M)?;
+        let minute = 60 * NANOS_PER_SECOND;
+        let start = T0 - T0 % minute;
+
+        // Test
+        let bars = feed(
+            &mut builder,
+            &[
+                trade(1, start + 59 * NANOS_PER_SECOND, 10, 1),
+                trade(2, start + 30 * NANOS_PER_SECOND, 50, 1),
+                trade(1, start + minute, 11, 1),
+            ],
+        );
+
+        // Validate
+        let first: Vec<_> = bars
+            .iter()
+            .filter(|bar| bar.hd.instrument_id == 1)
+            .collect();
+        let second: Vec<_> = bars
+            .iter()
+            .filter(|bar| bar.hd.instrument_id == 2)
+            .collect();
+        assert_eq!(first.len(), 2);
+        assert_eq!(first[0].hd.ts_event, start);
+        assert_eq!(first[1].hd.ts_event, start + minute);
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].hd.ts_event, start);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fill_empty_bars() -> Result<()> {
+        let mut builder = OhlcvBuilder::new(Schema::Ohlcv1S)?.with_empty_bars(EmptyBars::Fill);
+
+        // Test
+        let bars = feed(
+            &mut builder,
+            &[
+                trade(1, T0, 10, 1),
+                trade(1, T0 + 3 * NANOS_PER_SECOND, 12, 1),
+            ],
+        );
+
+        // Validate
+        let starts: Vec<u64> = bars.iter().map(|bar| bar.hd.ts_event).collect();
+        assert_eq!(
+            starts,
+            vec![
+                T0,
+                T0 + NANOS_PER_SECOND,
+                T0 + 2 * NANOS_PER_SECOND,
+                T0 + 3 * NANOS_PER_SECOND
+            ]
+        );
+        assert_eq!((bars[1].open, bars[1].close, bars[1].volume), (10, 10, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_alignment_and_filter() -> Result<()> {
+        // Session from 14:30 to 21:00 UTC
+        let hour = 3_600 * NANOS_PER_SECOND;
+        let open = 14 * hour + hour / 2;
+        let session = Session::new(open, 21 * hour)?;
+        let mut builder = OhlcvBuilder::new(Schema::Ohlcv1D)?.with_session(session);
+        let midnight = T0 - T0 % NANOS_PER_DAY;
+
+        // Test
+        let bars = feed(
+            &mut builder,
+            &[
+                trade(1, midnight + 10 * hour, 99, 1),
+                trade(1, midnight + 15 * hour, 10, 1),
+                trade(1, midnight + 20 * hour, 12, 1),
+                trade(1, midnight + 22 * hour, 99, 1),
+            ],
+        );
+
+        // Validate
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].hd.ts_event, midnight + open);
+        assert_eq!((bars[0].open, bars[0].close, bars[0].volume), (10, 12, 2));
+        assert!(Session::new(open, open).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mbp1_trades_only() -> Result<()> {
+        let mut builder = OhlcvBuilder::new(Schema::Ohlcv1S)?;
+        let mut mbp1 = Mbp1Msg {
+            hd: RecordHeader::new::<Mbp1Msg>(1, T0),
+            price: 10,
+            size: 1,
+            action: Action::Add.into(),
+            side: Side::Bid.into(),
+            depth: 0,
+            flags: 0,
+            ts_recv: T0,
+            ts_in_delta: 0,
+            sequence: 0,
+            discriminator: 0,
+            levels: [BidAskPair {
+                bid_px: 9,
+                ask_px: 11,
+                bid_sz: 1,
+                ask_sz: 1,
+                bid_ct: 1,
+                ask_ct: 1,
+            }],
+        };
+
+        // Test
+        builder.update(RecordEnumRef::Mbp1(&mbp1));
+        let ignored = builder.flush();
+        mbp1.action = Action::Trade.into();
+        builder.update(RecordEnumRef::Mbp1(&mbp1));
+        let bars = builder.flush();
+
+        // Validate
+        assert!(ignored.is_empty());
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].close, 10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bars_iterator() -> Result<()> {
+        let records = vec![
+            Ok(RecordEnum::Trade(trade(1, T0, 10, 1))),
+            Ok(RecordEnum::Trade(trade(1, T0 + NANOS_PER_SECOND, 11, 1))),
+            Ok(RecordEnum::Trade(trade(
+                1,
+                T0 + 2 * NANOS_PER_SECOND,
+                12,
+                1,
+            ))),
+        ];
+
+        // Test
+        let bars = OhlcvBuilder::new(Schema::Ohlcv1S)?
+            .bars(records.into_iter())
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        // Validate
+        let closes: Vec<i64> = bars.iter().map(|bar| bar.close).collect();
+        assert_eq!(closes, vec![10, 11, 12]);
+        assert!(OhlcvBuilder::new(Schema::Trade).is_err());
+        Ok(())
+    }
+}
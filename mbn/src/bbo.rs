@@ -0,0 +1,407 @@
+//! Interval-sampled BBO records derived from MBP-1 streams.
+//!
+//! Follows the vendor BBO-1s/BBO-1m semantics: from an instrument's first update on, a `BboMsg`
+//! is emitted at every interval boundary with `ts_recv` set to the interval end, repeating the
+//! last known quote through intervals without updates. It carries the last `levels[0]`, the
+//! `ts_event`, flags and sequence of the last update, and the price, size and side of the last
+//! trade seen, or `UNDEF_PRICE`, 0 and `Side::None` before any trade.
+use crate::enums::{Action, Schema, Side};
+use crate::error::{Error, Result};
+use crate::record_enum::{RecordEnum, RecordEnumRef};
+use crate::records::{BboMsg, BidAskPair, Mbp1Msg, RecordHeader};
+use databento::dbn::UNDEF_PRICE;
+use std::collections::{HashMap, VecDeque};
+use std::os::raw::c_char;
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone)]
+struct Quote {
+    ts_event: u64,
+    flags: u8,
+    sequence: u32,
+    level: BidAskPair,
+    price: i64,
+    size: u32,
+    side: c_char,
+    /// End of the next interval to emit, `None` until the next update without `fill_intervals`.
+    pending: Option<u64>,
+}
+
+impl Quote {
+    fn new(msg: &Mbp1Msg) -> Self {
+        Self {
+            ts_event: msg.hd.ts_event,
+            flags: msg.flags,
+            sequence: msg.sequence,
+            level: msg.levels[0].clone(),
+            price: UNDEF_PRICE,
+            size: 0,
+            side: Side::None.into(),
+            pending: None,
+        }
+    }
+
+    fn update(&mut self, msg: &Mbp1Msg) {
+        self.ts_event = msg.hd.ts_event;
+        self.flags = msg.flags;
+        self.sequence = msg.sequence;
+        self.level = msg.levels[0].clone();
+        if msg.action == Action::Trade as i8 {
+            self.price = msg.price;
+            self.size = msg.size;
+            self.side = msg.side;
+        }
+    }
+
+    fn to_msg(&self, instrument_id: u32, ts_recv: u64) -> BboMsg {
+        BboMsg {
+            hd: RecordHeader::new::<BboMsg>(instrument_id, self.ts_event),
+            price: self.price,
+            size: self.size,
+            side: self.side,
+            flags: self.flags,
+            ts_recv,
+            sequence: self.sequence,
+            levels: [self.level.clone()],
+        }
+    }
+}
+
+/// Streaming sampler from MBP-1 (or TBBO) records to BBO-1s or BBO-1m records.
+#[derive(Debug, Clone)]
+pub struct BboSampler {
+    interval: u64,
+    fill_intervals: bool,
+    quotes: HashMap<u32, Quote>,
+    /// Earliest pending interval end across instruments.
+    next_close: Option<u64>,
+    /// End of the interval of the latest update, where `flush` stops.
+    last_close: u64,
+}
+
+impl BboSampler {
+    pub fn new(schema: Schema) -> Result<Self> {
+        let interval = match schema {
+            Schema::Bbo1S => NANOS_PER_SECOND,
+            Schema::Bbo1M => 60 * NANOS_PER_SECOND,
+            _ => {
+                return Err(Error::Conversion(format!(
+                    "Schema {} is not a BBO schema",
+                    schema
+                )))
+            }
+        };
+        Ok(Self {
+            interval,
+            fill_intervals: true,
+            quotes: HashMap::new(),
+            next_close: None,
+            last_close: 0,
+        })
+    }
+
+    /// Emits the last known quote of an instrument at every interval boundary, also for
+    /// intervals without updates, `true` by default. With `false` only intervals that had an
+    /// update are sampled.
+    pub fn with_fill_intervals(mut self, fill_intervals: bool) -> Self {
+        self.fill_intervals = fill_intervals;
+        self
+    }
+
+    /// Adds a record and returns the samples for every interval that ended at or before its
+    /// `ts_recv`, ordered by interval end then instrument. Records other than MBP-1 are ignored.
+    /// Records are expected in `ts_recv` order.
+    pub fn update(&mut self, record: RecordEnumRef) -> Vec<BboMsg> {
+        let msg = match record {
+            RecordEnumRef::Mbp1(msg) | RecordEnumRef::Tbbo(msg) => msg,
+            _ => return Vec::new(),
+        };
+        let closed = self.close_until(msg.ts_recv);
+
+        let interval_end = (msg.ts_recv / self.interval + 1) * self.interval;
+        let quote = self
+            .quotes
+            .entry(msg.hd.instrument_id)
+            .or_insert_with(|| Quote::new(msg));
+        quote.update(msg);
+        let end = *quote.pending.get_or_insert(interval_end);
+        self.next_close = Some(self.next_close.map_or(end, |next| next.min(end)));
+        self.last_close = self.last_close.max(interval_end);
+        closed
+    }
+
+    /// Emits the samples of intervals up to the one of the latest update, e.g. at the end of a
+    /// stream.
+    pub fn flush(&mut self) -> Vec<BboMsg> {
+        self.close_until(self.last_close)
+    }
+
+    /// Wraps a record iterator, e.g. `DecoderIterator`, yielding samples as their intervals end
+    /// and the remaining ones once the records run out.
+    pub fn samples<I>(self, records: I) -> BboSamples<I>
+    where
+        I: Iterator<Item = std::io::Result<RecordEnum>>,
+    {
+        BboSamples {
+            sampler: self,
+            records,
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    fn close_until(&mut self, ts_recv: u64) -> Vec<BboMsg> {
+        match self.next_close {
+            Some(next) if next <= ts_recv => {}
+            _ => return Vec::new(),
+        }
+
+        let mut closed = Vec::new();
+        let mut next_close = None;
+        for (instrument_id, quote) in self.quotes.iter_mut() {
+            while let Some(end) = quote.pending.filter(|&end| end <= ts_recv) {
+                closed.push(quote.to_msg(*instrument_id, end));
+                quote.pending = self.fill_intervals.then_some(end + self.interval);
+            }
+            if let Some(end) = quote.pending {
+                next_close = Some(next_close.map_or(end, |next: u64| next.min(end)));
+            }
+        }
+        self.next_close = next_close;
+        closed.sort_by_key(|msg| (msg.ts_recv, msg.hd.instrument_id));
+        closed
+    }
+}
+
+/// Iterator adapter returned by `BboSampler::samples`.
+pub struct BboSamples<I> {
+    sampler: BboSampler,
+    records: I,
+    pending: VecDeque<BboMsg>,
+    done: bool,
+}
+
+impl<I> Iterator for BboSamples<I>
+where
+    I: Iterator<Item = std::io::Result<RecordEnum>>,
+{
+    type Item = std::io::Result<BboMsg>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(sample) = self.pending.pop_front() {
+                return Some(Ok(sample));
+            }
+            if self.done {
+                return None;
+            }
+            match self.records.next() {
+                Some(Ok(record)) => self.pending.extend(self.sampler.update(record.to_ref())),
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    self.done = true;
+                    self.pending.extend(self.sampler.flush());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use databento::dbn;
+    use dbn::FlagSet;
+
+    const T0: u64 = 1_700_000_000 * NANOS_PER_SECOND;
+
+    fn mbp1(instrument_id: u32, ts_recv: u64, action: Action, bid_px: i64) -> Mbp1Msg {
+        Mbp1Msg {
+            hd: RecordHeader::new::<Mbp1Msg>(instrument_id, ts_recv - 10),
+            price: bid_px,
+            size: 2,
+            action: action.into(),
+            side: Side::Ask.into(),
+            depth: 0,
+            flags: 0,
+            ts_recv,
+            ts_in_delta: 0,
+            sequence: (ts_recv % 1000) as u32,
+            discriminator: 0,
+            levels: [BidAskPair {
+                bid_px,
+                ask_px: bid_px + 1,
+                bid_sz: 1,
+                ask_sz: 1,
+                bid_ct: 1,
+                ask_ct: 1,
+            }],
+        }
+    }
+
+    fn sample(sampler: &mut BboSampler, msgs: &[Mbp1Msg]) -> Vec<BboMsg> {
+        let mut samples = Vec::new();
+        for msg in msgs {
+            samples.extend(sampler.update(RecordEnumRef::Mbp1(msg)));
+        }
+        samples.extend(sampler.flush());
+        samples
+    }
+
+    #[test]
+    fn test_sample_last_quote_per_interval() -> Result<()> {
+        let mut sampler = BboSampler::new(Schema::Bbo1S)?;
+
+        // Test
+        let samples = sample(
+            &mut sampler,
+            &[
+                mbp1(1, T0 + 100, Action::Add, 10),
+                mbp1(1, T0 + 200, Action::Trade, 11),
+                mbp1(1, T0 + 300, Action::Add, 12),
+                mbp1(1, T0 + 3 * NANOS_PER_SECOND + 1, Action::Add, 13),
+            ],
+        );
+
+        // Validate
+        assert_eq!(samples.len(), 4);
+        assert_eq!(samples[0].ts_recv, T0 + NANOS_PER_SECOND);
+        assert_eq!(samples[0].hd.ts_event, T0 + 290);
+        assert_eq!(samples[0].levels[0].bid_px, 12);
+        assert_eq!((samples[0].price, samples[0].size), (11, 2));
+        assert_eq!(samples[0].side, Side::Ask as i8);
+        for (i, msg) in samples[1..3].iter().enumerate() {
+            assert_eq!(msg.ts_recv, T0 + (i as u64 + 2) * NANOS_PER_SECOND);
+            assert_eq!(msg.hd.ts_event, T0 + 290);
+            assert_eq!(msg.levels[0].bid_px, 12);
+        }
+        assert_eq!(samples[3].ts_recv, T0 + 4 * NANOS_PER_SECOND);
+        assert_eq!(samples[3].levels[0].bid_px, 13);
+        assert_eq!(samples[3].price, 11);
+        Ok(())
+    }
+
+    #[test]
+    fn test_without_fill_intervals() -> Result<()> {
+        let mut sampler = BboSampler::new(Schema::Bbo1S)?.with_fill_intervals(false);
+
+        // Test
+        let samples = sample(
+            &mut sampler,
+            &[
+                mbp1(1, T0 + 100, Action::Add, 10),
+                mbp1(1, T0 + 3 * NANOS_PER_SECOND + 1, Action::Add, 13),
+            ],
+        );
+
+        // Validate
+        let ends: Vec<u64> = samples.iter().map(|msg| msg.ts_recv).collect();
+        assert_eq!(ends, vec![T0 + NANOS_PER_SECOND, T0 + 4 * NANOS_PER_SECOND]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_trade_is_undefined() -> Result<()> {
+        let mut sampler = BboSampler::new(Schema::Bbo1M)?;
+
+        // Test
+        let samples = sample(&mut sampler, &[mbp1(1, T0 + 100, Action::Add, 10)]);
+
+        // Validate
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].price, UNDEF_PRICE);
+        assert_eq!(samples[0].size, 0);
+        assert_eq!(samples[0].side, Side::None as i8);
+        assert_eq!(samples[0].ts_recv % (60 * NANOS_PER_SECOND), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_instruments_close_in_time_order() -> Result<()> {
+        let mut sampler = BboSampler::new(Schema::Bbo1S)?;
+
+        // Test
+        let samples = sample(
+            &mut sampler,
+            &[
+                mbp1(2, T0 + 100, Action::Add, 20),
+                mbp1(1, T0 + 200, Action::Add, 10),
+                mbp1(1, T0 + NANOS_PER_SECOND + 1, Action::Add, 11),
+            ],
+        );
+
+        // Validate
+        let order: Vec<(u64, u32)> = samples
+            .iter()
+            .map(|msg| (msg.ts_recv, msg.hd.instrument_id))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                (T0 + NANOS_PER_SECOND, 1),
+                (T0 + NANOS_PER_SECOND, 2),
+                (T0 + 2 * NANOS_PER_SECOND, 1),
+                (T0 + 2 * NANOS_PER_SECOND, 2)
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches_vendor_record() -> Result<()> {
+        let mut sampler = BboSampler::new(Schema::Bbo1S)?;
+        let samples = sample(&mut sampler, &[mbp1(1, T0 + 200, Action::Trade, 11)]);
+        let level = dbn::BidAskPair {
+            bid_px: 11,
+            ask_px: 12,
+            bid_sz: 1,
+            ask_sz: 1,
+            bid_ct: 1,
+            ask_ct: 1,
+        };
+
+        // Test
+        let vendor = dbn::Mbp1Msg {
+            hd: dbn::RecordHeader::new::<dbn::Mbp1Msg>(1, 1, 1, T0 + 190),
+            price: 11,
+            size: 2,
+            action: 0,
+            side: Side::Ask as i8,
+            flags: FlagSet::empty(),
+            depth: 0,
+            ts_recv: T0 + NANOS_PER_SECOND,
+            ts_in_delta: 0,
+            sequence: 200,
+            levels: [level],
+        };
+
+        // Validate
+        assert!(samples[0] == vendor);
+        assert!(BboSampler::new(Schema::Mbp1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_samples_iterator() -> Result<()> {
+        let records = vec![
+            Ok(RecordEnum::Mbp1(mbp1(1, T0 + 100, Action::Add, 10))),
+            Ok(RecordEnum::Mbp1(mbp1(
+                1,
+                T0 + NANOS_PER_SECOND + 100,
+                Action::Add,
+                11,
+            ))),
+        ];
+
+        // Test
+        let samples = BboSampler::new(Schema::Bbo1S)?
+            .samples(records.into_iter())
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        // Validate
+        let bids: Vec<i64> = samples.iter().map(|msg| msg.levels[0].bid_px).collect();
+        assert_eq!(bids, vec![10, 11]);
+        Ok(())
+    }
+}
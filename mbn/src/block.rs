@@ -0,0 +1,207 @@
+//! Encoder and decoder shared by the block based file formats, `checksum` and `columnar`.
+//!
+//! Both formats start with the regular MBN header, with their `FileLayout` in it, and metadata
+//! and then group records into blocks. `BlockEncoder` buffers the little-endian layout of the records and hands each full
+//! block to a `BlockFormat`, `BlockDecoder` pulls decoded blocks from a `BlockSource` and
+//! yields their records one by one.
+use crate::compression::FileReader;
+use crate::encode::MetadataEncoder;
+use crate::enums::FileLayout;
+use crate::error::Result;
+use crate::layout::write_record_le;
+use crate::metadata::Metadata;
+use crate::record_enum::RecordEnum;
+use crate::record_ref::RecordRef;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Maximum records per block when none is given.
+pub const DEFAULT_BLOCK_RECORDS: u32 = 4096;
+/// Largest little-endian size of the records of one block. `BlockEncoder` starts a new block
+/// before reaching it, and decoders refuse longer blocks before reading them, so a corrupted
+/// length can't make them buffer the rest of the file.
+pub const MAX_BLOCK_LENGTH: usize = 64 * 1024 * 1024;
+
+/// Length of a block payload as written in its header.
+pub(crate) fn payload_len(payload: &[u8]) -> io::Result<u32> {
+    u32::try_from(payload.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Block exceeds u32::MAX"))
+}
+
+/// How a block based format writes its metadata and blocks.
+pub trait BlockFormat {
+    /// Layout written in the file header, so record decoders refuse the file.
+    const LAYOUT: FileLayout;
+
+    /// Writes the file header and metadata, by default as `MetadataEncoder` does.
+    fn write_metadata<W: Write>(&mut self, writer: &mut W, metadata: &Metadata) -> io::Result<()> {
+        MetadataEncoder::new(writer)
+            .with_layout(Self::LAYOUT)
+            .encode_metadata(metadata)
+    }
+
+    /// Whether `record` can join the block whose first record is `first`, both in their
+    /// little-endian layout. Any record can by default.
+    fn same_block(&self, _first: &[u8], _record: &[u8]) -> bool {
+        true
+    }
+
+    /// Writes one block of `count` records, `records` holding their little-endian layout.
+    fn write_block<W: Write>(
+        &mut self,
+        writer: &mut W,
+        count: u32,
+        records: &[u8],
+    ) -> io::Result<()>;
+}
+
+/// Encodes records into the blocks of format `F`. `finish` has to be called to write the last
+/// block.
+pub struct BlockEncoder<W, F> {
+    writer: W,
+    format: F,
+    block_records: u32,
+    count: u32,
+    /// Little-endian layout of the records of the current block.
+    pending: Vec<u8>,
+    record: Vec<u8>,
+}
+
+impl<W: Write, F: BlockFormat + Default> BlockEncoder<W, F> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            format: F::default(),
+            block_records: DEFAULT_BLOCK_RECORDS,
+            count: 0,
+            pending: Vec::new(),
+            record: Vec::new(),
+        }
+    }
+}
+
+impl<W: Write, F: BlockFormat> BlockEncoder<W, F> {
+    /// Sets the maximum records per block, `DEFAULT_BLOCK_RECORDS` by default.
+    pub fn with_block_records(mut self, block_records: u32) -> Self {
+        self.block_records = block_records.max(1);
+        self
+    }
+
+    pub fn encode_metadata(&mut self, metadata: &Metadata) -> io::Result<()> {
+        self.format.write_metadata(&mut self.writer, metadata)
+    }
+
+    pub fn encode_record(&mut self, record: &RecordRef) -> io::Result<()> {
+        self.record.clear();
+        write_record_le(record, &mut self.record)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        if self.count == self.block_records
+            || self.pending.len() + self.record.len() > MAX_BLOCK_LENGTH
+            || (self.count > 0 && !self.format.same_block(&self.pending, &self.record))
+        {
+            self.write_block()?;
+        }
+        self.pending.extend_from_slice(&self.record);
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn encode_records(&mut self, records: &[RecordRef]) -> io::Result<()> {
+        for record in records {
+            self.encode_record(record)?;
+        }
+        Ok(())
+    }
+
+    pub fn encode(&mut self, metadata: &Metadata, records: &[RecordRef]) -> io::Result<()> {
+        self.encode_metadata(metadata)?;
+        self.encode_records(records)?;
+        self.finish()
+    }
+
+    /// Writes the pending block and flushes the writer.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.writer.flush()
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.count == 0 {
+            return Ok(());
+        }
+        self.format
+            .write_block(&mut self.writer, self.count, &self.pending)?;
+        self.pending.clear();
+        self.count = 0;
+        Ok(())
+    }
+}
+
+/// Reader of the blocks of one format.
+pub trait BlockSource: Sized {
+    type Reader: Read;
+
+    /// Reads the header and metadata at the start of `reader`.
+    fn open(reader: Self::Reader) -> Result<(Option<Metadata>, Self)>;
+
+    /// Reads the next block into `records`, returns `false` at the end of the stream.
+    fn read_block(&mut self, records: &mut VecDeque<RecordEnum>) -> io::Result<bool>;
+}
+
+/// Decodes the records of a block based file.
+pub struct BlockDecoder<S> {
+    pub metadata: Option<Metadata>,
+    pub(crate) source: S,
+    records: VecDeque<RecordEnum>,
+}
+
+impl<S: BlockSource> BlockDecoder<S> {
+    pub fn new(reader: S::Reader) -> Result<Self> {
+        let (metadata, source) = S::open(reader)?;
+        Ok(Self::from_source(metadata, source))
+    }
+
+    pub(crate) fn from_source(metadata: Option<Metadata>, source: S) -> Self {
+        Self {
+            metadata,
+            source,
+            records: VecDeque::new(),
+        }
+    }
+
+    pub fn metadata(&mut self) -> Option<Metadata> {
+        self.metadata.clone()
+    }
+
+    pub fn decode(&mut self) -> Result<Vec<RecordEnum>> {
+        let mut records = Vec::new();
+        for record in self {
+            records.push(record?);
+        }
+        Ok(records)
+    }
+}
+
+impl<S: BlockSource<Reader = FileReader>> BlockDecoder<S> {
+    /// Accepts PathBuf, Path and str for file_path. Zstd compressed files are detected and
+    /// decompressed transparently.
+    pub fn from_file<P: AsRef<Path>>(file_path: P) -> Result<Self> {
+        Self::new(FileReader::open(file_path)?)
+    }
+}
+
+impl<S: BlockSource> Iterator for BlockDecoder<S> {
+    type Item = io::Result<RecordEnum>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.records.is_empty() {
+            match self.source.read_block(&mut self.records) {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        self.records.pop_front().map(Ok)
+    }
+}
@@ -0,0 +1,586 @@
+//! Limit order book reconstruction from MBO and MBP records.
+//!
+//! `Market` routes records to a `Book` per `instrument_id`. MBO records are applied order by
+//! order; MBP records replace the levels they carry, so an MBP-1 stream only yields the top of
+//! the book. Derived `Mbp1Msg` and `BboMsg` records are built from the book and the last
+//! event applied to it.
+use crate::enums::{Action, Side};
+use crate::error::{Error, Result};
+use crate::record_enum::RecordEnumRef;
+use crate::records::{BboMsg, BidAskPair, MboMsg, Mbp10Msg, Mbp1Msg, RecordHeader};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::os::raw::c_char;
+
+/// Aggregated size and order count at one price.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PriceLevel {
+    pub price: i64,
+    pub size: u32,
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Order {
+    side: Side,
+    price: i64,
+    size: u32,
+}
+
+/// Fields of the last record applied, carried into derived records.
+#[derive(Debug, Clone, Copy)]
+struct LastEvent {
+    instrument_id: u32,
+    ts_event: u64,
+    price: i64,
+    size: u32,
+    action: c_char,
+    side: c_char,
+    flags: u8,
+    ts_recv: u64,
+    ts_in_delta: i32,
+    sequence: u32,
+}
+
+/// Limit order book for a single instrument.
+#[derive(Debug, Clone, Default)]
+pub struct Book {
+    bids: BTreeMap<Reverse<i64>, PriceLevel>,
+    asks: BTreeMap<i64, PriceLevel>,
+    orders: HashMap<u64, Order>,
+    last: Option<LastEvent>,
+}
+
+impl Book {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies a market by order event. `Trade` and `Fill` do not change the book, the venue
+    /// follows them with the `Cancel` or `Modify` that removes the filled size.
+    pub fn apply_mbo(&mut self, msg: &MboMsg) -> Result<()> {
+        let action = Action::try_from(msg.action as u8)
+            .map_err(|_| Error::Book(format!("Unknown action: {}", msg.action)))?;
+        match action {
+            Action::Add => self.add(msg)?,
+            Action::Modify => self.modify(msg)?,
+            Action::Cancel => self.cancel(msg)?,
+            Action::Clear => self.clear(),
+            Action::Trade | Action::Fill => {}
+        }
+        self.last = Some(LastEvent {
+            instrument_id: msg.hd.instrument_id,
+            ts_event: msg.hd.ts_event,
+            price: msg.price,
+            size: msg.size,
+            action: msg.action,
+            side: msg.side,
+            flags: msg.flags,
+            ts_recv: msg.ts_recv,
+            ts_in_delta: msg.ts_in_delta,
+            sequence: msg.sequence,
+        });
+        Ok(())
+    }
+
+    /// Replaces the book with the top level of an MBP-1 record.
+    pub fn apply_mbp1(&mut self, msg: &Mbp1Msg) {
+        self.replace_levels(&msg.levels);
+        self.last = Some(LastEvent {
+            instrument_id: msg.hd.instrument_id,
+            ts_event: msg.hd.ts_event,
+            price: msg.price,
+            size: msg.size,
+            action: msg.action,
+            side: msg.side,
+            flags: msg.flags,
+            ts_recv: msg.ts_recv,
+            ts_in_delta: msg.ts_in_delta,
+            sequence: msg.sequence,
+        });
+    }
+
+    /// Replaces the book with the ten levels of an MBP-10 record.
+    pub fn apply_mbp10(&mut self, msg: &Mbp10Msg) {
+        self.replace_levels(&msg.levels);
+        self.last = Some(LastEvent {
+            instrument_id: msg.hd.instrument_id,
+            ts_event: msg.hd.ts_event,
+            price: msg.price,
+            size: msg.size,
+            action: msg.action,
+            side: msg.side,
+            flags: msg.flags,
+            ts_recv: msg.ts_recv,
+            ts_in_delta: msg.ts_in_delta,
+            sequence: msg.sequence,
+        });
+    }
+
+    /// Removes every order and level.
+    pub fn clear(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+        self.orders.clear();
+    }
+
+    pub fn best_bid(&self) -> Option<PriceLevel> {
+        self.bids.values().next().copied()
+    }
+
+    pub fn best_ask(&self) -> Option<PriceLevel> {
+        self.asks.values().next().copied()
+    }
+
+    /// Bid levels from the best (highest) price down.
+    pub fn bids(&self) -> impl Iterator<Item = &PriceLevel> {
+        self.bids.values()
+    }
+
+    /// Ask levels from the best (lowest) price up.
+    pub fn asks(&self) -> impl Iterator<Item = &PriceLevel> {
+        self.asks.values()
+    }
+
+    /// Top `N` levels per side, best first. Missing levels are left zeroed.
+    pub fn depth<const N: usize>(&self) -> [BidAskPair; N] {
+        let mut levels = std::array::from_fn(|_| BidAskPair {
+            bid_px: 0,
+            ask_px: 0,
+            bid_sz: 0,
+            ask_sz: 0,
+            bid_ct: 0,
+            ask_ct: 0,
+        });
+        for (pair, bid) in levels.iter_mut().zip(self.bids()) {
+            pair.bid_px = bid.price;
+            pair.bid_sz = bid.size;
+            pair.bid_ct = bid.count;
+        }
+        for (pair, ask) in levels.iter_mut().zip(self.asks()) {
+            pair.ask_px = ask.price;
+            pair.ask_sz = ask.size;
+            pair.ask_ct = ask.count;
+        }
+        levels
+    }
+
+    /// MBP-1 record of the current top of book, tagged with the last event applied.
+    /// Returns `None` until a record has been applied.
+    pub fn to_mbp1(&self) -> Option<Mbp1Msg> {
+        let last = self.last?;
+        Some(Mbp1Msg {
+            hd: RecordHeader::new::<Mbp1Msg>(last.instrument_id, last.ts_event),
+            price: last.price,
+            size: last.size,
+            action: last.action,
+            side: last.side,
+            depth: 0,
+            flags: last.flags,
+            ts_recv: last.ts_recv,
+            ts_in_delta: last.ts_in_delta,
+            sequence: last.sequence,
+            discriminator: 0,
+            levels: self.depth::<1>(),
+        })
+    }
+
+    /// BBO record of the current top of book, tagged with the last event applied.
+    /// Returns `None` until a record has been applied.
+    pub fn to_bbo(&self) -> Option<BboMsg> {
+        let last = self.last?;
+        Some(BboMsg {
+            hd: RecordHeader::new::<BboMsg>(last.instrument_id, last.ts_event),
+            price: last.price,
+            size: last.size,
+            side: last.side,
+            flags: last.flags,
+            ts_recv: last.ts_recv,
+            sequence: last.sequence,
+            levels: self.depth::<1>(),
+        })
+    }
+
+    fn add(&mut self, msg: &MboMsg) -> Result<()> {
+        let side = book_side(msg)?;
+        if self.orders.contains_key(&msg.order_id) {
+            return Err(Error::Book(format!(
+                "Duplicate add for order {}",
+                msg.order_id
+            )));
+        }
+        let order = Order {
+            side,
+            price: msg.price,
+            size: msg.size,
+        };
+        self.orders.insert(msg.order_id, order);
+        self.insert(order);
+        Ok(())
+    }
+
+    /// Modifies of an order not seen yet, e.g. when joining mid session, are treated as adds.
+    fn modify(&mut self, msg: &MboMsg) -> Result<()> {
+        let Some(order) = self.orders.get(&msg.order_id).copied() else {
+            return self.add(msg);
+        };
+        self.remove(order);
+        let order = Order {
+            side: order.side,
+            price: msg.price,
+            size: msg.size,
+        };
+        self.orders.insert(msg.order_id, order);
+        self.insert(order);
+        Ok(())
+    }
+
+    fn cancel(&mut self, msg: &MboMsg) -> Result<()> {
+        let order = self
+            .orders
+            .get_mut(&msg.order_id)
+            .ok_or_else(|| Error::Book(format!("Cancel for unknown order {}", msg.order_id)))?;
+        if msg.size > order.size {
+            return Err(Error::Book(format!(
+                "Cancel of {} exceeds size {} of order {}",
+                msg.size, order.size, msg.order_id
+            )));
+        }
+        let before = *order;
+        order.size -= msg.size;
+        let after = *order;
+
+        self.remove(before);
+        if after.size == 0 {
+            self.orders.remove(&msg.order_id);
+        } else {
+            self.insert(after);
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, order: Order) {
+        let level = match order.side {
+            Side::Bid => self.bids.entry(Reverse(order.price)).or_default(),
+            _ => self.asks.entry(order.price).or_default(),
+        };
+        level.price = order.price;
+        level.size = level.size.saturating_add(order.size);
+        level.count += 1;
+    }
+
+    fn remove(&mut self, order: Order) {
+        let emptied = match order.side {
+            Side::Bid => remove_from(self.bids.get_mut(&Reverse(order.price)), order.size),
+            _ => remove_from(self.asks.get_mut(&order.price), order.size),
+        };
+        if emptied {
+            match order.side {
+                Side::Bid => self.bids.remove(&Reverse(order.price)),
+                _ => self.asks.remove(&order.price),
+            };
+        }
+    }
+
+    fn replace_levels(&mut self, levels: &[BidAskPair]) {
+        self.clear();
+        for level in levels {
+            if level.bid_sz > 0 {
+                self.bids.insert(
+                    Reverse(level.bid_px),
+                    PriceLevel {
+                        price: level.bid_px,
+                        size: level.bid_sz,
+                        count: level.bid_ct,
+                    },
+                );
+            }
+            if level.ask_sz > 0 {
+                self.asks.insert(
+                    level.ask_px,
+                    PriceLevel {
+                        price: level.ask_px,
+                        size: level.ask_sz,
+                        count: level.ask_ct,
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// Removes one order of `size` from the level, returns whether the level is now empty.
+fn remove_from(level: Option<&mut PriceLevel>, size: u32) -> bool {
+    match level {
+        Some(level) => {
+            level.size = level.size.saturating_sub(size);
+            level.count = level.count.saturating_sub(1);
+            level.count == 0
+        }
+        None => false,
+    }
+}
+
+fn book_side(msg: &MboMsg) -> Result<Side> {
+    match Side::try_from(msg.side as u8) {
+        Ok(side @ (Side::Bid | Side::Ask)) => Ok(side),
+        _ => Err(Error::Book(format!(
+            "Order {} has no book side: {}",
+            msg.order_id, msg.side
+        ))),
+    }
+}
+
+/// Order books for every instrument seen in a record stream.
+#[derive(Debug, Clone, Default)]
+pub struct Market {
+    books: HashMap<u32, Book>,
+}
+
+impl Market {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies a record to the book of its instrument. Records that carry no book state,
+    /// e.g. trades and bars, are ignored.
+    pub fn apply(&mut self, record: RecordEnumRef) -> Result<()> {
+        match record {
+            RecordEnumRef::Mbo(msg) => self.book_mut(msg.hd.instrument_id).apply_mbo(msg)?,
+            RecordEnumRef::Mbp1(msg) | RecordEnumRef::Tbbo(msg) => {
+                self.book_mut(msg.hd.instrument_id).apply_mbp1(msg)
+            }
+            RecordEnumRef::Mbp10(msg) => self.book_mut(msg.hd.instrument_id).apply_mbp10(msg),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn book(&self, instrument_id: u32) -> Option<&Book> {
+        self.books.get(&instrument_id)
+    }
+
+    pub fn books(&self) -> impl Iterator<Item = (&u32, &Book)> {
+        self.books.iter()
+    }
+
+    fn book_mut(&mut self, instrument_id: u32) -> &mut Book {
+        self.books.entry(instrument_id).or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record_ref::RecordRef;
+
+    fn mbo(order_id: u64, action: Action, side: Side, price: i64, size: u32) -> MboMsg {
+        MboMsg {
+            hd: RecordHeader::new::<MboMsg>(1, 1622471124),
+            order_id,
+            price,
+            size,
+            flags: 0,
+            channel_id: 0,
+            action: action.into(),
+            side: side.into(),
+            ts_recv: 1622471125,
+            ts_in_delta: 0,
+            sequence: order_id as u32,
+        }
+    }
+
+    fn book_from(msgs: &[MboMsg]) -> Result<Book> {
+        let mut book = Book::new();
+        for msg in msgs {
+            book.apply_mbo(msg)?;
+        }
+        Ok(book)
+    }
+
+    #[test]
+    fn test_add_orders() -> Result<()> {
+        // Test
+        let book = book_from(&[
+            mbo(1, Action::Add, Side::Bid, 100, 10),
+            mbo(2, Action::Add, Side::Bid, 100, 5),
+            mbo(3, Action::Add, Side::Bid, 99, 7),
+            mbo(4, Action::Add, Side::Ask, 101, 3),
+            mbo(5, Action::Add, Side::Ask, 102, 4),
+        ])?;
+
+        // Validate
+        assert_eq!(
+            book.best_bid(),
+            Some(PriceLevel {
+                price: 100,
+                size: 15,
+                count: 2
+            })
+        );
+        assert_eq!(
+            book.best_ask(),
+            Some(PriceLevel {
+                price: 101,
+                size: 3,
+                count: 1
+            })
+        );
+        let prices: Vec<i64> = book.bids().map(|level| level.price).collect();
+        assert_eq!(prices, vec![100, 99]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_modify_cancel_clear() -> Result<()> {
+        let mut book = book_from(&[
+            mbo(1, Action::Add, Side::Bid, 100, 10),
+            mbo(2, Action::Add, Side::Ask, 101, 5),
+        ])?;
+
+        // Test
+        book.apply_mbo(&mbo(1, Action::Modify, Side::Bid, 98, 6))?;
+        book.apply_mbo(&mbo(2, Action::Cancel, Side::Ask, 101, 2))?;
+
+        // Validate
+        assert_eq!(
+            book.best_bid().map(|level| (level.price, level.size)),
+            Some((98, 6))
+        );
+        assert_eq!(
+            book.best_ask().map(|level| (level.price, level.size)),
+            Some((101, 3))
+        );
+
+        book.apply_mbo(&mbo(2, Action::Cancel, Side::Ask, 101, 3))?;
+        assert_eq!(book.best_ask(), None);
+
+        book.apply_mbo(&mbo(0, Action::Clear, Side::None, 0, 0))?;
+        assert_eq!(book.best_bid(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_trade_does_not_change_book() -> Result<()> {
+        let mut book = book_from(&[mbo(1, Action::Add, Side::Bid, 100, 10)])?;
+
+        // Test
+        book.apply_mbo(&mbo(1, Action::Fill, Side::Bid, 100, 4))?;
+        book.apply_mbo(&mbo(9, Action::Trade, Side::Ask, 100, 4))?;
+
+        // Validate
+        assert_eq!(book.best_bid().map(|level| level.size), Some(10));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_events() -> Result<()> {
+        let mut book = book_from(&[mbo(1, Action::Add, Side::Bid, 100, 10)])?;
+
+        // Validate
+        assert!(book
+            .apply_mbo(&mbo(2, Action::Cancel, Side::Bid, 100, 1))
+            .is_err());
+        assert!(book
+            .apply_mbo(&mbo(1, Action::Cancel, Side::Bid, 100, 11))
+            .is_err());
+        assert!(book
+            .apply_mbo(&mbo(1, Action::Add, Side::Bid, 100, 1))
+            .is_err());
+        assert!(book
+            .apply_mbo(&mbo(3, Action::Add, Side::None, 100, 1))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_depth_and_derived_records() -> Result<()> {
+        let book = book_from(&[
+            mbo(1, Action::Add, Side::Bid, 100, 10),
+            mbo(2, Action::Add, Side::Bid, 99, 7),
+            mbo(3, Action::Add, Side::Ask, 101, 3),
+        ])?;
+
+        // Test
+        let depth = book.depth::<3>();
+        let mbp1 = book.to_mbp1().expect("book has events");
+        let bbo = book.to_bbo().expect("book has events");
+
+        // Validate
+        assert_eq!((depth[0].bid_px, depth[0].ask_px), (100, 101));
+        assert_eq!(
+            (depth[1].bid_px, depth[1].bid_sz, depth[1].ask_sz),
+            (99, 7, 0)
+        );
+        assert_eq!(depth[2].bid_sz, 0);
+        assert_eq!(mbp1.levels[0], depth[0]);
+        assert_eq!(mbp1.sequence, 3);
+        assert_eq!(mbp1.hd.instrument_id, 1);
+        assert_eq!(bbo.levels[0], depth[0]);
+        assert_eq!(Book::new().to_mbp1(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_market_routes_by_instrument() -> Result<()> {
+        let mut other = mbo(1, Action::Add, Side::Ask, 200, 1);
+        other.hd.instrument_id = 2;
+        let records = [mbo(1, Action::Add, Side::Bid, 100, 10), other];
+
+        // Test
+        let mut market = Market::new();
+        for record in &records {
+            let record_ref = RecordRef::from(record);
+            let record_enum = RecordEnumRef::from_ref(record_ref).expect("known rtype");
+            market.apply(record_enum)?;
+        }
+
+        // Validate
+        let first = market.book(1).expect("book for instrument 1");
+        let second = market.book(2).expect("book for instrument 2");
+        assert_eq!(first.best_bid().map(|level| level.price), Some(100));
+        assert_eq!(first.best_ask(), None);
+        assert_eq!(second.best_ask().map(|level| level.price), Some(200));
+        assert_eq!(market.books().count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_mbp10_snapshot() {
+        let levels = std::array::from_fn(|i| BidAskPair {
+            bid_px: 100 - i as i64,
+            ask_px: 101 + i as i64,
+            bid_sz: if i < 5 { 10 } else { 0 },
+            ask_sz: 20,
+            bid_ct: 1,
+            ask_ct: 2,
+        });
+        let msg = Mbp10Msg {
+            hd: RecordHeader::new::<Mbp10Msg>(1, 1622471124),
+            price: 100,
+            size: 1,
+            action: Action::Add.into(),
+            side: Side::Bid.into(),
+            depth: 0,
+            flags: 0,
+            ts_recv: 1622471125,
+            ts_in_delta: 0,
+            sequence: 1,
+            discriminator: 0,
+            levels,
+        };
+
+        // Test
+        let mut market = Market::new();
+        market
+            .apply(RecordEnumRef::Mbp10(&msg))
+            .expect("mbp10 applies");
+
+        // Validate
+        let book = market.book(1).expect("book exists");
+        assert_eq!(book.bids().count(), 5);
+        assert_eq!(book.asks().count(), 10);
+        assert_eq!(book.depth::<10>()[9].ask_px, 110);
+        assert_eq!(book.best_bid().map(|level| level.price), Some(100));
+    }
+}
@@ -0,0 +1,553 @@
+//! CRC32 checksummed framing for detecting and recovering from corrupted files.
+//!
+//! A checksummed file starts with the regular MBN header, with `FileLayout::Checksum`, and
+//! metadata, followed by
+//! `CHECKSUM_METADATA_MAGIC` and the CRC32 of every byte before it. Records are then written
+//! in blocks:
+//!
+//! | field       | type                                         |
+//! |-------------|----------------------------------------------|
+//! | magic       | `CHECKSUM_BLOCK_MAGIC`                       |
+//! | count       | u32                                          |
+//! | payload_len | u32                                          |
+//! | crc         | u32, CRC32 of count, payload_len and payload |
+//! | payload     | `payload_len` bytes of little-endian records |
+//!
+//! A truncated or bit-flipped block fails its checksum instead of decoding into garbage. The
+//! magic marks where each block starts, so a reader in recovery mode skips a bad block by
+//! scanning for the next magic whose block passes its checksum.
+use crate::block::{
+    payload_len, BlockDecoder, BlockEncoder, BlockFormat, BlockSource, MAX_BLOCK_LENGTH,
+};
+use crate::compression::FileReader;
+use crate::decode::{read_full, MetadataDecoder};
+use crate::encode::MetadataEncoder;
+use crate::enums::FileLayout;
+use crate::error::{invalid_data, Error, Result};
+use crate::layout::ByteLayout;
+use crate::metadata::Metadata;
+use crate::record_enum::RecordEnum;
+use crate::records::RecordHeader;
+use crc32fast::Hasher;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Signature of the metadata checksum following the metadata block.
+pub const CHECKSUM_METADATA_MAGIC: &[u8; 4] = b"MBNS";
+/// Signature at the start of every block.
+pub const CHECKSUM_BLOCK_MAGIC: &[u8; 4] = b"MBNK";
+
+const BLOCK_HEADER_LENGTH: usize = 16;
+
+fn block_crc(count: u32, payload: &[u8]) -> u32 {
+    let mut hasher = Hasher::new();
+    hasher.update(&count.to_le_bytes());
+    hasher.update(&(payload.len() as u32).to_le_bytes());
+    hasher.update(payload);
+    hasher.finalize()
+}
+
+/// Encodes records into checksummed blocks. Smaller blocks, see `with_block_records`, lose
+/// fewer records to a single corruption.
+pub type ChecksumEncoder<W> = BlockEncoder<W, ChecksumFormat>;
+
+/// Writes the metadata checksum and checksummed blocks, see the module documentation.
+#[derive(Debug, Default)]
+pub struct ChecksumFormat;
+
+impl BlockFormat for ChecksumFormat {
+    const LAYOUT: FileLayout = FileLayout::Checksum;
+
+    fn write_metadata<W: Write>(&mut self, writer: &mut W, metadata: &Metadata) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        MetadataEncoder::new(&mut bytes)
+            .with_layout(Self::LAYOUT)
+            .encode_metadata(metadata)?;
+        writer.write_all(&bytes)?;
+        writer.write_all(CHECKSUM_METADATA_MAGIC)?;
+        writer.write_all(&crc32fast::hash(&bytes).to_le_bytes())
+    }
+
+    fn write_block<W: Write>(
+        &mut self,
+        writer: &mut W,
+        count: u32,
+        records: &[u8],
+    ) -> io::Result<()> {
+        writer.write_all(CHECKSUM_BLOCK_MAGIC)?;
+        writer.write_all(&count.to_le_bytes())?;
+        writer.write_all(&payload_len(records)?.to_le_bytes())?;
+        writer.write_all(&block_crc(count, records).to_le_bytes())?;
+        writer.write_all(records)
+    }
+}
+
+/// Reader that can push bytes back, so a corrupted block can be rescanned for the next magic.
+struct Rewind<R> {
+    inner: R,
+    replay: VecDeque<u8>,
+}
+
+impl<R: Read> Read for Rewind<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.replay.is_empty() {
+            return self.inner.read(buf);
+        }
+        self.replay.read(buf)
+    }
+}
+
+/// Records the bytes read through it, used to checksum the metadata block.
+struct Tee<'a, R> {
+    inner: &'a mut R,
+    bytes: Vec<u8>,
+}
+
+impl<R: Read> Read for Tee<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Outcome of reading one block.
+enum Block {
+    Valid,
+    End,
+    /// The block failed a check, with the reason.
+    Corrupted(String),
+}
+
+/// Decodes a checksummed file, verifying every block before returning its records.
+///
+/// By default the first corrupted block ends decoding with an `InvalidData` error naming its
+/// offset. With `with_recovery(true)` corrupted blocks are skipped instead and their offsets
+/// collected in `corrupted`. `new` returns `Error::Decode` if the metadata fails its checksum.
+pub type ChecksumDecoder<R> = BlockDecoder<ChecksumBlocks<R>>;
+
+/// Reads and verifies the blocks of a checksummed file.
+pub struct ChecksumBlocks<R> {
+    reader: Rewind<R>,
+    metadata_valid: bool,
+    recover: bool,
+    /// Byte offset of the next unread byte in the file.
+    position: u64,
+    blocks: u64,
+    corrupted: Vec<u64>,
+    bytes: Vec<u8>,
+}
+
+impl<R: Read> ChecksumDecoder<R> {
+    /// Skips corrupted blocks and resumes at the next valid one instead of returning an error.
+    pub fn with_recovery(mut self, recover: bool) -> Self {
+        self.source.recover = recover;
+        self
+    }
+
+    /// Offsets of the corrupted blocks skipped so far in recovery mode.
+    pub fn corrupted(&self) -> &[u64] {
+        &self.source.corrupted
+    }
+}
+
+impl<R: Read> BlockSource for ChecksumBlocks<R> {
+    type Reader = R;
+
+    fn open(reader: R) -> Result<(Option<Metadata>, Self)> {
+        let (metadata, blocks) = Self::open_unverified(reader)?;
+        if !blocks.metadata_valid {
+            return Err(Error::Decode("Metadata checksum mismatch".to_string()));
+        }
+        Ok((metadata, blocks))
+    }
+
+    /// Reads the next valid block into `records`, returns `false` at the end of the stream.
+    fn read_block(&mut self, records: &mut VecDeque<RecordEnum>) -> io::Result<bool> {
+        loop {
+            let start = self.position;
+            self.bytes.clear();
+            let reason = match self.try_read_block(records)? {
+                Block::Valid => {
+                    self.position += self.bytes.len() as u64;
+                    self.blocks += 1;
+                    return Ok(true);
+                }
+                Block::End => return Ok(false),
+                Block::Corrupted(reason) => reason,
+            };
+            if !self.recover {
+                return Err(invalid_data(format!(
+                    "Corrupted block at byte {}: {}",
+                    start, reason
+                )));
+            }
+            self.corrupted.push(start);
+            if !self.resync()? {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+impl<R: Read> ChecksumBlocks<R> {
+    /// Reads the metadata, recording whether it passes its checksum instead of failing.
+    fn open_unverified(reader: R) -> Result<(Option<Metadata>, Self)> {
+        let mut reader = Rewind {
+            inner: reader,
+            replay: VecDeque::new(),
+        };
+        let mut tee = Tee {
+            inner: &mut reader,
+            bytes: Vec::new(),
+        };
+        let metadata = MetadataDecoder::new(&mut tee)
+            .with_layout(FileLayout::Checksum)
+            .decode()?;
+        let mut position = tee.bytes.len() as u64;
+        let mut metadata_valid = true;
+        if metadata.is_some() {
+            let crc = crc32fast::hash(&tee.bytes);
+            let mut trailer = [0u8; 8];
+            reader.read_exact(&mut trailer)?;
+            if &trailer[..4] != CHECKSUM_METADATA_MAGIC {
+                return Err(Error::Decode(
+                    "Missing metadata checksum, not a checksummed file".to_string(),
+                ));
+            }
+            metadata_valid = u32::from_le_bytes(trailer[4..].try_into().unwrap()) == crc;
+            position += trailer.len() as u64;
+        }
+        let blocks = Self {
+            reader,
+            metadata_valid,
+            recover: false,
+            position,
+            blocks: 0,
+            corrupted: Vec::new(),
+            bytes: Vec::new(),
+        };
+        Ok((metadata, blocks))
+    }
+
+    /// Reads one block into `bytes` and its records into `records`.
+    fn try_read_block(&mut self, records: &mut VecDeque<RecordEnum>) -> io::Result<Block> {
+        self.bytes.resize(BLOCK_HEADER_LENGTH, 0);
+        let read = read_full(&mut self.reader, &mut self.bytes)?;
+        self.bytes.truncate(read);
+        if read == 0 {
+            return Ok(Block::End);
+        }
+        if read < BLOCK_HEADER_LENGTH {
+            return Ok(Block::Corrupted("truncated block header".to_string()));
+        }
+        if &self.bytes[..4] != CHECKSUM_BLOCK_MAGIC {
+            return Ok(Block::Corrupted("missing block magic".to_string()));
+        }
+        let count = u32::from_le_bytes(self.bytes[4..8].try_into().unwrap());
+        let payload_len = u32::from_le_bytes(self.bytes[8..12].try_into().unwrap()) as u64;
+        let crc = u32::from_le_bytes(self.bytes[12..16].try_into().unwrap());
+        if payload_len > MAX_BLOCK_LENGTH as u64 {
+            return Ok(Block::Corrupted(format!(
+                "block payload of {} bytes exceeds the maximum of {}",
+                payload_len, MAX_BLOCK_LENGTH
+            )));
+        }
+
+        (&mut self.reader)
+            .take(payload_len)
+            .read_to_end(&mut self.bytes)?;
+        let payload = &self.bytes[BLOCK_HEADER_LENGTH..];
+        if payload.len() as u64 != payload_len {
+            return Ok(Block::Corrupted("truncated block payload".to_string()));
+        }
+        if block_crc(count, payload) != crc {
+            return Ok(Block::Corrupted("checksum mismatch".to_string()));
+        }
+
+        // A block that passes its checksum can still hold invalid records if it was written
+        // that way, so none are returned unless all of them decode.
+        let mut decoded = Vec::new();
+        let mut offset = 0;
+        while offset < payload.len() {
+            let record_size = payload[offset] as usize * RecordHeader::LENGTH_MULTIPLIER;
+            let Some(record) = payload
+                .get(offset..offset + record_size)
+                .filter(|_| record_size >= <RecordHeader as ByteLayout>::SIZE)
+            else {
+                return Ok(Block::Corrupted("invalid record length".to_string()));
+            };
+            match RecordEnum::read_le(record) {
+                Ok(record) => decoded.push(record),
+                Err(e) => return Ok(Block::Corrupted(e.to_string())),
+            }
+            offset += record_size;
+        }
+        if decoded.len() != count as usize {
+            return Ok(Block::Corrupted(format!(
+                "block holds {} records, expected {}",
+                decoded.len(),
+                count
+            )));
+        }
+        records.extend(decoded);
+        Ok(Block::Valid)
+    }
+
+    /// Moves to the next block magic after the corrupted block in `bytes`. Returns `false` if
+    /// the stream ends first.
+    fn resync(&mut self) -> io::Result<bool> {
+        // The corrupted block is rescanned from its second byte, as a block may start anywhere
+        // inside a damaged one.
+        let skipped = self.bytes.len().min(1);
+        for &byte in self.bytes[skipped..].iter().rev() {
+            self.reader.replay.push_front(byte);
+        }
+        self.position += skipped as u64;
+
+        let mut window = [0u8; 4];
+        let mut filled = 0;
+        let mut byte = [0u8; 1];
+        loop {
+            if read_full(&mut self.reader, &mut byte)? == 0 {
+                self.position += filled as u64;
+                return Ok(false);
+            }
+            if filled < window.len() {
+                window[filled] = byte[0];
+                filled += 1;
+            } else {
+                window.rotate_left(1);
+                window[3] = byte[0];
+                self.position += 1;
+            }
+            if filled == window.len() && &window == CHECKSUM_BLOCK_MAGIC {
+                for &byte in window.iter().rev() {
+                    self.reader.replay.push_front(byte);
+                }
+                return Ok(true);
+            }
+        }
+    }
+}
+
+/// Result of `verify`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub metadata_valid: bool,
+    /// Blocks that passed their checksum.
+    pub blocks: u64,
+    pub records: u64,
+    /// Byte offsets of the corrupted blocks, in file order.
+    pub corrupted: Vec<u64>,
+}
+
+impl VerifyReport {
+    pub fn is_valid(&self) -> bool {
+        self.metadata_valid && self.corrupted.is_empty()
+    }
+
+    /// Offset of the first corrupted block, 0 if the metadata itself is corrupted.
+    pub fn first_corrupted(&self) -> Option<u64> {
+        if !self.metadata_valid {
+            return Some(0);
+        }
+        self.corrupted.first().copied()
+    }
+}
+
+/// Checks the metadata and every block of a checksummed file without stopping at the first
+/// corruption.
+///
+/// Returns an error only if the metadata can't be parsed at all or the stream fails to read.
+pub fn verify<R: Read>(reader: R) -> Result<VerifyReport> {
+    let (metadata, blocks) = ChecksumBlocks::open_unverified(reader)?;
+    let mut decoder = ChecksumDecoder::from_source(metadata, blocks).with_recovery(true);
+    let mut records = 0;
+    for record in &mut decoder {
+        record?;
+        records += 1;
+    }
+    Ok(VerifyReport {
+        metadata_valid: decoder.source.metadata_valid,
+        blocks: decoder.source.blocks,
+        records,
+        corrupted: decoder.source.corrupted,
+    })
+}
+
+/// Runs `verify` on the file at `file_path`.
+pub fn verify_file<P: AsRef<Path>>(file_path: P) -> Result<VerifyReport> {
+    verify(FileReader::open(file_path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode::{AsyncDecoder, Decoder};
+    use crate::enums::Schema;
+    use crate::record_ref::RecordRef;
+    use crate::records::OhlcvMsg;
+    use crate::symbols::SymbolMap;
+    use serial_test::serial;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    fn records() -> Vec<RecordEnum> {
+        (0..10)
+            .map(|i| {
+                RecordEnum::Ohlcv(OhlcvMsg {
+                    hd: RecordHeader::new::<OhlcvMsg>(1, i),
+                    open: 100,
+                    high: 200,
+                    low: 50,
+                    close: 150,
+                    volume: i,
+                })
+            })
+            .collect()
+    }
+
+    /// Encodes `records` in blocks of 3 and returns the buffer with the offset of each block.
+    fn encode(records: &[RecordEnum]) -> (Vec<u8>, Vec<usize>) {
+        let metadata = Metadata::new(Schema::Ohlcv1S, 0, 10, SymbolMap::new());
+        let refs: Vec<RecordRef> = records.iter().map(|r| r.to_record_ref()).collect();
+        let mut buffer = Vec::new();
+        ChecksumEncoder::new(&mut buffer)
+            .with_block_records(3)
+            .encode(&metadata, &refs)
+            .unwrap();
+        let blocks = buffer
+            .windows(4)
+            .enumerate()
+            .filter(|(_, w)| w == CHECKSUM_BLOCK_MAGIC)
+            .map(|(i, _)| i)
+            .collect();
+        (buffer, blocks)
+    }
+
+    #[test]
+    #[serial]
+    fn test_checksum_round_trip() -> anyhow::Result<()> {
+        let records = records();
+        let (buffer, blocks) = encode(&records);
+        let file = PathBuf::from("tests/checksum.bin");
+        std::fs::write(&file, &buffer)?;
+
+        // Test
+        let mut decoder = ChecksumDecoder::<FileReader>::from_file(&file)?;
+        let report = verify_file(&file)?;
+
+        // Validate
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(decoder.metadata().unwrap().schema, Schema::Ohlcv1S);
+        assert_eq!(decoder.decode()?, records);
+        assert!(report.is_valid());
+        assert_eq!(report.blocks, 4);
+        assert_eq!(report.records, 10);
+
+        // Cleanup
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksum_bit_flip() -> anyhow::Result<()> {
+        let records = records();
+        let (mut buffer, blocks) = encode(&records);
+        buffer[blocks[1] + BLOCK_HEADER_LENGTH + 20] ^= 0x01;
+
+        // Test
+        let strict = ChecksumDecoder::new(Cursor::new(&buffer))?.decode();
+        let mut recovering = ChecksumDecoder::new(Cursor::new(&buffer))?.with_recovery(true);
+        let recovered = recovering.decode()?;
+        let report = verify(Cursor::new(&buffer))?;
+
+        // Validate
+        let err = strict.unwrap_err().to_string();
+        assert!(err.contains(&format!("byte {}", blocks[1])), "{}", err);
+        assert_eq!(recovered.len(), 7);
+        assert_eq!(recovered[..3], records[..3]);
+        assert_eq!(recovered[3..], records[6..]);
+        assert_eq!(recovering.corrupted(), &[blocks[1] as u64]);
+        assert_eq!(report.first_corrupted(), Some(blocks[1] as u64));
+        assert_eq!(report.blocks, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksum_truncated() -> anyhow::Result<()> {
+        let records = records();
+        let (mut buffer, blocks) = encode(&records);
+        buffer.truncate(buffer.len() - 4);
+
+        // Test
+        let strict = ChecksumDecoder::new(Cursor::new(&buffer))?.decode();
+        let recovered = ChecksumDecoder::new(Cursor::new(&buffer))?
+            .with_recovery(true)
+            .decode()?;
+        let report = verify(Cursor::new(&buffer))?;
+
+        // Validate
+        assert!(strict.is_err());
+        assert_eq!(recovered, records[..9]);
+        assert_eq!(report.corrupted, vec![blocks[3] as u64]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksum_oversized_payload_len() -> anyhow::Result<()> {
+        let records = records();
+        let (mut buffer, blocks) = encode(&records);
+        let field = blocks[1] + 8..blocks[1] + 12;
+        buffer[field].copy_from_slice(&(MAX_BLOCK_LENGTH as u32 + 1).to_le_bytes());
+
+        // Test
+        let strict = ChecksumDecoder::new(Cursor::new(&buffer))?.decode();
+        let mut recovering = ChecksumDecoder::new(Cursor::new(&buffer))?.with_recovery(true);
+        let recovered = recovering.decode()?;
+
+        // Validate
+        let err = strict.unwrap_err().to_string();
+        assert!(err.contains("exceeds the maximum"), "{}", err);
+        assert_eq!(recovered[..3], records[..3]);
+        assert_eq!(recovered[3..], records[6..]);
+        assert_eq!(recovering.corrupted(), &[blocks[1] as u64]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_checksum_layout_in_header() -> anyhow::Result<()> {
+        let (buffer, _) = encode(&records());
+
+        // Test
+        let sync = Decoder::new(Cursor::new(&buffer)).map(|_| ());
+        let async_ = AsyncDecoder::new(Cursor::new(&buffer)).await.map(|_| ());
+
+        // Validate
+        for result in [sync, async_] {
+            let err = result.unwrap_err().to_string();
+            assert!(err.contains("read it with ChecksumDecoder"), "{}", err);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksum_corrupted_metadata() -> anyhow::Result<()> {
+        let (mut buffer, _) = encode(&records());
+        // Flip a bit in the metadata start timestamp, which still parses.
+        buffer[10] ^= 0x01;
+
+        // Test
+        let result = ChecksumDecoder::new(Cursor::new(&buffer));
+        let report = verify(Cursor::new(&buffer))?;
+
+        // Validate
+        assert!(result.is_err());
+        assert!(!report.metadata_valid);
+        assert_eq!(report.first_corrupted(), Some(0));
+        assert_eq!(report.records, 10);
+        Ok(())
+    }
+}
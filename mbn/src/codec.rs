@@ -0,0 +1,361 @@
+//! `tokio_util` codec framing MBN records over a byte transport such as a TCP socket.
+//!
+//! Each frame is one record in its little-endian layout, delimited by `RecordHeader::length`.
+//! With `with_handshake(true)` the stream starts with the file header and metadata block, as
+//! written by `MetadataEncoder`, so a connection carries the same bytes as an MBN file.
+use crate::decode::{metadata_prefix_length, MetadataDecoder, UnknownRecordPolicy};
+use crate::encode::MetadataEncoder;
+use crate::error::invalid_data;
+use crate::layout::write_record_le;
+use crate::metadata::Metadata;
+use crate::record_enum::RecordEnum;
+use crate::records::RecordHeader;
+use crate::{MBN_MAGIC, METADATA_LENGTH_PREFIX};
+use bytes::{Buf, BufMut, BytesMut};
+use std::io;
+use std::mem;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Size of `MBN_MAGIC` and the version byte.
+const HANDSHAKE_HEADER: usize = MBN_MAGIC.len() + 1;
+/// Largest metadata block accepted in a handshake when none is given.
+pub const DEFAULT_MAX_HANDSHAKE_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Codec for `tokio_util::codec::Framed`, encoding `RecordEnum` and `Metadata` and decoding
+/// `RecordEnum`.
+///
+/// When the handshake is enabled the decoder reads the metadata frame before any record and
+/// keeps it in `metadata`, and the encoder refuses records until `Metadata` has been sent.
+#[derive(Debug)]
+pub struct MbnCodec {
+    pub metadata: Option<Metadata>,
+    handshake: bool,
+    max_handshake_length: usize,
+    metadata_sent: bool,
+    policy: UnknownRecordPolicy,
+    /// Bytes decoded so far, reported by `Error::Truncated`.
+    position: u64,
+    buffer: Vec<u8>,
+}
+
+impl Default for MbnCodec {
+    fn default() -> Self {
+        Self {
+            metadata: None,
+            handshake: false,
+            max_handshake_length: DEFAULT_MAX_HANDSHAKE_LENGTH,
+            metadata_sent: false,
+            policy: UnknownRecordPolicy::default(),
+            position: 0,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl MbnCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expects, and sends, a metadata frame before the first record.
+    pub fn with_handshake(mut self, handshake: bool) -> Self {
+        self.handshake = handshake;
+        self
+    }
+
+    /// Sets the largest metadata block accepted in the handshake, `DEFAULT_MAX_HANDSHAKE_LENGTH`
+    /// by default. A longer length prefix fails with `InvalidData` before anything is buffered.
+    pub fn with_max_handshake_length(mut self, max_handshake_length: usize) -> Self {
+        self.max_handshake_length = max_handshake_length;
+        self
+    }
+
+    /// Sets how records with an unknown rtype are handled, `UnknownRecordPolicy::Error` by default.
+    pub fn with_unknown_policy(mut self, policy: UnknownRecordPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Metadata received in the handshake, `None` before it arrives or without a handshake.
+    pub fn metadata(&self) -> Option<Metadata> {
+        self.metadata.clone()
+    }
+
+    /// Reads the metadata frame, returns `false` until it is complete.
+    fn decode_handshake(&mut self, src: &mut BytesMut) -> io::Result<bool> {
+        let magic = &src[..src.len().min(MBN_MAGIC.len())];
+        if magic != &MBN_MAGIC[..magic.len()] {
+            return Err(invalid_data(
+                "Missing MBN signature in handshake".to_string(),
+            ));
+        }
+        if src.len() < HANDSHAKE_HEADER {
+            return Ok(false);
+        }
+        // The version decides where the length prefix is, so it is checked before the length.
+        let prefix = metadata_prefix_length(src[MBN_MAGIC.len()])
+            .map_err(|e| invalid_data(e.to_string()))?;
+        if src.len() < prefix {
+            return Ok(false);
+        }
+        let length = u32::from_le_bytes(
+            src[prefix - METADATA_LENGTH_PREFIX..prefix]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        if length > self.max_handshake_length {
+            return Err(invalid_data(format!(
+                "Handshake metadata of {} bytes exceeds the maximum of {}",
+                length, self.max_handshake_length
+            )));
+        }
+        let frame_len = prefix + length;
+        if src.len() < frame_len {
+            src.reserve(frame_len - src.len());
+            return Ok(false);
+        }
+        let frame = src.split_to(frame_len);
+        self.position += frame_len as u64;
+        let metadata = MetadataDecoder::new(&frame[..])
+            .decode()
+            .map_err(|e| invalid_data(e.to_string()))?;
+        self.metadata = metadata;
+        Ok(true)
+    }
+}
+
+impl Decoder for MbnCodec {
+    type Item = RecordEnum;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<RecordEnum>> {
+        if self.handshake && self.metadata.is_none() && !self.decode_handshake(src)? {
+            return Ok(None);
+        }
+        loop {
+            let Some(&length_byte) = src.first() else {
+                return Ok(None);
+            };
+            let length = length_byte as usize * RecordHeader::LENGTH_MULTIPLIER;
+            if length < mem::size_of::<RecordHeader>() {
+                return Err(invalid_data(format!(
+                    "invalid record with length {} shorter than header",
+                    length
+                )));
+            }
+            if src.len() < length {
+                src.reserve(length - src.len());
+                return Ok(None);
+            }
+            self.position += length as u64;
+            if !self.policy.accept(&src[..length])? {
+                src.advance(length);
+                continue;
+            }
+            let frame = src.split_to(length);
+            return RecordEnum::read_le(&frame)
+                .map(Some)
+                .map_err(|e| invalid_data(e.to_string()));
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> io::Result<Option<RecordEnum>> {
+        match self.decode(src)? {
+            Some(record) => Ok(Some(record)),
+            None if src.is_empty() => Ok(None),
+            None if self.handshake && self.metadata.is_none() => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Stream ended inside the metadata handshake",
+            )),
+            None => {
+                let got = src.len();
+                let expected = src[0] as usize * RecordHeader::LENGTH_MULTIPLIER;
+                src.clear();
+                Err(crate::Error::Truncated {
+                    offset: self.position,
+                    expected,
+                    got,
+                }
+                .into_io())
+            }
+        }
+    }
+}
+
+impl Encoder<Metadata> for MbnCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, metadata: Metadata, dst: &mut BytesMut) -> io::Result<()> {
+        self.encode(&metadata, dst)
+    }
+}
+
+impl Encoder<&Metadata> for MbnCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, metadata: &Metadata, dst: &mut BytesMut) -> io::Result<()> {
+        if !self.handshake {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Metadata can only be sent with the handshake enabled",
+            ));
+        }
+        if self.metadata_sent {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Metadata was already sent",
+            ));
+        }
+        MetadataEncoder::new(dst.writer()).encode_metadata(metadata)?;
+        self.metadata_sent = true;
+        Ok(())
+    }
+}
+
+impl Encoder<RecordEnum> for MbnCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, record: RecordEnum, dst: &mut BytesMut) -> io::Result<()> {
+        self.encode(&record, dst)
+    }
+}
+
+impl Encoder<&RecordEnum> for MbnCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, record: &RecordEnum, dst: &mut BytesMut) -> io::Result<()> {
+        if self.handshake && !self.metadata_sent {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Metadata has to be sent before the first record",
+            ));
+        }
+        self.buffer.clear();
+        write_record_le(&record.to_record_ref(), &mut self.buffer)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        dst.extend_from_slice(&self.buffer);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::Schema;
+    use crate::records::OhlcvMsg;
+    use crate::symbols::SymbolMap;
+    use futures::{SinkExt, StreamExt};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    fn records() -> Vec<RecordEnum> {
+        (0..5)
+            .map(|i| {
+                RecordEnum::Ohlcv(OhlcvMsg {
+                    hd: RecordHeader::new::<OhlcvMsg>(1, i),
+                    open: 100,
+                    high: 200,
+                    low: 50,
+                    close: 150,
+                    volume: i,
+                })
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_codec_framed_with_handshake() -> anyhow::Result<()> {
+        let mut mappings = SymbolMap::new();
+        mappings.add_instrument("AAPL", 1);
+        let metadata = Metadata::new(Schema::Ohlcv1S, 0, 5, mappings);
+        let records = records();
+        // A small duplex buffer splits frames across reads.
+        let (client, server) = tokio::io::duplex(7);
+
+        // Test
+        let sent = records.clone();
+        let sent_metadata = metadata.clone();
+        let writer = tokio::spawn(async move {
+            let mut framed = FramedWrite::new(client, MbnCodec::new().with_handshake(true));
+            framed.send(sent_metadata).await?;
+            for record in sent {
+                framed.send(record).await?;
+            }
+            io::Result::Ok(())
+        });
+        let mut framed = FramedRead::new(server, MbnCodec::new().with_handshake(true));
+        let mut received = Vec::new();
+        while let Some(record) = framed.next().await {
+            received.push(record?);
+        }
+        writer.await??;
+
+        // Validate
+        assert_eq!(framed.decoder().metadata(), Some(metadata));
+        assert_eq!(received, records);
+        Ok(())
+    }
+
+    #[test]
+    fn test_codec_partial_frames() -> anyhow::Result<()> {
+        let mut codec = MbnCodec::new();
+        let mut bytes = BytesMut::new();
+        for record in records() {
+            codec.encode(record, &mut bytes)?;
+        }
+        let mut src = BytesMut::new();
+
+        // Test
+        let mut decoded = Vec::new();
+        for chunk in bytes.chunks(9) {
+            src.extend_from_slice(chunk);
+            while let Some(record) = codec.decode(&mut src)? {
+                decoded.push(record);
+            }
+        }
+
+        // Validate
+        assert_eq!(decoded, records());
+        Ok(())
+    }
+
+    #[test]
+    fn test_codec_errors() -> anyhow::Result<()> {
+        let mut codec = MbnCodec::new().with_handshake(true);
+        let mut bytes = BytesMut::new();
+
+        // Test
+        let record_first = codec.encode(records()[0].clone(), &mut bytes);
+        let mut not_mbn = BytesMut::from(&b"XYZ\x01"[..]);
+        let bad_handshake = codec.decode(&mut not_mbn);
+
+        let mut plain = MbnCodec::new();
+        plain.encode(records()[0].clone(), &mut bytes)?;
+        bytes.truncate(bytes.len() - 1);
+        let truncated = plain.decode_eof(&mut bytes);
+
+        let mut oversized = BytesMut::from(&MBN_MAGIC[..]);
+        oversized.extend_from_slice(&[1]);
+        oversized.extend_from_slice(&u32::MAX.to_le_bytes());
+        let too_long = MbnCodec::new().with_handshake(true).decode(&mut oversized);
+
+        let mut newer = BytesMut::from(&MBN_MAGIC[..]);
+        newer.extend_from_slice(&[crate::MBN_VERSION + 1]);
+        newer.extend_from_slice(&u32::MAX.to_le_bytes());
+        let unsupported = MbnCodec::new().with_handshake(true).decode(&mut newer);
+
+        // Validate
+        assert!(record_first.is_err());
+        assert!(bad_handshake.is_err());
+        assert!(matches!(
+            truncated.map_err(crate::Error::from),
+            Err(crate::Error::Truncated { got, .. }) if got == mem::size_of::<OhlcvMsg>() - 1
+        ));
+        assert_eq!(too_long.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(oversized.capacity() < 1024);
+        assert!(unsupported
+            .unwrap_err()
+            .to_string()
+            .contains("Unsupported MBN version"));
+        Ok(())
+    }
+}
@@ -0,0 +1,382 @@
+//! Columnar, delta-encoded block format for archival storage.
+//!
+//! A columnar file starts with the regular MBN header, with `FileLayout::Columnar`, and
+//! metadata, followed by blocks instead of raw records. Each block holds consecutive records sharing one rtype and record length:
+//!
+//! | field       | type                             |
+//! |-------------|----------------------------------|
+//! | magic       | `COLUMNAR_BLOCK_MAGIC`           |
+//! | rtype       | u8                               |
+//! | length      | u8, as in `RecordHeader::length` |
+//! | count       | u32                              |
+//! | payload_len | u32                              |
+//! | payload     | `payload_len` bytes              |
+//!
+//! The payload splits the little-endian layout of each record (see `layout`) into 4 byte
+//! words and stores them column by column, each word as the zigzag varint of its wrapping
+//! difference to the same word of the previous record. Timestamps, prices and header fields
+//! that change slowly between records shrink to one or two bytes, and decoding restores the
+//! exact bytes, so every rtype, including unknown ones, round trips.
+use crate::block::{
+    payload_len, BlockDecoder, BlockEncoder, BlockFormat, BlockSource, MAX_BLOCK_LENGTH,
+};
+use crate::decode::{read_full, MetadataDecoder};
+use crate::enums::FileLayout;
+use crate::error::{invalid_data, Result};
+use crate::layout::ByteLayout;
+use crate::metadata::Metadata;
+use crate::record_enum::RecordEnum;
+use crate::records::RecordHeader;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+/// Signature at the start of every block.
+pub const COLUMNAR_BLOCK_MAGIC: &[u8; 4] = b"MBNB";
+
+const WORD: usize = RecordHeader::LENGTH_MULTIPLIER;
+const BLOCK_HEADER_LENGTH: usize = 14;
+
+fn write_varint(mut value: u32, buffer: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], offset: &mut usize) -> io::Result<u32> {
+    let mut value = 0u32;
+    for shift in (0..32).step_by(7) {
+        let byte = *bytes
+            .get(*offset)
+            .ok_or_else(|| invalid_data("Truncated block payload".to_string()))?;
+        *offset += 1;
+        value |= ((byte & 0x7F) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("Varint longer than 32 bits".to_string()))
+}
+
+fn zigzag(delta: u32) -> u32 {
+    let delta = delta as i32;
+    ((delta << 1) ^ (delta >> 31)) as u32
+}
+
+fn unzigzag(value: u32) -> u32 {
+    (value >> 1) ^ (value & 1).wrapping_neg()
+}
+
+fn word_at(record: &[u8], column: usize) -> u32 {
+    let start = column * WORD;
+    u32::from_le_bytes(record[start..start + WORD].try_into().unwrap())
+}
+
+/// Encodes records into columnar blocks. A block ends at `with_block_records` records or
+/// when the rtype or record length changes.
+pub type ColumnarEncoder<W> = BlockEncoder<W, ColumnarFormat>;
+
+/// Writes delta-encoded columnar blocks, see the module documentation.
+#[derive(Debug, Default)]
+pub struct ColumnarFormat {
+    payload: Vec<u8>,
+}
+
+impl BlockFormat for ColumnarFormat {
+    const LAYOUT: FileLayout = FileLayout::Columnar;
+
+    /// Records share a block when their length and rtype, the first two header bytes, match.
+    fn same_block(&self, first: &[u8], record: &[u8]) -> bool {
+        first[..2] == record[..2]
+    }
+
+    fn write_block<W: Write>(
+        &mut self,
+        writer: &mut W,
+        count: u32,
+        records: &[u8],
+    ) -> io::Result<()> {
+        let (length, rtype) = (records[0], records[1]);
+        let record_size = length as usize * RecordHeader::LENGTH_MULTIPLIER;
+        self.payload.clear();
+        for column in 0..record_size / WORD {
+            let mut previous = 0u32;
+            for record in records.chunks_exact(record_size) {
+                let word = word_at(record, column);
+                write_varint(zigzag(word.wrapping_sub(previous)), &mut self.payload);
+                previous = word;
+            }
+        }
+
+        writer.write_all(COLUMNAR_BLOCK_MAGIC)?;
+        writer.write_all(&[rtype, length])?;
+        writer.write_all(&count.to_le_bytes())?;
+        writer.write_all(&payload_len(&self.payload)?.to_le_bytes())?;
+        writer.write_all(&self.payload)
+    }
+}
+
+/// Decodes a columnar file back into the records it was encoded from.
+pub type ColumnarDecoder<R> = BlockDecoder<ColumnarBlocks<R>>;
+
+/// Reads and decodes the blocks of a columnar file.
+pub struct ColumnarBlocks<R> {
+    reader: R,
+    payload: Vec<u8>,
+}
+
+impl<R: Read> BlockSource for ColumnarBlocks<R> {
+    type Reader = R;
+
+    fn open(mut reader: R) -> Result<(Option<Metadata>, Self)> {
+        let metadata = MetadataDecoder::new(&mut reader)
+            .with_layout(FileLayout::Columnar)
+            .decode()?;
+        let blocks = Self {
+            reader,
+            payload: Vec::new(),
+        };
+        Ok((metadata, blocks))
+    }
+
+    /// Reads the next block into `records`, returns `false` at the end of the stream.
+    fn read_block(&mut self, records: &mut VecDeque<RecordEnum>) -> io::Result<bool> {
+        let mut header = [0u8; BLOCK_HEADER_LENGTH];
+        match read_full(&mut self.reader, &mut header)? {
+            0 => return Ok(false),
+            BLOCK_HEADER_LENGTH => {}
+            _ => return Err(invalid_data("Truncated block header".to_string())),
+        }
+        if &header[..4] != COLUMNAR_BLOCK_MAGIC {
+            return Err(invalid_data("Missing columnar block magic".to_string()));
+        }
+        let (rtype, length) = (header[4], header[5]);
+        let count = u32::from_le_bytes(header[6..10].try_into().unwrap()) as usize;
+        let payload_len = u32::from_le_bytes(header[10..14].try_into().unwrap()) as u64;
+
+        let record_size = length as usize * RecordHeader::LENGTH_MULTIPLIER;
+        if record_size < <RecordHeader as ByteLayout>::SIZE {
+            return Err(invalid_data(format!(
+                "Block record length {} shorter than header",
+                record_size
+            )));
+        }
+        let words = record_size / WORD;
+        if (count as u64) * (record_size as u64) > MAX_BLOCK_LENGTH as u64 {
+            return Err(invalid_data(format!(
+                "Block of {} records exceeds the maximum of {} bytes",
+                count, MAX_BLOCK_LENGTH
+            )));
+        }
+        // Every word takes one to five bytes, which bounds the reads and allocations below.
+        let words_total = (count * words) as u64;
+        if words_total > payload_len || payload_len > words_total * 5 {
+            return Err(invalid_data(format!(
+                "Block of {} records does not fit payload of {} bytes",
+                count, payload_len
+            )));
+        }
+        self.payload.clear();
+        (&mut self.reader)
+            .take(payload_len)
+            .read_to_end(&mut self.payload)?;
+        if self.payload.len() as u64 != payload_len {
+            return Err(invalid_data("Truncated block payload".to_string()));
+        }
+
+        let mut bytes = vec![0u8; count * words * WORD];
+        let mut offset = 0;
+        for column in 0..words {
+            let mut previous = 0u32;
+            for record in 0..count {
+                previous =
+                    previous.wrapping_add(unzigzag(read_varint(&self.payload, &mut offset)?));
+                let start = (record * words + column) * WORD;
+                bytes[start..start + WORD].copy_from_slice(&previous.to_le_bytes());
+            }
+        }
+        if offset != self.payload.len() {
+            return Err(invalid_data("Trailing bytes in block payload".to_string()));
+        }
+
+        for record in bytes.chunks_exact(record_size) {
+            if record[0] != length || record[1] != rtype {
+                return Err(invalid_data(
+                    "Record header does not match its block".to_string(),
+                ));
+            }
+            let record = RecordEnum::read_le(record).map_err(|e| invalid_data(e.to_string()))?;
+            records.push_back(record);
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::DEFAULT_BLOCK_RECORDS;
+    use crate::checksum::ChecksumDecoder;
+    use crate::decode::Decoder;
+    use crate::encode::CombinedEncoder;
+    use crate::enums::Schema;
+    use crate::record_ref::RecordRef;
+    use crate::records::{BidAskPair, Mbp1Msg, OhlcvMsg, TradeMsg};
+    use crate::symbols::SymbolMap;
+    use std::io::Cursor;
+
+    fn records() -> Vec<RecordEnum> {
+        let mut records = Vec::new();
+        for i in 0..500u64 {
+            let ts = 1_700_000_000_000_000_000 + i * 1_000_123;
+            let px = 150_000_000_000 + (i as i64 % 7 - 3) * 10_000_000;
+            records.push(RecordEnum::Mbp1(Mbp1Msg {
+                hd: RecordHeader::new::<Mbp1Msg>(1 + i as u32 % 3, ts),
+                price: px,
+                size: 100,
+                action: b'A' as i8,
+                side: b'B' as i8,
+                depth: 0,
+                flags: 0,
+                ts_recv: ts + 250,
+                ts_in_delta: 17,
+                sequence: i as u32,
+                discriminator: 0,
+                levels: [BidAskPair {
+                    bid_px: px - 10_000_000,
+                    ask_px: px + 10_000_000,
+                    bid_sz: 200,
+                    ask_sz: 300,
+                    bid_ct: 2,
+                    ask_ct: 3,
+                }],
+            }));
+            if i % 50 == 0 {
+                records.push(RecordEnum::Trade(TradeMsg {
+                    hd: RecordHeader::new::<TradeMsg>(1, ts),
+                    price: -px,
+                    size: 5,
+                    action: b'T' as i8,
+                    side: b'A' as i8,
+                    depth: 0,
+                    flags: 0,
+                    ts_recv: ts + 300,
+                    ts_in_delta: -4,
+                    sequence: i as u32,
+                }));
+            }
+        }
+        records.push(RecordEnum::Ohlcv(OhlcvMsg {
+            hd: RecordHeader::new::<OhlcvMsg>(2, u64::MAX),
+            open: i64::MIN,
+            high: i64::MAX,
+            low: 0,
+            close: -1,
+            volume: u64::MAX,
+        }));
+        records
+    }
+
+    fn encode(records: &[RecordEnum], block_records: u32) -> Vec<u8> {
+        let metadata = Metadata::new(Schema::Mbp1, 0, 1, SymbolMap::new());
+        let refs: Vec<RecordRef> = records.iter().map(|r| r.to_record_ref()).collect();
+        let mut buffer = Vec::new();
+        ColumnarEncoder::new(&mut buffer)
+            .with_block_records(block_records)
+            .encode(&metadata, &refs)
+            .unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_columnar_round_trip() -> anyhow::Result<()> {
+        let records = records();
+
+        for block_records in [1, 7, DEFAULT_BLOCK_RECORDS] {
+            // Test
+            let buffer = encode(&records, block_records);
+            let mut decoder = ColumnarDecoder::new(Cursor::new(buffer))?;
+
+            // Validate
+            assert_eq!(decoder.metadata().unwrap().schema, Schema::Mbp1);
+            assert_eq!(decoder.decode()?, records);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_columnar_smaller_than_raw() {
+        let records = records();
+        let metadata = Metadata::new(Schema::Mbp1, 0, 1, SymbolMap::new());
+        let refs: Vec<RecordRef> = records.iter().map(|r| r.to_record_ref()).collect();
+        let mut raw = Vec::new();
+        CombinedEncoder::new(&mut raw)
+            .encode(&metadata, &refs)
+            .unwrap();
+
+        // Test
+        let columnar = encode(&records, DEFAULT_BLOCK_RECORDS);
+
+        // Validate
+        assert!(columnar.len() * 2 < raw.len());
+    }
+
+    #[test]
+    fn test_columnar_corrupt_block() -> anyhow::Result<()> {
+        let buffer = encode(&records(), DEFAULT_BLOCK_RECORDS);
+        let data_offset = buffer
+            .windows(4)
+            .position(|w| w == COLUMNAR_BLOCK_MAGIC)
+            .unwrap();
+
+        let mut truncated = buffer.clone();
+        truncated.truncate(buffer.len() - 3);
+        let mut truncated_header = buffer.clone();
+        truncated_header.extend_from_slice(COLUMNAR_BLOCK_MAGIC);
+        truncated_header.extend_from_slice(&[0u8; 3]);
+        let mut bad_count = buffer.clone();
+        bad_count[data_offset + 6..data_offset + 10].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut bad_magic = buffer;
+        bad_magic[data_offset] = b'X';
+
+        // Validate
+        for buffer in [truncated, truncated_header, bad_count, bad_magic] {
+            assert!(ColumnarDecoder::new(Cursor::new(buffer))?.decode().is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_columnar_layout_in_header() -> anyhow::Result<()> {
+        let columnar = encode(&records(), DEFAULT_BLOCK_RECORDS);
+        let metadata = Metadata::new(Schema::Mbp1, 0, 1, SymbolMap::new());
+        let mut raw = Vec::new();
+        CombinedEncoder::new(&mut raw).encode(&metadata, &[])?;
+        let message = |result: Result<()>| result.unwrap_err().to_string();
+
+        // Test
+        let as_records = message(Decoder::new(Cursor::new(&columnar)).map(|_| ()));
+        let as_checksum = message(ChecksumDecoder::new(Cursor::new(&columnar)).map(|_| ()));
+        let from_records = message(ColumnarDecoder::new(Cursor::new(&raw)).map(|_| ()));
+
+        // Validate
+        assert!(
+            as_records.contains("read it with ColumnarDecoder"),
+            "{}",
+            as_records
+        );
+        assert!(as_checksum.contains("read it with ColumnarDecoder"));
+        assert!(from_records.contains("read it with Decoder"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_zigzag() {
+        for value in [0u32, 1, u32::MAX, i32::MIN as u32, i32::MAX as u32, 12345] {
+            assert_eq!(unzigzag(zigzag(value)), value);
+        }
+        assert_eq!(zigzag((-1i32) as u32), 1);
+        assert_eq!(zigzag(1), 2);
+    }
+}
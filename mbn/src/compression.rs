@@ -0,0 +1,143 @@
+//! Zstd framing for MBN files.
+//!
+//! A compressed file is a plain MBN stream (header, metadata and records) wrapped in zstd
+//! frames. Readers opened with `FileReader::open` or `AsyncFileReader::open` detect the zstd
+//! magic number and decompress transparently.
+use async_compression::tokio::bufread::ZstdDecoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncSeek, ReadBuf};
+
+/// Magic number at the start of every zstd frame.
+pub const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+/// Compression level used when none is given.
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// Whether `bytes` starts with a zstd frame.
+pub fn is_zstd(bytes: &[u8]) -> bool {
+    bytes.starts_with(&ZSTD_MAGIC)
+}
+
+/// File reader returned by `Decoder::from_file`, decompressing zstd files transparently.
+pub enum FileReader {
+    Plain(BufReader<File>),
+    Zstd(zstd::stream::read::Decoder<'static, BufReader<File>>),
+}
+
+impl FileReader {
+    pub fn open<P: AsRef<Path>>(file_path: P) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(file_path.as_ref())?);
+        if is_zstd(reader.fill_buf()?) {
+            Ok(FileReader::Zstd(zstd::stream::read::Decoder::with_buffer(
+                reader,
+            )?))
+        } else {
+            Ok(FileReader::Plain(reader))
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        matches!(self, FileReader::Zstd(_))
+    }
+}
+
+impl Read for FileReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            FileReader::Plain(reader) => reader.read(buf),
+            FileReader::Zstd(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// Compressed files can't be seeked, which includes building an `Index` over them.
+impl Seek for FileReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            FileReader::Plain(reader) => reader.seek(pos),
+            FileReader::Zstd(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Seeking is not supported on zstd compressed files",
+            )),
+        }
+    }
+}
+
+/// Async counterpart of `FileReader`, returned by `AsyncDecoder::from_file`.
+pub enum AsyncFileReader {
+    Plain(tokio::io::BufReader<tokio::fs::File>),
+    Zstd(tokio::io::BufReader<ZstdDecoder<tokio::io::BufReader<tokio::fs::File>>>),
+}
+
+impl AsyncFileReader {
+    pub async fn open<P: AsRef<Path>>(file_path: P) -> io::Result<Self> {
+        let mut reader =
+            tokio::io::BufReader::new(tokio::fs::File::open(file_path.as_ref()).await?);
+        if is_zstd(reader.fill_buf().await?) {
+            let mut decoder = ZstdDecoder::new(reader);
+            decoder.multiple_members(true);
+            Ok(AsyncFileReader::Zstd(tokio::io::BufReader::new(decoder)))
+        } else {
+            Ok(AsyncFileReader::Plain(reader))
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        matches!(self, AsyncFileReader::Zstd(_))
+    }
+}
+
+impl AsyncRead for AsyncFileReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            AsyncFileReader::Plain(reader) => Pin::new(reader).poll_read(cx, buf),
+            AsyncFileReader::Zstd(reader) => Pin::new(reader).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncBufRead for AsyncFileReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        match self.get_mut() {
+            AsyncFileReader::Plain(reader) => Pin::new(reader).poll_fill_buf(cx),
+            AsyncFileReader::Zstd(reader) => Pin::new(reader).poll_fill_buf(cx),
+        }
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        match self.get_mut() {
+            AsyncFileReader::Plain(reader) => Pin::new(reader).consume(amt),
+            AsyncFileReader::Zstd(reader) => Pin::new(reader).consume(amt),
+        }
+    }
+}
+
+/// Like `FileReader`, compressed files can't be seeked.
+impl AsyncSeek for AsyncFileReader {
+    fn start_seek(self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        match self.get_mut() {
+            AsyncFileReader::Plain(reader) => Pin::new(reader).start_seek(position),
+            AsyncFileReader::Zstd(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Seeking is not supported on zstd compressed files",
+            )),
+        }
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        match self.get_mut() {
+            AsyncFileReader::Plain(reader) => Pin::new(reader).poll_complete(cx),
+            AsyncFileReader::Zstd(_) => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Seeking is not supported on zstd compressed files",
+            ))),
+        }
+    }
+}
@@ -1,32 +1,67 @@
-use crate::decode_iterator::{AsyncDecoderIterator, DecoderIterator};
+use crate::compression::{AsyncFileReader, FileReader};
+use crate::decode_iterator::{AsyncDecoderIterator, AsyncRecordStream, DecoderIterator};
+use crate::enums::{FileLayout, RType};
+use crate::error::{Error, Result};
+use crate::index::{read_header, Index, DEFAULT_CHECKPOINT_EVERY};
 use crate::metadata::Metadata;
 use crate::record_enum::RecordEnum;
 use crate::record_ref::*;
 use crate::records::RecordHeader;
-use crate::METADATA_LENGTH;
-use std::io::{BufReader, Read};
+use crate::{MBN_MAGIC, MBN_VERSION, METADATA_LENGTH_PREFIX};
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::io::{Read, Seek, SeekFrom};
 use std::mem;
 use std::path::Path;
-use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncSeek, AsyncSeekExt};
 
 pub struct Decoder<R> {
     pub metadata: Option<Metadata>,
     decoder: RecordDecoder<R>,
+    index: Option<Index>,
 }
 
 impl<R: Read> Decoder<R> {
-    pub fn new(mut reader: R) -> std::io::Result<Self> {
-        let metadata = MetadataDecoder::new(&mut reader).decode()?;
+    /// Streams that don't start with `MBN_MAGIC`, e.g. written by `RecordEncoder`, are decoded
+    /// as records without metadata.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0u8; MBN_MAGIC.len()];
+        let read = read_full(&mut reader, &mut magic)?;
+        let magic = &magic[..read];
+        if !is_magic_prefix(magic) {
+            let mut decoder = RecordDecoder::new(reader);
+            decoder.read_ahead.extend(magic);
+            return Ok(Self {
+                metadata: None,
+                decoder,
+                index: None,
+            });
+        }
+
+        let mut metadata_decoder = MetadataDecoder::new(Read::chain(magic, &mut reader));
+        let metadata = metadata_decoder.decode()?;
+        let data_offset = metadata_decoder.data_offset();
+        let mut decoder = RecordDecoder::new(reader);
+        decoder.set_position(data_offset);
         Ok(Self {
             metadata,
-            decoder: RecordDecoder::new(reader),
+            decoder,
+            index: None,
         })
     }
     pub fn metadata(&mut self) -> Option<Metadata> {
         self.metadata.clone()
     }
 
-    pub fn decode(&mut self) -> crate::error::Result<Vec<RecordEnum>> {
+    /// Sets how records with an unknown rtype are handled, `UnknownRecordPolicy::Error` by default.
+    pub fn with_unknown_policy(mut self, policy: UnknownRecordPolicy) -> Self {
+        self.decoder.set_unknown_policy(policy);
+        self
+    }
+
+    pub fn decode(&mut self) -> Result<Vec<RecordEnum>> {
         Ok(self.decoder.decode_to_owned()?)
     }
 
@@ -38,54 +73,298 @@ impl<R: Read> Decoder<R> {
         self.decoder.decode_iterator()
     }
 
-    /// Accepts PathBuf, Path and str for file_path
-    pub fn from_file<P: AsRef<Path>>(
-        file_path: P,
-    ) -> std::io::Result<Decoder<BufReader<std::fs::File>>> {
-        let file = std::fs::File::open(file_path.as_ref())?;
-        let buffered_reader = BufReader::new(file);
+    /// Accepts PathBuf, Path and str for file_path. Zstd compressed files are detected and
+    /// decompressed transparently.
+    pub fn from_file<P: AsRef<Path>>(file_path: P) -> Result<Decoder<FileReader>> {
+        Decoder::new(FileReader::open(file_path)?)
+    }
+}
+
+impl<R: Read + Seek> Decoder<R> {
+    /// Uses `index` for seeking, e.g. one from `Index::load_or_build`.
+    pub fn with_index(mut self, index: Index) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// Returns the index used for seeking, building it from the stream on first use. The
+    /// read position is restored afterwards.
+    pub fn index(&mut self) -> Result<&Index> {
+        if self.index.is_none() {
+            let reader = self.decoder.get_mut();
+            let position = reader.stream_position()?;
+            let index = Index::build(reader, DEFAULT_CHECKPOINT_EVERY)?;
+            reader.seek(SeekFrom::Start(position))?;
+            self.index = Some(index);
+        }
+        Ok(self.index.as_ref().unwrap())
+    }
+
+    /// Positions the decoder so the next record is the first with `ts_event >= ts`, or the end
+    /// of the stream if there is none. Records are expected to be sorted by `ts_event`.
+    pub fn seek_to_time(&mut self, ts: u64) -> Result<()> {
+        let mut offset = self.index()?.scan_offset(ts);
+        let reader = self.decoder.get_mut();
+        reader.seek(SeekFrom::Start(offset))?;
+        while let Some(header) = read_header(reader)? {
+            if header.ts_event >= ts {
+                break;
+            }
+            offset += header.record_size() as u64;
+            reader.seek(SeekFrom::Start(offset))?;
+        }
+        reader.seek(SeekFrom::Start(offset))?;
+        self.decoder.set_position(offset);
+        Ok(())
+    }
 
-        Ok(Decoder::new(buffered_reader)?)
+    /// Decodes the records with `start <= ts_event < end`.
+    pub fn decode_range(&mut self, start: u64, end: u64) -> Result<Vec<RecordEnum>> {
+        self.seek_to_time(start)?;
+        let mut records = Vec::new();
+        while let Some(record_ref) = self.decoder.decode_ref()? {
+            if record_ref.header().ts_event >= end {
+                break;
+            }
+            records.push(RecordEnum::read_le(record_ref.as_ref())?);
+        }
+        Ok(records)
     }
 }
 
 pub struct MetadataDecoder<R> {
     reader: R,
     read_buffer: Vec<u8>,
+    layout: FileLayout,
+    data_offset: u64,
 }
 
 impl<R: Read> MetadataDecoder<R> {
     pub fn new(reader: R) -> Self {
         Self {
             reader,
-            read_buffer: vec![0; METADATA_LENGTH], // Initialize buffer with fixed size
-        }
-    }
-    pub fn decode(&mut self) -> std::io::Result<Option<Metadata>> {
-        // Try to read the buffer for metadata
-        match self.reader.read_exact(&mut self.read_buffer) {
-            Ok(_) => {
-                // Attempt to deserialize the buffer if data is present
-                match Metadata::deserialize(&self.read_buffer) {
-                    Ok(metadata) => Ok(Some(metadata)),
-                    Err(_) => {
-                        // If deserialization fails, return None
-                        Ok(None)
-                    }
-                }
-            }
+            read_buffer: Vec::new(),
+            layout: FileLayout::Records,
+            data_offset: 0,
+        }
+    }
+
+    /// Sets the layout the file must have, `FileLayout::Records` by default. Headers before
+    /// version 3 carry no layout and are accepted as any layout.
+    pub fn with_layout(mut self, layout: FileLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Bytes taken by the file header and metadata block read by `decode`, i.e. the offset of
+    /// the first record. 0 before `decode` or if the stream held no metadata.
+    pub fn data_offset(&self) -> u64 {
+        self.data_offset
+    }
+
+    /// Reads the file header and the metadata block.
+    ///
+    /// Returns `Ok(None)` on an empty stream and `Error::Decode` if the stream is not an MBN
+    /// file, was written with an unsupported version or has a layout other than `with_layout`.
+    pub fn decode(&mut self) -> Result<Option<Metadata>> {
+        let mut header = [0u8; HEADER_LENGTH];
+        match self.reader.read_exact(&mut header) {
+            Ok(_) => {}
             Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
                 // If we reach EOF without finding metadata, return None
-                Ok(None)
+                return Ok(None);
             }
-            Err(e) => Err(e),
+            Err(e) => return Err(e.into()),
+        }
+
+        let version = check_header(&header)?;
+        let prefix = metadata_prefix_length(version)?;
+        if version >= 3 {
+            let mut layout = [0u8; 1];
+            self.reader.read_exact(&mut layout)?;
+            check_layout(layout[0], self.layout)?;
+        }
+        self.decode_block(prefix).map(Some)
+    }
+
+    /// u32 length prefix followed by the serialized metadata block, which version 2 and later
+    /// zero pad so records start 8 byte aligned. The offset of the first record is taken from
+    /// the prefix, so blocks written with or without padding are both skipped.
+    fn decode_block(&mut self, prefix: usize) -> Result<Metadata> {
+        let mut length_buffer = [0u8; METADATA_LENGTH_PREFIX];
+        self.reader.read_exact(&mut length_buffer)?;
+        let length = u32::from_le_bytes(length_buffer) as usize;
+        // Read through `take` so a corrupt prefix can't allocate more than the stream holds.
+        self.read_buffer.clear();
+        (&mut self.reader)
+            .take(length as u64)
+            .read_to_end(&mut self.read_buffer)?;
+        check_metadata_length(length, self.read_buffer.len())?;
+
+        let metadata = Metadata::deserialize(&self.read_buffer)
+            .map_err(|e| Error::Decode(format!("Invalid metadata block: {}", e)))?;
+        self.data_offset = (prefix + length) as u64;
+        Ok(metadata)
+    }
+}
+
+/// Size in bytes of `MBN_MAGIC` plus the version byte.
+const HEADER_LENGTH: usize = MBN_MAGIC.len() + 1;
+
+/// Validates the magic signature and returns the file version.
+fn check_header(header: &[u8; HEADER_LENGTH]) -> Result<u8> {
+    if &header[..MBN_MAGIC.len()] != MBN_MAGIC {
+        return Err(Error::Decode(
+            "Missing MBN signature, stream is not an MBN file".to_string(),
+        ));
+    }
+    Ok(header[MBN_MAGIC.len()])
+}
+
+/// Fails if the stream ended before the `expected` metadata bytes were read.
+fn check_metadata_length(expected: usize, got: usize) -> Result<()> {
+    if got < expected {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!(
+                "Metadata block truncated: expected {} bytes, got {}",
+                expected, got
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Bytes before the metadata block of a stream of `version`: the file header, the layout byte
+/// since version 3 and the length prefix. Fails with `Error::Decode` if this build can't read
+/// `version`.
+pub(crate) fn metadata_prefix_length(version: u8) -> Result<usize> {
+    match version {
+        1 | 2 => Ok(HEADER_LENGTH + METADATA_LENGTH_PREFIX),
+        3 => Ok(HEADER_LENGTH + 1 + METADATA_LENGTH_PREFIX),
+        version => Err(unsupported_version(version)),
+    }
+}
+
+/// Fails unless the layout byte of a version 3 header is `expected`, naming the decoder for
+/// the file otherwise.
+fn check_layout(layout: u8, expected: FileLayout) -> Result<()> {
+    let layout = FileLayout::try_from(layout)
+        .map_err(|_| Error::Decode(format!("Unknown MBN file layout {}", layout)))?;
+    if layout != expected {
+        return Err(Error::Decode(format!(
+            "MBN file has the {} layout, read it with {}",
+            layout,
+            layout.decoder()
+        )));
+    }
+    Ok(())
+}
+
+fn unsupported_version(version: u8) -> Error {
+    Error::Decode(format!(
+        "Unsupported MBN version {}, this build reads up to version {}",
+        version, MBN_VERSION
+    ))
+}
+
+/// Reads until `buf` is full or the stream ends, returning the bytes read.
+pub(crate) fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// Truncates the MBN file at `file_path` back to the end of its last complete record, so an
+/// interrupted recording can be decoded and appended to again. Returns the number of bytes
+/// removed, 0 if the file was intact.
+///
+/// The file header and metadata have to be complete. Zstd compressed files can't be repaired.
+pub fn repair_file<P: AsRef<Path>>(file_path: P) -> Result<u64> {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(file_path.as_ref())?;
+    let file_len = file.metadata()?.len();
+    let (_, _, complete_len) = scan_records(&mut std::io::BufReader::new(&file), file_len)?;
+    if complete_len < file_len {
+        file.set_len(complete_len)?;
+        file.sync_all()?;
+    }
+    Ok(file_len - complete_len)
+}
+
+/// Reads the metadata of a file of `file_len` bytes and walks its record headers. Returns the
+/// metadata, the offset of the first record and the length up to the end of the last complete
+/// record.
+pub(crate) fn scan_records<R: Read + Seek>(
+    reader: &mut R,
+    file_len: u64,
+) -> Result<(Option<Metadata>, u64, u64)> {
+    reader.seek(SeekFrom::Start(0))?;
+    let mut metadata_decoder = MetadataDecoder::new(&mut *reader);
+    let metadata = metadata_decoder.decode()?;
+    let data_offset = metadata_decoder.data_offset();
+    let mut offset = data_offset;
+    while let Some(header) = read_header(reader)? {
+        let end = offset + header.record_size() as u64;
+        if end > file_len {
+            break;
+        }
+        offset = end;
+        reader.seek(SeekFrom::Start(offset))?;
+    }
+    Ok((metadata, data_offset, offset))
+}
+
+/// How record decoders handle records whose rtype is unknown to this version of the crate,
+/// e.g. in files written by a newer producer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownRecordPolicy {
+    /// Fail with an `InvalidData` error.
+    #[default]
+    Error,
+    /// Skip the record using the length in its header.
+    Skip,
+    /// Return the record as `RecordEnum::Unknown` carrying its raw bytes.
+    Surface,
+}
+
+impl UnknownRecordPolicy {
+    /// Returns whether the record in `buffer` should be returned, skipped, or is an error.
+    pub(crate) fn accept(&self, buffer: &[u8]) -> std::io::Result<bool> {
+        let rtype = buffer[1];
+        if RType::try_from(rtype).is_ok() {
+            return Ok(true);
+        }
+        match self {
+            UnknownRecordPolicy::Error => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("unknown record type {:#04x}", rtype),
+            )),
+            UnknownRecordPolicy::Skip => Ok(false),
+            UnknownRecordPolicy::Surface => Ok(true),
         }
     }
 }
 
 pub struct RecordDecoder<R> {
     reader: R,
-    read_buffer: Vec<u8>,
+    read_buffer: AlignedBuffer,
+    policy: UnknownRecordPolicy,
+    /// Offset of the next record in the stream, reported by `Error::Truncated`.
+    position: u64,
+    /// Bytes taken from `reader` ahead of the records, e.g. by `Decoder::new` looking for the
+    /// file header, read before the reader itself.
+    read_ahead: VecDeque<u8>,
 }
 
 impl<R> RecordDecoder<R>
@@ -95,27 +374,69 @@ where
     pub fn new(reader: R) -> Self {
         Self {
             reader,
-            read_buffer: vec![0],
+            read_buffer: AlignedBuffer::new(),
+            policy: UnknownRecordPolicy::default(),
+            position: 0,
+            read_ahead: VecDeque::new(),
         }
     }
 
-    pub fn decode_to_owned(&mut self) -> crate::error::Result<Vec<RecordEnum>> {
+    /// Sets how records with an unknown rtype are handled, `UnknownRecordPolicy::Error` by default.
+    pub fn with_unknown_policy(mut self, policy: UnknownRecordPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn set_unknown_policy(&mut self, policy: UnknownRecordPolicy) {
+        self.policy = policy;
+    }
+
+    /// Sets the stream offset of the next record, e.g. the length of the metadata already read.
+    pub(crate) fn set_position(&mut self, position: u64) {
+        self.position = position;
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub fn decode_to_owned(&mut self) -> Result<Vec<RecordEnum>> {
         let mut records = Vec::new();
         while let Some(record_ref) = self.decode_ref()? {
-            let record = RecordEnum::from_ref(record_ref)?;
+            let record = RecordEnum::read_le(record_ref.as_ref())?;
             records.push(record);
         }
         Ok(records)
     }
 
     pub fn decode_iterator(&mut self) -> DecoderIterator<R> {
-        DecoderIterator::new(&mut self.reader)
+        DecoderIterator::new(self)
     }
 
+    /// Returns a zero-copy view of the next record. The view reinterprets the bytes in memory,
+    /// which matches the explicit `layout` on little-endian hosts only; `decode_to_owned`
+    /// reads field by field and is host independent.
     pub fn decode_ref(&mut self) -> std::io::Result<Option<RecordRef>> {
-        if let Err(err) = self.reader.read_exact(&mut self.read_buffer[..1]) {
-            if err.kind() == std::io::ErrorKind::UnexpectedEof {
+        loop {
+            if !self.read_record()? {
                 return Ok(None);
+            }
+            if self.policy.accept(self.read_buffer.as_slice())? {
+                break;
+            }
+        }
+        RecordRef::try_new_opaque(self.read_buffer.as_slice())
+            .map(Some)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    /// Reads the next record into the read buffer, returns `false` at the end of the stream.
+    fn read_record(&mut self) -> std::io::Result<bool> {
+        let mut reader = (&mut self.read_ahead).chain(&mut self.reader);
+        let mut length_byte = [0u8; 1];
+        if let Err(err) = reader.read_exact(&mut length_byte) {
+            if err.kind() == std::io::ErrorKind::UnexpectedEof {
+                return Ok(false);
             } else {
                 return Err(std::io::Error::new(
                     err.kind(),
@@ -123,35 +444,35 @@ where
                 ));
             }
         }
-        let length = self.read_buffer[0] as usize * RecordHeader::LENGTH_MULTIPLIER;
+        let length = length_byte[0] as usize * RecordHeader::LENGTH_MULTIPLIER;
         if length < mem::size_of::<RecordHeader>() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                format!("invalid record with length {} shorter than header", length),
-            ));
-        }
-        if length > self.read_buffer.len() {
-            self.read_buffer.resize(length, 0);
+            return Err(Error::Framing {
+                offset: self.position,
+                length,
+            }
+            .into_io());
         }
-        if let Err(err) = self.reader.read_exact(&mut self.read_buffer[1..length]) {
-            if err.kind() == std::io::ErrorKind::UnexpectedEof {
-                return Ok(None);
-            } else {
-                return Err(std::io::Error::new(
-                    err.kind(),
-                    format!("decoding record reference: {}", err),
-                ));
+        self.read_buffer.resize(length);
+        let buffer = self.read_buffer.as_mut_slice();
+        buffer[0] = length_byte[0];
+        let got = 1 + read_full(&mut reader, &mut buffer[1..]).map_err(|err| {
+            std::io::Error::new(err.kind(), format!("decoding record reference: {}", err))
+        })?;
+        if got < length {
+            return Err(Error::Truncated {
+                offset: self.position,
+                expected: length,
+                got,
             }
+            .into_io());
         }
-        // Safety: `read_buffer` is resized to contain at least `length` bytes.
-        Ok(Some(unsafe { RecordRef::new(&self.read_buffer) }))
+        self.position += length as u64;
+        Ok(true)
     }
 
-    pub fn from_file(file_path: &Path) -> std::io::Result<RecordDecoder<BufReader<std::fs::File>>> {
-        let file = std::fs::File::open(file_path)?;
-        let buffered_reader = BufReader::new(file);
-
-        Ok(RecordDecoder::new(buffered_reader))
+    /// Zstd compressed files are detected and decompressed transparently.
+    pub fn from_file(file_path: &Path) -> std::io::Result<RecordDecoder<FileReader>> {
+        Ok(RecordDecoder::new(FileReader::open(file_path)?))
     }
 }
 
@@ -162,19 +483,32 @@ pub struct AsyncDecoder<R> {
 }
 
 impl<R: AsyncBufRead + Unpin> AsyncDecoder<R> {
-    pub async fn new(mut reader: R) -> tokio::io::Result<Self> {
-        let metadata = AsyncMetadataDecoder::new(&mut reader).decode().await?;
-        Ok(Self {
-            metadata,
-            decoder: AsyncRecordDecoder::new(reader),
-        })
+    /// Streams that don't start with `MBN_MAGIC`, e.g. written by `AsyncRecordEncoder`, are
+    /// decoded as records without metadata.
+    pub async fn new(mut reader: R) -> Result<Self> {
+        let (metadata, data_offset) = if starts_with_magic(&mut reader).await? {
+            let mut metadata_decoder = AsyncMetadataDecoder::new(&mut reader);
+            let metadata = metadata_decoder.decode().await?;
+            (metadata, metadata_decoder.data_offset())
+        } else {
+            (None, 0)
+        };
+        let mut decoder = AsyncRecordDecoder::new(reader);
+        decoder.set_position(data_offset);
+        Ok(Self { metadata, decoder })
     }
 
     pub fn metadata(&mut self) -> Option<Metadata> {
         self.metadata.clone()
     }
 
-    pub async fn decode(&mut self) -> crate::error::Result<Vec<RecordEnum>> {
+    /// Sets how records with an unknown rtype are handled, `UnknownRecordPolicy::Error` by default.
+    pub fn with_unknown_policy(mut self, policy: UnknownRecordPolicy) -> Self {
+        self.decoder.set_unknown_policy(policy);
+        self
+    }
+
+    pub async fn decode(&mut self) -> Result<Vec<RecordEnum>> {
         Ok(self.decoder.decode_to_owned().await?)
     }
 
@@ -186,54 +520,116 @@ impl<R: AsyncBufRead + Unpin> AsyncDecoder<R> {
         self.decoder.decode_iterator()
     }
 
-    /// Accepts PathBuf, Path and str for file_path
-    pub async fn from_file<P: AsRef<Path>>(
-        file_path: P,
-    ) -> tokio::io::Result<AsyncDecoder<tokio::io::BufReader<tokio::fs::File>>> {
-        let file = tokio::fs::File::open(file_path.as_ref()).await?;
-        let buffered_reader = tokio::io::BufReader::new(file);
+    /// Converts the decoder into a stream of the remaining records that owns the reader.
+    pub fn into_stream(self) -> AsyncRecordStream<R> {
+        self.decoder.into_stream()
+    }
 
-        AsyncDecoder::new(buffered_reader).await
+    /// Accepts PathBuf, Path and str for file_path. Zstd compressed files are detected and
+    /// decompressed transparently.
+    pub async fn from_file<P: AsRef<Path>>(file_path: P) -> Result<AsyncDecoder<AsyncFileReader>> {
+        AsyncDecoder::new(AsyncFileReader::open(file_path).await?).await
     }
 }
 
+impl<R: AsyncBufRead + AsyncSeek + Unpin> AsyncDecoder<R> {
+    /// Positions the decoder at the record starting at byte `offset` of the stream, e.g. an
+    /// offset from an `Index`.
+    pub async fn seek_to_offset(&mut self, offset: u64) -> Result<()> {
+        self.decoder.seek(offset).await
+    }
+}
+
+/// Peeks at the buffered bytes without consuming them, see `is_magic_prefix`.
+async fn starts_with_magic<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<bool> {
+    let available = reader.fill_buf().await?;
+    Ok(is_magic_prefix(available))
+}
+
+/// Whether a stream starting with `bytes` is read as an MBN file. Bytes shorter than the magic,
+/// including none, count as a match if they are a prefix of it, leaving the decision to the
+/// metadata decoder.
+fn is_magic_prefix(bytes: &[u8]) -> bool {
+    let prefix = bytes.len().min(MBN_MAGIC.len());
+    bytes[..prefix] == MBN_MAGIC[..prefix]
+}
+
 pub struct AsyncMetadataDecoder<R> {
     reader: R,
-    // read_buffer: Vec<u8>,
+    read_buffer: Vec<u8>,
+    layout: FileLayout,
+    data_offset: u64,
 }
 
 impl<R: AsyncBufRead + Unpin> AsyncMetadataDecoder<R> {
     pub fn new(reader: R) -> Self {
         Self {
             reader,
-            // read_buffer: vec![0; METADATA_LENGTH], // Initialize buffer with fixed size
+            read_buffer: Vec::new(),
+            layout: FileLayout::Records,
+            data_offset: 0,
         }
     }
 
-    pub async fn decode(&mut self) -> tokio::io::Result<Option<Metadata>> {
-        // Peek into the buffer without consuming bytes
-        let buffer = self.reader.fill_buf().await?;
+    /// Sets the layout the file must have, see `MetadataDecoder::with_layout`.
+    pub fn with_layout(mut self, layout: FileLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Offset of the first record, see `MetadataDecoder::data_offset`.
+    pub fn data_offset(&self) -> u64 {
+        self.data_offset
+    }
 
-        // Ensure we have enough bytes for metadata
-        if buffer.len() < METADATA_LENGTH {
-            return Ok(None); // Not enough data, return None (no metadata)
+    /// Reads the file header and the metadata block, see `MetadataDecoder::decode`.
+    pub async fn decode(&mut self) -> Result<Option<Metadata>> {
+        let mut header = [0u8; HEADER_LENGTH];
+        match self.reader.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == tokio::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
         }
 
-        // Attempt to deserialize the peeked bytes
-        match Metadata::deserialize(&buffer[..METADATA_LENGTH]) {
-            Ok(metadata) => {
-                // Consume the metadata bytes from the buffer
-                self.reader.consume(METADATA_LENGTH);
-                Ok(Some(metadata)) // Return the deserialized metadata
-            }
-            Err(_) => Ok(None),
+        let version = check_header(&header)?;
+        let prefix = metadata_prefix_length(version)?;
+        if version >= 3 {
+            let layout = self.reader.read_u8().await?;
+            check_layout(layout, self.layout)?;
         }
+        self.decode_block(prefix).await.map(Some)
+    }
+
+    /// See `MetadataDecoder::decode_block`.
+    async fn decode_block(&mut self, prefix: usize) -> Result<Metadata> {
+        let mut length_buffer = [0u8; METADATA_LENGTH_PREFIX];
+        self.reader.read_exact(&mut length_buffer).await?;
+        let length = u32::from_le_bytes(length_buffer) as usize;
+        self.read_buffer.clear();
+        (&mut self.reader)
+            .take(length as u64)
+            .read_to_end(&mut self.read_buffer)
+            .await?;
+        check_metadata_length(length, self.read_buffer.len())?;
+
+        let metadata = Metadata::deserialize(&self.read_buffer)
+            .map_err(|e| Error::Decode(format!("Invalid metadata block: {}", e)))?;
+        self.data_offset = (prefix + length) as u64;
+        Ok(metadata)
     }
 }
 
+/// Async record decoder. Reads are cancellation safe: the bytes of a partially read record are
+/// kept in the decoder, so dropping a pending `decode_ref` future and calling it again resumes
+/// the same record.
 pub struct AsyncRecordDecoder<R> {
     reader: R,
-    read_buffer: Vec<u8>,
+    read_buffer: AlignedBuffer,
+    policy: UnknownRecordPolicy,
+    /// Offset of the next record in the stream, reported by `Error::Truncated`.
+    position: u64,
+    /// Bytes of the current record read into `read_buffer` so far.
+    filled: usize,
 }
 
 impl<R> AsyncRecordDecoder<R>
@@ -243,14 +639,43 @@ where
     pub fn new(reader: R) -> Self {
         Self {
             reader,
-            read_buffer: vec![0],
+            read_buffer: AlignedBuffer::new(),
+            policy: UnknownRecordPolicy::default(),
+            position: 0,
+            filled: 0,
         }
     }
 
-    pub async fn decode_to_owned(&mut self) -> crate::error::Result<Vec<RecordEnum>> {
+    /// Sets how records with an unknown rtype are handled, `UnknownRecordPolicy::Error` by default.
+    pub fn with_unknown_policy(mut self, policy: UnknownRecordPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn set_unknown_policy(&mut self, policy: UnknownRecordPolicy) {
+        self.policy = policy;
+    }
+
+    /// Sets the stream offset of the next record, e.g. the length of the metadata already read.
+    pub(crate) fn set_position(&mut self, position: u64) {
+        self.position = position;
+    }
+
+    /// Seeks the reader to `offset`, dropping any partially read record.
+    pub(crate) async fn seek(&mut self, offset: u64) -> Result<()>
+    where
+        R: AsyncSeek,
+    {
+        self.reader.seek(std::io::SeekFrom::Start(offset)).await?;
+        self.filled = 0;
+        self.position = offset;
+        Ok(())
+    }
+
+    pub async fn decode_to_owned(&mut self) -> Result<Vec<RecordEnum>> {
         let mut records = Vec::new();
         while let Some(record_ref) = self.decode_ref().await? {
-            let record = RecordEnum::from_ref(record_ref)?;
+            let record = RecordEnum::read_le(record_ref.as_ref())?;
             records.push(record);
         }
 
@@ -258,46 +683,112 @@ where
     }
 
     pub fn decode_iterator(&mut self) -> AsyncDecoderIterator<R> {
-        AsyncDecoderIterator::new(&mut self.reader)
+        AsyncDecoderIterator::new(self)
     }
 
+    pub fn into_stream(self) -> AsyncRecordStream<R> {
+        AsyncRecordStream::new(self)
+    }
+
+    /// Returns a zero-copy view of the next record. The view reinterprets the bytes in memory,
+    /// which matches the explicit `layout` on little-endian hosts only; `decode_to_owned`
+    /// reads field by field and is host independent.
     pub async fn decode_ref(&mut self) -> tokio::io::Result<Option<RecordRef>> {
-        if let Err(err) = self.reader.read_exact(&mut self.read_buffer[..1]).await {
-            if err.kind() == tokio::io::ErrorKind::UnexpectedEof {
+        loop {
+            if !poll_fn(|cx| self.poll_read_record(cx)).await? {
                 return Ok(None);
-            } else {
-                return Err(tokio::io::Error::new(
-                    err.kind(),
-                    format!("decoding record reference: {}", err),
-                ));
+            }
+            if self.policy.accept(self.read_buffer.as_slice())? {
+                break;
             }
         }
-        let length = self.read_buffer[0] as usize * RecordHeader::LENGTH_MULTIPLIER;
-        if length < mem::size_of::<RecordHeader>() {
-            return Err(tokio::io::Error::new(
-                tokio::io::ErrorKind::InvalidData,
-                format!("invalid record with length {} shorter than header", length),
-            ));
-        }
-        if length > self.read_buffer.len() {
-            self.read_buffer.resize(length, 0);
-        }
-        if let Err(err) = self
-            .reader
-            .read_exact(&mut self.read_buffer[1..length])
-            .await
-        {
-            if err.kind() == tokio::io::ErrorKind::UnexpectedEof {
-                return Ok(None);
+        RecordRef::try_new_opaque(self.read_buffer.as_slice())
+            .map(Some)
+            .map_err(|e| tokio::io::Error::new(tokio::io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    /// Polls for the next record accepted by the unknown record policy, converted to an owned
+    /// `RecordEnum`. Used by the record streams.
+    pub(crate) fn poll_next_record(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<tokio::io::Result<RecordEnum>>> {
+        loop {
+            match ready!(self.poll_read_record(cx)) {
+                Ok(true) => {}
+                Ok(false) => return Poll::Ready(None),
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+            match self.policy.accept(self.read_buffer.as_slice()) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+            let record = RecordEnum::read_le(self.read_buffer.as_slice()).map_err(|_| {
+                tokio::io::Error::new(
+                    tokio::io::ErrorKind::InvalidData,
+                    "Failed to convert record reference to RecordEnum",
+                )
+            });
+            return Poll::Ready(Some(record));
+        }
+    }
+
+    /// Reads the next record into the read buffer, returns `false` at the end of the stream.
+    /// Progress is kept in `filled` between polls.
+    fn poll_read_record(&mut self, cx: &mut Context<'_>) -> Poll<tokio::io::Result<bool>> {
+        loop {
+            let available = match ready!(Pin::new(&mut self.reader).poll_fill_buf(cx)) {
+                Ok(available) => available,
+                Err(err) => {
+                    return Poll::Ready(Err(tokio::io::Error::new(
+                        err.kind(),
+                        format!("decoding record reference: {}", err),
+                    )))
+                }
+            };
+            if available.is_empty() {
+                if self.filled == 0 {
+                    return Poll::Ready(Ok(false));
+                }
+                let got = mem::take(&mut self.filled);
+                return Poll::Ready(Err(Error::Truncated {
+                    offset: self.position,
+                    expected: self.read_buffer.as_slice().len(),
+                    got,
+                }
+                .into_io()));
+            }
+
+            let consumed = if self.filled == 0 {
+                let length = available[0] as usize * RecordHeader::LENGTH_MULTIPLIER;
+                if length < mem::size_of::<RecordHeader>() {
+                    Pin::new(&mut self.reader).consume(1);
+                    return Poll::Ready(Err(Error::Framing {
+                        offset: self.position,
+                        length,
+                    }
+                    .into_io()));
+                }
+                self.read_buffer.resize(length);
+                self.read_buffer.as_mut_slice()[0] = available[0];
+                1
             } else {
-                return Err(tokio::io::Error::new(
-                    err.kind(),
-                    format!("decoding record reference: {}", err),
-                ));
+                let buffer = &mut self.read_buffer.as_mut_slice()[self.filled..];
+                let n = buffer.len().min(available.len());
+                buffer[..n].copy_from_slice(&available[..n]);
+                n
+            };
+            Pin::new(&mut self.reader).consume(consumed);
+            self.filled += consumed;
+
+            let length = self.read_buffer.as_slice().len();
+            if self.filled == length {
+                self.filled = 0;
+                self.position += length as u64;
+                return Poll::Ready(Ok(true));
             }
         }
-        // Safety: `read_buffer` is resized to contain at least `length` bytes.
-        Ok(Some(unsafe { RecordRef::new(&self.read_buffer) }))
     }
 }
 
@@ -417,6 +908,199 @@ mod tests {
         Ok(())
     }
 
+    fn ohlcv_stream(count: u64) -> (Metadata, Vec<OhlcvMsg>) {
+        let metadata = Metadata::new(Schema::Ohlcv1S, 0, count, SymbolMap::new());
+        let msgs = (0..count)
+            .map(|i| OhlcvMsg {
+                hd: RecordHeader::new::<OhlcvMsg>(1, i * 10),
+                open: 100,
+                high: 200,
+                low: 50,
+                close: 150,
+                volume: i,
+            })
+            .collect();
+        (metadata, msgs)
+    }
+
+    #[test]
+    fn test_decode_range_builds_index() -> anyhow::Result<()> {
+        let (metadata, msgs) = ohlcv_stream(100);
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+        let mut buffer = Vec::new();
+        CombinedEncoder::new(&mut buffer).encode(&metadata, &records)?;
+
+        // Test
+        let mut decoder = Decoder::new(Cursor::new(buffer))?;
+        let range = decoder.decode_range(255, 300)?;
+        decoder.seek_to_time(990)?;
+        let tail = decoder.decode()?;
+        decoder.seek_to_time(5_000)?;
+        let past_end = decoder.decode()?;
+
+        // Validate
+        let ts: Vec<u64> = range.iter().map(|r| r.msg().header().ts_event).collect();
+        assert_eq!(ts, vec![260, 270, 280, 290]);
+        assert_eq!(tail, vec![RecordEnum::Ohlcv(msgs[99].clone())]);
+        assert!(past_end.is_empty());
+        assert_eq!(decoder.index()?.records, 100);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_decode_range_with_sidecar() -> anyhow::Result<()> {
+        let (metadata, msgs) = ohlcv_stream(50);
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+        let mut buffer = Vec::new();
+        let mut encoder = CombinedEncoder::new(&mut buffer).with_index(8);
+        encoder.encode(&metadata, &records)?;
+        let file = PathBuf::from("tests/indexed.bin");
+        encoder.write_to_file(&file, false)?;
+
+        // Test
+        let index = Index::load_or_build(&file)?;
+        let mut decoder =
+            Decoder::<std::io::BufReader<std::fs::File>>::from_file(&file)?.with_index(index);
+        let range = decoder.decode_range(100, 130)?;
+
+        // Validate
+        assert_eq!(decoder.index()?.every, 8);
+        assert_eq!(
+            range,
+            msgs[10..13]
+                .iter()
+                .map(|m| RecordEnum::Ohlcv(m.clone()))
+                .collect::<Vec<_>>()
+        );
+
+        // Cleanup
+        std::fs::remove_file(Index::sidecar_path(&file))?;
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_truncated_record() -> anyhow::Result<()> {
+        let (metadata, msgs) = ohlcv_stream(3);
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+        let mut buffer = Vec::new();
+        CombinedEncoder::new(&mut buffer).encode(&metadata, &records)?;
+        let record_size = mem::size_of::<OhlcvMsg>();
+        buffer.truncate(buffer.len() - 10);
+
+        // Test
+        let result = Decoder::new(Cursor::new(buffer.clone()))?.decode();
+        let mut decoder = Decoder::new(Cursor::new(buffer.clone()))?;
+        let iter_result: Vec<_> = decoder.decode_iterator().collect();
+
+        // Validate
+        let offset = (buffer.len() + 10 - record_size) as u64;
+        assert!(matches!(
+            result,
+            Err(Error::Truncated { offset: o, expected, got })
+                if o == offset && expected == record_size && got == record_size - 10
+        ));
+        assert_eq!(iter_result.len(), 3);
+        let err = iter_result[2].as_ref().unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert!(err.to_string().contains(&format!("byte {}", offset)));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_repair_file() -> anyhow::Result<()> {
+        let (metadata, msgs) = ohlcv_stream(3);
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+        let mut buffer = Vec::new();
+        CombinedEncoder::new(&mut buffer).encode(&metadata, &records)?;
+        let file = PathBuf::from("tests/repair.bin");
+        std::fs::write(&file, &buffer[..buffer.len() - 10])?;
+
+        // Test
+        let removed = repair_file(&file)?;
+        let decoded = Decoder::<FileReader>::from_file(&file)?.decode()?;
+        let removed_again = repair_file(&file)?;
+
+        // Validate
+        assert_eq!(removed, (mem::size_of::<OhlcvMsg>() - 10) as u64);
+        assert_eq!(
+            decoded,
+            msgs[..2]
+                .iter()
+                .map(|m| RecordEnum::Ohlcv(m.clone()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(removed_again, 0);
+
+        // Cleanup
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_version_1_unpadded_metadata() -> anyhow::Result<()> {
+        let (metadata, msgs) = ohlcv_stream(3);
+        let serialized = metadata.serialize();
+        assert!(!serialized.len().is_multiple_of(8));
+        // Version 1 metadata block written without padding, as by earlier encoders.
+        let mut buffer = MBN_MAGIC.to_vec();
+        buffer.push(1);
+        buffer.extend_from_slice(&(serialized.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(&serialized);
+        let data_offset = buffer.len() as u64;
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+        RecordEncoder::new(&mut buffer).encode_records(&records)?;
+        let file_len = buffer.len() as u64;
+        buffer.truncate(buffer.len() - 10);
+
+        // Test
+        let mut metadata_decoder = MetadataDecoder::new(Cursor::new(&buffer));
+        let decoded_metadata = metadata_decoder.decode()?;
+        let (_, scanned_offset, complete_len) =
+            scan_records(&mut Cursor::new(&buffer), buffer.len() as u64)?;
+        let result = Decoder::new(Cursor::new(&buffer))?.decode();
+
+        // Validate
+        assert_eq!(decoded_metadata, Some(metadata));
+        assert_eq!(metadata_decoder.data_offset(), data_offset);
+        assert_eq!(scanned_offset, data_offset);
+        let record_size = mem::size_of::<OhlcvMsg>() as u64;
+        assert_eq!(complete_len, file_len - record_size);
+        assert!(matches!(
+            result,
+            Err(Error::Truncated { offset, .. }) if offset == complete_len
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_without_metadata() -> anyhow::Result<()> {
+        let (_, msgs) = ohlcv_stream(3);
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+        let mut buffer = Vec::new();
+        RecordEncoder::new(&mut buffer).encode_records(&records)?;
+        let expected: Vec<RecordEnum> = msgs.iter().map(|m| RecordEnum::Ohlcv(m.clone())).collect();
+
+        // Test
+        let mut decoder = Decoder::new(Cursor::new(&buffer))?;
+        let decoded = decoder.decode()?;
+        let mut iter_decoder = Decoder::new(Cursor::new(&buffer))?;
+        let iterated = iter_decoder
+            .decode_iterator()
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let mut empty = Decoder::new(Cursor::new(Vec::new()))?;
+
+        // Validate
+        assert!(decoder.metadata.is_none());
+        assert_eq!(decoded, expected);
+        assert_eq!(iterated, expected);
+        assert!(empty.metadata.is_none());
+        assert!(empty.decode()?.is_empty());
+        Ok(())
+    }
+
     // MetadataDecoder
     #[test]
     #[serial]
@@ -445,6 +1129,73 @@ mod tests {
         assert_eq!(decoded.mappings, metadata.mappings);
     }
 
+    #[test]
+    #[serial]
+    fn test_decode_metadata_rejects_non_mbn() {
+        let cursor = Cursor::new(vec![0x0e, 0x02, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]);
+
+        // Test
+        let mut decoder = MetadataDecoder::new(cursor);
+        let result = decoder.decode();
+
+        // Validate
+        assert!(matches!(result, Err(Error::Decode(_))));
+    }
+
+    #[test]
+    #[serial]
+    fn test_decode_metadata_rejects_unknown_version() {
+        let mut bytes = MBN_MAGIC.to_vec();
+        bytes.push(MBN_VERSION + 1);
+        bytes.extend_from_slice(&[0u8; 32]);
+
+        // Test
+        let mut decoder = MetadataDecoder::new(Cursor::new(bytes));
+        let result = decoder.decode();
+
+        // Validate
+        match result {
+            Err(Error::Decode(msg)) => assert!(msg.contains("Unsupported MBN version")),
+            other => panic!("Expected decode error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_decode_metadata_invalid_block() {
+        let mut bytes = MBN_MAGIC.to_vec();
+        bytes.extend_from_slice(&[MBN_VERSION, FileLayout::Records.into()]);
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(&[1, 2, 3]);
+
+        // Test
+        let mut decoder = MetadataDecoder::new(Cursor::new(bytes));
+        let result = decoder.decode();
+
+        // Validate
+        assert!(matches!(result, Err(Error::Decode(_))));
+    }
+
+    #[test]
+    #[serial]
+    fn test_decode_metadata_oversized_length() {
+        // Claims a 4 GiB block but holds 3 bytes.
+        let mut bytes = MBN_MAGIC.to_vec();
+        bytes.extend_from_slice(&[MBN_VERSION, FileLayout::Records.into()]);
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&[1, 2, 3]);
+
+        // Test
+        let mut decoder = MetadataDecoder::new(Cursor::new(bytes));
+        let result = decoder.decode();
+
+        // Validate
+        assert!(
+            matches!(result, Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof)
+        );
+        assert!(decoder.read_buffer.capacity() < 1024);
+    }
+
     // RecordDecoder
     #[test]
     #[serial]
@@ -520,6 +1271,135 @@ mod tests {
         }
     }
 
+    #[test]
+    #[serial]
+    fn test_decode_record_ref_corrupt_rtype() {
+        let ohlcv_msg = OhlcvMsg {
+            hd: RecordHeader::new::<OhlcvMsg>(1, 1622471124),
+            open: 100,
+            high: 200,
+            low: 50,
+            close: 150,
+            volume: 1000,
+        };
+        let mut data = unsafe { as_u8_slice(&ohlcv_msg) }.to_vec();
+        data[1] = 0xEE;
+
+        // Test
+        let mut decoder = RecordDecoder::new(Cursor::new(data));
+        let result = decoder.decode_ref();
+
+        // Validate
+        let err = result.expect_err("Expected corrupt record to error");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    /// Known record, record with an unknown rtype, known record.
+    fn stream_with_unknown_record() -> (Vec<u8>, OhlcvMsg, Vec<u8>, OhlcvMsg) {
+        let first = OhlcvMsg {
+            hd: RecordHeader::new::<OhlcvMsg>(1, 1622471124),
+            open: 100,
+            high: 200,
+            low: 50,
+            close: 150,
+            volume: 1000,
+        };
+        let last = OhlcvMsg {
+            hd: RecordHeader::new::<OhlcvMsg>(2, 1622471126),
+            open: 110,
+            high: 210,
+            low: 55,
+            close: 155,
+            volume: 1100,
+        };
+        let mut unknown = unsafe { as_u8_slice(&first) }.to_vec();
+        unknown[1] = 0xEE;
+
+        let mut data = unsafe { as_u8_slice(&first) }.to_vec();
+        data.extend_from_slice(&unknown);
+        data.extend_from_slice(unsafe { as_u8_slice(&last) });
+        (data, first, unknown, last)
+    }
+
+    #[test]
+    #[serial]
+    fn test_decode_unknown_policy_skip() -> anyhow::Result<()> {
+        let (data, first, _, last) = stream_with_unknown_record();
+
+        // Test
+        let mut decoder =
+            RecordDecoder::new(Cursor::new(data)).with_unknown_policy(UnknownRecordPolicy::Skip);
+        let records = decoder.decode_to_owned()?;
+
+        // Validate
+        assert_eq!(
+            records,
+            vec![RecordEnum::Ohlcv(first), RecordEnum::Ohlcv(last)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_decode_unknown_policy_surface() -> anyhow::Result<()> {
+        let (data, first, unknown, last) = stream_with_unknown_record();
+
+        // Test
+        let mut decoder =
+            RecordDecoder::new(Cursor::new(data)).with_unknown_policy(UnknownRecordPolicy::Surface);
+        let records = decoder.decode_to_owned()?;
+
+        // Validate
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0], RecordEnum::Ohlcv(first));
+        assert_eq!(records[2], RecordEnum::Ohlcv(last));
+        match &records[1] {
+            RecordEnum::Unknown(msg) => {
+                assert_eq!(msg.hd.rtype, 0xEE);
+                assert!(msg.hd.rtype().is_err());
+                assert_eq!(msg.as_ref(), unknown.as_slice());
+            }
+            other => panic!("Expected unknown record, got {:?}", other),
+        }
+
+        // Unknown records are re-encoded unchanged
+        let mut buffer = Vec::new();
+        RecordEncoder::new(&mut buffer).encode_record(&records[1].to_record_ref())?;
+        assert_eq!(buffer, unknown);
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_iter_decode_unknown_policy() -> anyhow::Result<()> {
+        let (data, _, _, _) = stream_with_unknown_record();
+
+        // Test
+        let mut cursor = Cursor::new(data.clone());
+        let mut decoder =
+            RecordDecoder::new(&mut cursor).with_unknown_policy(UnknownRecordPolicy::Surface);
+        let surfaced = decoder
+            .decode_iterator()
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        let mut cursor = Cursor::new(data);
+        let mut decoder = RecordDecoder::new(&mut cursor);
+        let errored = decoder
+            .decode_iterator()
+            .collect::<std::io::Result<Vec<_>>>();
+
+        // Validate
+        assert_eq!(surfaced.len(), 3);
+        assert!(matches!(surfaced[1], RecordEnum::Unknown(_)));
+        assert_eq!(
+            errored
+                .expect_err("Expected unknown record to error")
+                .kind(),
+            std::io::ErrorKind::InvalidData
+        );
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn test_encode_decode_records() {
@@ -753,6 +1633,56 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_decode_large_metadata_async() -> anyhow::Result<()> {
+        let mut symbol_map = SymbolMap::new();
+        for id in 0..1000 {
+            symbol_map.add_instrument(&format!("TICKER{}", id), id);
+        }
+
+        let metadata = Metadata::new(Schema::Ohlcv1S, 1234567898765, 123456765432, symbol_map);
+
+        let ohlcv_msg = OhlcvMsg {
+            hd: RecordHeader::new::<OhlcvMsg>(999, 1622471124),
+            open: 100,
+            high: 200,
+            low: 50,
+            close: 150,
+            volume: 1000,
+        };
+
+        let mut buffer = Vec::new();
+        let mut encoder = CombinedEncoder::new(&mut buffer);
+        encoder
+            .encode(&metadata, &[(&ohlcv_msg).into()])
+            .expect("Error on encoding");
+
+        // Test
+        let reader = tokio::io::BufReader::with_capacity(64, Cursor::new(buffer));
+        let mut decoder = AsyncDecoder::new(reader).await?;
+        let decoded = decoder.decode().await?;
+
+        // Validate
+        assert_eq!(decoder.metadata(), Some(metadata));
+        assert_eq!(decoded, [RecordEnum::Ohlcv(ohlcv_msg)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_decode_metadata_rejects_unknown_version_async() {
+        let mut bytes = MBN_MAGIC.to_vec();
+        bytes.push(MBN_VERSION + 1);
+        bytes.extend_from_slice(&[0u8; 32]);
+
+        // Test
+        let result = AsyncDecoder::new(Cursor::new(bytes)).await;
+
+        // Validate
+        assert!(matches!(result, Err(Error::Decode(_))));
+    }
+
     // RecordDecoder
     #[tokio::test]
     #[serial]
@@ -826,6 +1756,24 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_decode_unknown_policy_skip_async() -> anyhow::Result<()> {
+        let (data, first, _, last) = stream_with_unknown_record();
+
+        // Test
+        let mut decoder = AsyncRecordDecoder::new(Cursor::new(data))
+            .with_unknown_policy(UnknownRecordPolicy::Skip);
+        let records = decoder.decode_to_owned().await?;
+
+        // Validate
+        assert_eq!(
+            records,
+            vec![RecordEnum::Ohlcv(first), RecordEnum::Ohlcv(last)]
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_encode_decode_records_async() -> anyhow::Result<()> {
@@ -926,4 +1874,183 @@ mod tests {
         }
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_decode_truncated_record_async() -> anyhow::Result<()> {
+        let (metadata, msgs) = ohlcv_stream(3);
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+        let mut buffer = Vec::new();
+        CombinedEncoder::new(&mut buffer).encode(&metadata, &records)?;
+        buffer.truncate(buffer.len() - 1);
+
+        // Test
+        let mut decoder = AsyncDecoder::new(Cursor::new(buffer.clone())).await?;
+        let result = decoder.decode().await;
+
+        // Validate
+        let record_size = mem::size_of::<OhlcvMsg>();
+        assert!(matches!(
+            result,
+            Err(Error::Truncated { offset, expected, got })
+                if offset == (buffer.len() + 1 - record_size) as u64
+                    && expected == record_size
+                    && got == record_size - 1
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decode_without_metadata_async() -> anyhow::Result<()> {
+        let (_, msgs) = ohlcv_stream(3);
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+        let mut buffer = Vec::new();
+        RecordEncoder::new(&mut buffer).encode_records(&records)?;
+
+        // Test
+        let mut decoder = AsyncDecoder::new(Cursor::new(buffer)).await?;
+        let decoded = decoder.decode().await?;
+
+        // Validate
+        assert!(decoder.metadata.is_none());
+        assert_eq!(
+            decoded,
+            msgs.iter()
+                .map(|m| RecordEnum::Ohlcv(m.clone()))
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    /// Reader handing out `chunk` bytes at a time and returning `Pending` on every other poll.
+    struct Trickle {
+        bytes: Vec<u8>,
+        offset: usize,
+        chunk: usize,
+        pending: bool,
+    }
+
+    impl tokio::io::AsyncRead for Trickle {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            let available = ready!(self.as_mut().poll_fill_buf(cx))?;
+            let n = available.len().min(buf.remaining());
+            buf.put_slice(&available[..n]);
+            self.consume(n);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncBufRead for Trickle {
+        fn poll_fill_buf(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<&[u8]>> {
+            let this = self.get_mut();
+            this.pending = !this.pending;
+            if this.pending {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let end = (this.offset + this.chunk).min(this.bytes.len());
+            Poll::Ready(Ok(&this.bytes[this.offset..end]))
+        }
+
+        fn consume(self: Pin<&mut Self>, amt: usize) {
+            self.get_mut().offset += amt;
+        }
+    }
+
+    fn trickle_stream(count: u64) -> (Vec<OhlcvMsg>, Trickle) {
+        let (metadata, msgs) = ohlcv_stream(count);
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+        let mut buffer = Vec::new();
+        CombinedEncoder::new(&mut buffer)
+            .encode(&metadata, &records)
+            .unwrap();
+        let reader = Trickle {
+            bytes: buffer,
+            offset: 0,
+            chunk: 5,
+            pending: false,
+        };
+        (msgs, reader)
+    }
+
+    #[tokio::test]
+    async fn test_decode_ref_cancellation_safe_async() -> anyhow::Result<()> {
+        let (msgs, reader) = trickle_stream(5);
+        let mut decoder = AsyncDecoder::new(reader).await?;
+
+        // Test
+        let mut decoded = Vec::new();
+        loop {
+            // Each future is dropped after a single poll, mid record.
+            let poll = futures::poll!(Box::pin(decoder.decode_ref()));
+            match poll {
+                Poll::Ready(Ok(Some(record))) => {
+                    decoded.push(RecordEnum::read_le(record.as_ref())?)
+                }
+                Poll::Ready(Ok(None)) => break,
+                Poll::Ready(Err(e)) => return Err(e.into()),
+                Poll::Pending => {}
+            }
+        }
+
+        // Validate
+        assert_eq!(
+            decoded,
+            msgs.into_iter().map(RecordEnum::Ohlcv).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decode_iterator_after_cancelled_decode_ref_async() -> anyhow::Result<()> {
+        let (msgs, reader) = trickle_stream(3);
+        let expected: Vec<RecordEnum> = msgs.into_iter().map(RecordEnum::Ohlcv).collect();
+        let mut decoder = AsyncDecoder::new(reader).await?;
+
+        // Test
+        // Two polls read the first chunk of the first record before the future is dropped.
+        let mut future = Box::pin(decoder.decode_ref());
+        for _ in 0..2 {
+            assert!(futures::poll!(future.as_mut()).is_pending());
+        }
+        drop(future);
+        let decoded: Vec<RecordEnum> = decoder
+            .decode_iterator()
+            .map(|r| r.unwrap())
+            .collect()
+            .await;
+
+        // Validate
+        assert_eq!(decoded, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decode_stream_async() -> anyhow::Result<()> {
+        let (msgs, reader) = trickle_stream(20);
+        let expected: Vec<RecordEnum> = msgs.into_iter().map(RecordEnum::Ohlcv).collect();
+        let mut decoder = AsyncDecoder::new(reader).await?;
+
+        // Test
+        let borrowed: Vec<RecordEnum> = decoder
+            .decode_iterator()
+            .take(3)
+            .map(|r| r.unwrap())
+            .collect()
+            .await;
+        let stream = decoder.into_stream();
+        let owned = tokio::spawn(async move { stream.collect::<Vec<_>>().await }).await?;
+
+        // Validate
+        assert_eq!(borrowed, expected[..3]);
+        let owned: Vec<RecordEnum> = owned.into_iter().collect::<std::io::Result<_>>()?;
+        assert_eq!(owned, expected[3..]);
+        Ok(())
+    }
 }
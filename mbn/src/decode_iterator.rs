@@ -1,21 +1,26 @@
-use crate::decode::{AsyncRecordDecoder, RecordDecoder};
+use crate::decode::{AsyncRecordDecoder, RecordDecoder, UnknownRecordPolicy};
 use crate::record_enum::RecordEnum;
 use futures::stream::Stream;
-use std::future::Future;
 use std::io::Read;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::io::AsyncBufRead;
 
+/// Iterator over the records of a borrowed decoder, which keeps the stream position and any
+/// bytes read ahead of the records.
 pub struct DecoderIterator<'a, R> {
-    decoder: RecordDecoder<&'a mut R>,
+    decoder: &'a mut RecordDecoder<R>,
 }
 
 impl<'a, R: Read> DecoderIterator<'a, R> {
-    pub fn new(reader: &'a mut R) -> Self {
-        Self {
-            decoder: RecordDecoder::new(reader),
-        }
+    pub fn new(decoder: &'a mut RecordDecoder<R>) -> Self {
+        Self { decoder }
+    }
+
+    /// Sets how records with an unknown rtype are handled on the borrowed decoder.
+    pub fn with_unknown_policy(self, policy: UnknownRecordPolicy) -> Self {
+        self.decoder.set_unknown_policy(policy);
+        self
     }
 }
 
@@ -24,7 +29,7 @@ impl<'a, R: Read> Iterator for DecoderIterator<'a, R> {
 
     fn next(&mut self) -> Option<Self::Item> {
         match self.decoder.decode_ref() {
-            Ok(Some(record_ref)) => match RecordEnum::from_ref(record_ref) {
+            Ok(Some(record_ref)) => match RecordEnum::read_le(record_ref.as_ref()) {
                 Ok(record) => Some(Ok(record)),
                 Err(_) => Some(Err(std::io::Error::new(
                     std::io::ErrorKind::InvalidData,
@@ -37,40 +42,48 @@ impl<'a, R: Read> Iterator for DecoderIterator<'a, R> {
     }
 }
 
+/// Stream of records borrowing the decoder, which keeps partially read records and the stream
+/// position, so decoding resumes where the stream stopped.
 pub struct AsyncDecoderIterator<'a, R> {
-    decoder: AsyncRecordDecoder<&'a mut R>,
+    decoder: &'a mut AsyncRecordDecoder<R>,
 }
 
 impl<'a, R: AsyncBufRead + Unpin> AsyncDecoderIterator<'a, R> {
-    pub fn new(reader: &'a mut R) -> Self {
-        Self {
-            decoder: AsyncRecordDecoder::new(reader),
-        }
+    pub fn new(decoder: &'a mut AsyncRecordDecoder<R>) -> Self {
+        Self { decoder }
+    }
+
+    /// Sets how records with an unknown rtype are handled on the borrowed decoder.
+    pub fn with_unknown_policy(self, policy: UnknownRecordPolicy) -> Self {
+        self.decoder.set_unknown_policy(policy);
+        self
     }
 }
 
 impl<'a, R: AsyncBufRead + Unpin> Stream for AsyncDecoderIterator<'a, R> {
     type Item = std::io::Result<RecordEnum>;
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        // Poll for the next record asynchronously
-        let fut = self.decoder.decode_ref();
-        let mut fut = Box::pin(fut); // Pin the future
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().decoder.poll_next_record(cx)
+    }
+}
+
+/// Stream of records owning its reader, returned by `AsyncDecoder::into_stream`. It is `Send`
+/// and `'static` when the reader is, so it can be moved into `tokio::spawn`.
+pub struct AsyncRecordStream<R> {
+    decoder: AsyncRecordDecoder<R>,
+}
 
-        match Future::poll(fut.as_mut(), cx) {
-            Poll::Ready(Ok(Some(record_ref))) => {
-                // If the record_ref is decoded successfully, convert it to RecordEnum
-                match RecordEnum::from_ref(record_ref) {
-                    Ok(record) => Poll::Ready(Some(Ok(record))),
-                    Err(_) => Poll::Ready(Some(Err(std::io::Error::new(
-                        std::io::ErrorKind::InvalidData,
-                        "Failed to convert record reference to RecordEnum",
-                    )))),
-                }
-            }
-            Poll::Ready(Ok(None)) => Poll::Ready(None),
-            Poll::Ready(Err(e)) => Poll::Ready(Some(Err(e))),
-            Poll::Pending => Poll::Pending,
-        }
+impl<R: AsyncBufRead + Unpin> AsyncRecordStream<R> {
+    pub(crate) fn new(decoder: AsyncRecordDecoder<R>) -> Self {
+        Self { decoder }
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> Stream for AsyncRecordStream<R> {
+    type Item = std::io::Result<RecordEnum>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().decoder.poll_next_record(cx)
     }
 }
@@ -1,33 +1,75 @@
+use crate::decode::scan_records;
+use crate::enums::{FileLayout, RType};
+use crate::error::{Error, Result};
+use crate::index::{fingerprint, Index};
+use crate::layout::write_record_le;
 use crate::metadata::Metadata;
 use crate::record_ref::*;
-use crate::METADATA_LENGTH;
-use std::fs::OpenOptions;
-use std::io::{self, Write};
-use std::path::Path;
+use crate::symbols::SymbolMap;
+use crate::{MBN_MAGIC, MBN_VERSION, METADATA_LENGTH_PREFIX};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 pub struct CombinedEncoder<W> {
     writer: W,
+    index: Option<Index>,
+    every: u32,
 }
 
 impl<W: Write> CombinedEncoder<W> {
     pub fn new(writer: W) -> Self {
-        CombinedEncoder { writer }
+        CombinedEncoder {
+            writer,
+            index: None,
+            every: 0,
+        }
+    }
+
+    /// Builds an `Index` with a checkpoint every `every` records while encoding. Offsets assume
+    /// the writer starts at the beginning of the file, so the index is only valid for a file
+    /// written from scratch.
+    pub fn with_index(mut self, every: u32) -> Self {
+        self.every = every;
+        self.index = Some(Index::new(every, 0));
+        self
+    }
+
+    /// Index of the records encoded so far, if enabled with `with_index`.
+    pub fn index(&self) -> Option<&Index> {
+        self.index.as_ref()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
     }
 
     pub fn encode_metadata(&mut self, metadata: &Metadata) -> io::Result<()> {
         let mut metadata_encoder = MetadataEncoder::new(&mut self.writer);
-        metadata_encoder.encode_metadata(metadata)
+        metadata_encoder.encode_metadata(metadata)?;
+        if self.index.is_some() {
+            let data_offset = encoded_metadata_length(metadata);
+            self.index = Some(Index::new(self.every, data_offset));
+        }
+        Ok(())
     }
 
     pub fn encode_record(&mut self, record: &RecordRef) -> io::Result<()> {
         let mut record_encoder = RecordEncoder::new(&mut self.writer);
-        record_encoder.encode_record(record)
+        record_encoder.encode_record(record)?;
+        if let Some(index) = self.index.as_mut() {
+            index.push(record.header());
+        }
+        Ok(())
     }
 
     pub fn encode_records(&mut self, records: &[RecordRef]) -> io::Result<()> {
-        let mut record_encoder = RecordEncoder::new(&mut self.writer);
-        record_encoder.encode_records(records)
+        for record in records {
+            self.encode_record(record)?;
+        }
+        self.writer.flush()?;
+        Ok(())
     }
 
     pub fn encode(&mut self, metadata: &Metadata, records: &[RecordRef]) -> io::Result<()> {
@@ -36,6 +78,8 @@ impl<W: Write> CombinedEncoder<W> {
         Ok(())
     }
 
+    /// Writes the encoded buffer to `file_path`. With `append` the buffer is added as is,
+    /// metadata included; use `AppendWriter` to add records to an existing file.
     pub fn write_to_file(&self, file_path: &Path, append: bool) -> io::Result<()>
     where
         W: AsRef<[u8]>,
@@ -53,27 +97,106 @@ impl<W: Write> CombinedEncoder<W> {
 
         file.write_all(self.writer.as_ref())?;
         file.flush()?;
+
+        // Offsets of an appended buffer don't match the file, `Index::load_or_build` rebuilds
+        // the stale sidecar instead.
+        if let (Some(index), false) = (&self.index, append) {
+            let mut index = index.clone();
+            index.fingerprint = fingerprint(self.writer.as_ref());
+            index
+                .write_to_file(Index::sidecar_path(file_path))
+                .map_err(|e| io::Error::other(e.to_string()))?;
+        }
         Ok(())
     }
 }
 
+impl<W: Write> CombinedEncoder<zstd::stream::write::Encoder<'static, W>> {
+    /// Encoder compressing the stream into zstd frames at `level`, e.g. `DEFAULT_ZSTD_LEVEL`.
+    /// `finish` has to be called to write the end of the last frame.
+    pub fn new_zstd(writer: W, level: i32) -> io::Result<Self> {
+        Ok(CombinedEncoder::new(zstd::stream::write::Encoder::new(
+            writer, level,
+        )?))
+    }
+
+    /// Completes the compressed stream and returns the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.writer.finish()
+    }
+}
+
+/// Size of the header written by `MetadataEncoder`: `MBN_MAGIC`, the version and layout bytes
+/// and the metadata length prefix.
+const ENCODED_PREFIX_LENGTH: usize = MBN_MAGIC.len() + 2 + METADATA_LENGTH_PREFIX;
+
+/// Metadata blocks are zero padded so the records after them start at a multiple of this, e.g.
+/// for `MmapDecoder`.
+const METADATA_ALIGNMENT: usize = 8;
+
+/// Length of the metadata block written for `serialized_len` bytes of metadata.
+fn padded_metadata_len(serialized_len: usize) -> usize {
+    (ENCODED_PREFIX_LENGTH + serialized_len).next_multiple_of(METADATA_ALIGNMENT)
+        - ENCODED_PREFIX_LENGTH
+}
+
+/// Bytes `MetadataEncoder` writes for `metadata`, i.e. the offset of the first record.
+pub(crate) fn encoded_metadata_length(metadata: &Metadata) -> u64 {
+    (ENCODED_PREFIX_LENGTH + padded_metadata_len(metadata.serialize().len())) as u64
+}
+
 pub struct MetadataEncoder<W> {
     writer: W,
-    buffer: Vec<u8>,
+    layout: FileLayout,
+    min_length: usize,
 }
 
 impl<W: Write> MetadataEncoder<W> {
     pub fn new(writer: W) -> Self {
         MetadataEncoder {
             writer,
-            buffer: vec![0; METADATA_LENGTH], // Initialize buffer with fixed size
+            layout: FileLayout::Records,
+            min_length: 0,
         }
     }
 
+    /// Sets the layout written in the header, `FileLayout::Records` by default.
+    pub fn with_layout(mut self, layout: FileLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Zero pads the metadata block so at least `min_length` bytes are written in total, e.g. to
+    /// rewrite a header in place with metadata that serializes shorter.
+    pub fn with_min_length(mut self, min_length: usize) -> Self {
+        self.min_length = min_length;
+        self
+    }
+
+    /// Writes the file header (`MBN_MAGIC`, `MBN_VERSION` and the `FileLayout` byte), then the
+    /// metadata as a u32 little-endian length prefix followed by the serialized block, zero
+    /// padded so the records after it start 8 byte aligned. The prefix counts the padding,
+    /// which `Metadata::deserialize` ignores.
     pub fn encode_metadata(&mut self, metadata: &Metadata) -> io::Result<()> {
-        let serialized = metadata.serialize();
-        self.buffer[..serialized.len()].copy_from_slice(&serialized);
-        self.writer.write_all(&self.buffer)?;
+        let mut serialized = metadata.serialize();
+        serialized.resize(
+            padded_metadata_len(
+                serialized
+                    .len()
+                    .max(self.min_length.saturating_sub(ENCODED_PREFIX_LENGTH)),
+            ),
+            0,
+        );
+        let length = u32::try_from(serialized.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Metadata block exceeds u32::MAX bytes",
+            )
+        })?;
+        self.writer.write_all(MBN_MAGIC)?;
+        self.writer.write_all(&[MBN_VERSION, self.layout.into()])?;
+        self.writer.write_all(&length.to_le_bytes())?;
+        self.writer.write_all(&serialized)?;
         self.writer.flush()?;
         Ok(())
     }
@@ -81,16 +204,27 @@ impl<W: Write> MetadataEncoder<W> {
 
 pub struct RecordEncoder<W> {
     writer: W,
+    buffer: Vec<u8>,
 }
 
 impl<W: Write> RecordEncoder<W> {
     pub fn new(writer: W) -> Self {
-        RecordEncoder { writer }
+        RecordEncoder {
+            writer,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
     }
 
+    /// Writes the record field by field in its little-endian layout (see `layout`).
     pub fn encode_record(&mut self, record: &RecordRef) -> io::Result<()> {
-        let bytes = record.as_ref();
-        self.writer.write_all(bytes)?;
+        self.buffer.clear();
+        write_record_le(record, &mut self.buffer)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        self.writer.write_all(&self.buffer)?;
         Ok(())
     }
 
@@ -123,10 +257,245 @@ impl<W: Write> RecordEncoder<W> {
     }
 }
 
+impl<W: Write> RecordEncoder<zstd::stream::write::Encoder<'static, W>> {
+    /// Encoder compressing the stream into zstd frames at `level`, e.g. `DEFAULT_ZSTD_LEVEL`.
+    /// `finish` has to be called to write the end of the last frame.
+    pub fn new_zstd(writer: W, level: i32) -> io::Result<Self> {
+        Ok(RecordEncoder::new(zstd::stream::write::Encoder::new(
+            writer, level,
+        )?))
+    }
+
+    /// Completes the compressed stream and returns the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.writer.finish()
+    }
+}
+
+/// Appends records to an existing MBN file while keeping its metadata in sync.
+///
+/// Records are checked against the file's `Schema` and buffered until `commit`, which raises
+/// `Metadata.end` to the latest appended `ts_event` and rewrites the header with any merged
+/// mappings. If the metadata block keeps its length the records are appended and synced before
+/// the header is patched in place, so a crash leaves either the old header or a truncated tail
+/// that `repair_file` removes. Otherwise the file is rewritten to a temporary file that
+/// replaces it by rename.
+pub struct AppendWriter {
+    file_path: PathBuf,
+    metadata: Metadata,
+    /// Length of the header and metadata block currently in the file.
+    data_offset: u64,
+    /// rtype of the file's schema.
+    rtype: u8,
+    buffer: Vec<u8>,
+}
+
+impl AppendWriter {
+    /// Opens the file at `file_path`, which must hold metadata and end on a complete record.
+    pub fn open<P: AsRef<Path>>(file_path: P) -> Result<Self> {
+        let file_path = file_path.as_ref().to_path_buf();
+        let file = File::open(&file_path)?;
+        let file_len = file.metadata()?.len();
+        let (metadata, data_offset, complete_len) =
+            scan_records(&mut BufReader::new(file), file_len)?;
+        let metadata = metadata
+            .ok_or_else(|| Error::Decode("Can't append to a file without metadata".to_string()))?;
+        if complete_len < file_len {
+            return Err(Error::Decode(format!(
+                "Truncated record at byte {}, run repair_file before appending",
+                complete_len
+            )));
+        }
+        Ok(Self {
+            file_path,
+            rtype: RType::from(metadata.schema) as u8,
+            metadata,
+            data_offset,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Metadata as it will be written on the next `commit`.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Merges `mappings` into the file's symbol map, failing on conflicting instrument ids.
+    pub fn add_mappings(&mut self, mappings: &SymbolMap) -> Result<()> {
+        self.metadata.mappings.try_merge(mappings)
+    }
+
+    /// Buffers `record`, returning `Error::Encode` if its rtype doesn't match the schema.
+    pub fn append_record(&mut self, record: &RecordRef) -> Result<()> {
+        let header = record.header();
+        if header.rtype != self.rtype {
+            return Err(Error::Encode(format!(
+                "Record rtype {:#04x} does not match schema {}",
+                header.rtype, self.metadata.schema
+            )));
+        }
+        write_record_le(record, &mut self.buffer)?;
+        self.metadata.end = self.metadata.end.max(header.ts_event);
+        Ok(())
+    }
+
+    pub fn append_records(&mut self, records: &[RecordRef]) -> Result<()> {
+        for record in records {
+            self.append_record(record)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the buffered records and the updated header to the file.
+    pub fn commit(&mut self) -> Result<()> {
+        let mut header = Vec::new();
+        MetadataEncoder::new(&mut header).encode_metadata(&self.metadata)?;
+
+        if header.len() as u64 == self.data_offset {
+            let mut file = OpenOptions::new().write(true).open(&self.file_path)?;
+            file.seek(SeekFrom::End(0))?;
+            file.write_all(&self.buffer)?;
+            file.sync_data()?;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&header)?;
+            file.sync_all()?;
+        } else {
+            let mut temp_path = self.file_path.as_os_str().to_owned();
+            temp_path.push(".tmp");
+            let temp_path = PathBuf::from(temp_path);
+
+            let mut source = File::open(&self.file_path)?;
+            source.seek(SeekFrom::Start(self.data_offset))?;
+            let mut temp = File::create(&temp_path)?;
+            temp.write_all(&header)?;
+            io::copy(&mut source, &mut temp)?;
+            temp.write_all(&self.buffer)?;
+            temp.sync_all()?;
+            std::fs::rename(&temp_path, &self.file_path)?;
+            self.data_offset = header.len() as u64;
+        }
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
 // -- Aysnc --
 
+pub struct AsyncCombinedEncoder<W> {
+    writer: W,
+    index: Option<Index>,
+    every: u32,
+}
+
+impl<W> AsyncCombinedEncoder<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(writer: W) -> Self {
+        AsyncCombinedEncoder {
+            writer,
+            index: None,
+            every: 0,
+        }
+    }
+
+    /// Builds an `Index` with a checkpoint every `every` records while encoding, as
+    /// `CombinedEncoder::with_index`.
+    pub fn with_index(mut self, every: u32) -> Self {
+        self.every = every;
+        self.index = Some(Index::new(every, 0));
+        self
+    }
+
+    /// Index of the records encoded so far, if enabled with `with_index`.
+    pub fn index(&self) -> Option<&Index> {
+        self.index.as_ref()
+    }
+
+    pub async fn encode_metadata(&mut self, metadata: &Metadata) -> tokio::io::Result<()> {
+        let mut metadata_encoder = AsyncMetadataEncoder::new(&mut self.writer);
+        metadata_encoder.encode_metadata(metadata).await?;
+        if self.index.is_some() {
+            let data_offset = encoded_metadata_length(metadata);
+            self.index = Some(Index::new(self.every, data_offset));
+        }
+        Ok(())
+    }
+
+    pub async fn encode_record<'a>(&mut self, record: &'a RecordRef<'a>) -> tokio::io::Result<()> {
+        let mut record_encoder = AsyncRecordEncoder::new(&mut self.writer);
+        record_encoder.encode_record(record).await?;
+        if let Some(index) = self.index.as_mut() {
+            index.push(record.header());
+        }
+        Ok(())
+    }
+
+    pub async fn encode_records<'a>(
+        &mut self,
+        records: &'a [RecordRef<'a>],
+    ) -> tokio::io::Result<()> {
+        for record in records {
+            self.encode_record(record).await?;
+        }
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    pub async fn encode<'a>(
+        &mut self,
+        metadata: &Metadata,
+        records: &'a [RecordRef<'a>],
+    ) -> tokio::io::Result<()> {
+        self.encode_metadata(metadata).await?;
+        self.encode_records(records).await?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Writes the encoded buffer to `file_path`, see `CombinedEncoder::write_to_file`.
+    pub async fn write_to_file(&self, file_path: &Path, append: bool) -> tokio::io::Result<()>
+    where
+        W: AsRef<[u8]>,
+    {
+        AsyncRecordEncoder::<W>::write_to_file(file_path, append, self.writer.as_ref()).await?;
+        if let (Some(index), false) = (&self.index, append) {
+            let mut index = index.clone();
+            index.fingerprint = fingerprint(self.writer.as_ref());
+            tokio::fs::write(Index::sidecar_path(file_path), index.serialize()).await?;
+        }
+        Ok(())
+    }
+}
+
+pub struct AsyncMetadataEncoder<W> {
+    writer: W,
+}
+
+impl<W> AsyncMetadataEncoder<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(writer: W) -> Self {
+        AsyncMetadataEncoder { writer }
+    }
+
+    /// Writes the same header and metadata block as `MetadataEncoder::encode_metadata`.
+    pub async fn encode_metadata(&mut self, metadata: &Metadata) -> tokio::io::Result<()> {
+        let mut header = Vec::new();
+        MetadataEncoder::new(&mut header).encode_metadata(metadata)?;
+        self.writer.write_all(&header).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+}
+
 pub struct AsyncRecordEncoder<W> {
     writer: W,
+    buffer: Vec<u8>,
 }
 
 impl<W> AsyncRecordEncoder<W>
@@ -134,12 +503,22 @@ where
     W: AsyncWrite + Unpin,
 {
     pub fn new(writer: W) -> Self {
-        AsyncRecordEncoder { writer }
+        AsyncRecordEncoder {
+            writer,
+            buffer: Vec::new(),
+        }
     }
 
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Writes the record field by field in its little-endian layout (see `layout`).
     pub async fn encode_record<'a>(&mut self, record: &'a RecordRef<'a>) -> tokio::io::Result<()> {
-        let bytes = record.as_ref();
-        self.writer.write_all(bytes).await?;
+        self.buffer.clear();
+        write_record_le(record, &mut self.buffer)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        self.writer.write_all(&self.buffer).await?;
         Ok(())
     }
 
@@ -180,7 +559,7 @@ mod tests {
     use serial_test::serial;
 
     use super::*;
-    use crate::decode::AsyncDecoder;
+    use crate::decode::{AsyncDecoder, Decoder, MetadataDecoder, RecordDecoder};
     use crate::enums::Schema;
     use crate::record_enum::RecordEnum;
     use crate::records::BidAskPair;
@@ -188,6 +567,7 @@ mod tests {
     use crate::records::OhlcvMsg;
     use crate::records::RecordHeader;
     use crate::symbols::SymbolMap;
+    use crate::METADATA_LENGTH_PREFIX;
     use std::io::Cursor;
     use std::path::PathBuf;
 
@@ -214,6 +594,7 @@ mod tests {
         // Validate
         let cursor = Cursor::new(buffer);
         let mut decoder = AsyncDecoder::new(cursor).await?;
+        assert!(decoder.metadata().is_none());
         let record_ref = decoder.decode_ref().await?.unwrap();
         let decoded_record: &OhlcvMsg = record_ref.get().unwrap();
         assert_eq!(decoded_record, &ohlcv_msg);
@@ -265,6 +646,60 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_async_encode_metadata() -> anyhow::Result<()> {
+        let mut symbol_map = SymbolMap::new();
+        symbol_map.add_instrument("AAPL", 1);
+        let metadata = Metadata::new(Schema::Ohlcv1S, 1234567898765, 123456765432, symbol_map);
+
+        // Test
+        let mut buffer = Vec::new();
+        AsyncMetadataEncoder::new(&mut buffer)
+            .encode_metadata(&metadata)
+            .await?;
+
+        // Validate
+        let mut expected = Vec::new();
+        MetadataEncoder::new(&mut expected).encode_metadata(&metadata)?;
+        assert_eq!(buffer, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_async_combined_encoder_to_file() -> anyhow::Result<()> {
+        let metadata = Metadata::new(Schema::Mbp1, 1, 1000, SymbolMap::new());
+        let msgs = mbp1_msgs(10);
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+        let file = PathBuf::from("tests/async_combined.bin");
+
+        // Test
+        let mut encoder = AsyncCombinedEncoder::new(Vec::new()).with_index(4);
+        encoder.encode(&metadata, &records).await?;
+        encoder.write_to_file(&file, false).await?;
+
+        // Validate
+        let mut sync_buffer = Vec::new();
+        let mut sync_encoder = CombinedEncoder::new(&mut sync_buffer).with_index(4);
+        sync_encoder.encode(&metadata, &records)?;
+        assert_eq!(encoder.index(), sync_encoder.index());
+        assert_eq!(encoder.into_inner(), sync_buffer);
+
+        let mut decoder =
+            AsyncDecoder::<crate::compression::AsyncFileReader>::from_file(&file).await?;
+        assert_eq!(decoder.metadata(), Some(metadata));
+        assert_eq!(
+            decoder.decode().await?,
+            msgs.into_iter().map(RecordEnum::Mbp1).collect::<Vec<_>>()
+        );
+        assert!(Index::sidecar_path(&file).exists());
+
+        // Cleanup
+        std::fs::remove_file(Index::sidecar_path(&file))?;
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_encode_record() -> anyhow::Result<()> {
         let ohlcv_msg = OhlcvMsg {
@@ -285,6 +720,7 @@ mod tests {
         // Validate
         let cursor = Cursor::new(buffer);
         let mut decoder = AsyncDecoder::new(cursor).await?;
+        assert!(decoder.metadata().is_none());
         let record_ref = decoder.decode_ref().await?.unwrap();
         let decoded_record: &OhlcvMsg = record_ref.get().unwrap();
         assert_eq!(decoded_record, &ohlcv_msg);
@@ -351,7 +787,16 @@ mod tests {
             .expect("Error metadata encoding.");
 
         // Validate
-        let decoded = Metadata::deserialize(&buffer)?;
+        assert_eq!(&buffer[..3], MBN_MAGIC);
+        assert_eq!(buffer[3], MBN_VERSION);
+        assert_eq!(buffer[4], u8::from(FileLayout::Records));
+        assert!(buffer.len().is_multiple_of(8));
+
+        let block = &buffer[5..];
+        let length = u32::from_le_bytes(block[..METADATA_LENGTH_PREFIX].try_into()?) as usize;
+        assert_eq!(length, block.len() - METADATA_LENGTH_PREFIX);
+
+        let decoded = Metadata::deserialize(&block[METADATA_LENGTH_PREFIX..])?;
         assert_eq!(decoded.schema, metadata.schema);
         assert_eq!(decoded.start, metadata.start);
         assert_eq!(decoded.end, metadata.end);
@@ -359,6 +804,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_encode_metadata_large_symbol_map() -> anyhow::Result<()> {
+        let mut symbol_map = SymbolMap::new();
+        for id in 0..500 {
+            symbol_map.add_instrument(&format!("TICKER{}", id), id);
+        }
+
+        let metadata = Metadata::new(Schema::Mbp1, 1234567898765, 123456765432, symbol_map);
+
+        // Test
+        let mut buffer = Vec::new();
+        let mut encoder = MetadataEncoder::new(&mut buffer);
+        encoder
+            .encode_metadata(&metadata)
+            .expect("Error metadata encoding.");
+
+        // Validate
+        let mut decoder = MetadataDecoder::new(Cursor::new(buffer));
+        let decoded = decoder.decode()?.unwrap();
+        assert_eq!(decoded, metadata);
+        Ok(())
+    }
+
     #[test]
     fn test_encode() {
         // Metadata
@@ -551,7 +1019,7 @@ mod tests {
 
         // Validate
         let mut decoder =
-            <AsyncDecoder<tokio::io::BufReader<tokio::fs::File>>>::from_file(file.clone()).await?;
+            AsyncDecoder::<crate::compression::AsyncFileReader>::from_file(&file).await?;
         let records = decoder.decode().await?;
         let expected = vec![
             RecordEnum::from_ref(record_ref1)?,
@@ -566,4 +1034,170 @@ mod tests {
         }
         Ok(())
     }
+
+    fn mbp1_msgs(count: u64) -> Vec<Mbp1Msg> {
+        (0..count)
+            .map(|i| Mbp1Msg {
+                hd: RecordHeader::new::<Mbp1Msg>(1, 1622471124 + i),
+                price: 12345676543,
+                size: 100,
+                action: 0,
+                side: 0,
+                depth: 0,
+                flags: 0,
+                ts_recv: 1622471124 + i,
+                ts_in_delta: 0,
+                sequence: i as u32,
+                discriminator: 0,
+                levels: [BidAskPair {
+                    bid_px: 12345676000,
+                    ask_px: 12345677000,
+                    bid_sz: 10,
+                    ask_sz: 20,
+                    bid_ct: 1,
+                    ask_ct: 2,
+                }],
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_encode_zstd_to_file() -> anyhow::Result<()> {
+        let metadata = Metadata::new(Schema::Mbp1, 1, 1000, SymbolMap::new());
+        let msgs = mbp1_msgs(1000);
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+        let file = PathBuf::from("tests/mbp_zstd.bin");
+
+        // Test
+        let mut encoder = CombinedEncoder::new_zstd(
+            std::fs::File::create(&file)?,
+            crate::compression::DEFAULT_ZSTD_LEVEL,
+        )?;
+        encoder.encode(&metadata, &records)?;
+        encoder.finish()?;
+
+        // Validate
+        let expected: Vec<RecordEnum> = msgs.into_iter().map(RecordEnum::Mbp1).collect();
+        assert!(std::fs::metadata(&file)?.len() < (expected.len() * 80) as u64 / 10);
+
+        let mut decoder = Decoder::<crate::compression::FileReader>::from_file(&file)?;
+        assert_eq!(decoder.metadata(), Some(metadata.clone()));
+        assert_eq!(decoder.decode()?, expected);
+        assert!(decoder.seek_to_time(1622471200).is_err());
+
+        let mut decoder =
+            AsyncDecoder::<crate::compression::AsyncFileReader>::from_file(&file).await?;
+        assert_eq!(decoder.metadata(), Some(metadata));
+        assert_eq!(decoder.decode().await?, expected);
+
+        // Cleanup
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_record_encode_zstd_to_file() -> anyhow::Result<()> {
+        let msgs = mbp1_msgs(3);
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+        let file = PathBuf::from("tests/mbp_zstd_records.bin");
+
+        // Test
+        let mut encoder = RecordEncoder::new_zstd(std::fs::File::create(&file)?, 1)?;
+        encoder.encode_records(&records)?;
+        encoder.finish()?;
+
+        // Validate
+        let mut decoder = RecordDecoder::<crate::compression::FileReader>::from_file(&file)?;
+        let decoded = decoder.decode_to_owned()?;
+        assert_eq!(
+            decoded,
+            msgs.into_iter().map(RecordEnum::Mbp1).collect::<Vec<_>>()
+        );
+
+        // Cleanup
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_append_writer() -> anyhow::Result<()> {
+        let mut mappings = SymbolMap::new();
+        mappings.add_instrument("AAPL", 1);
+        let metadata = Metadata::new(Schema::Mbp1, 1, 1000, mappings);
+        let msgs = mbp1_msgs(12);
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+        let file = PathBuf::from("tests/append.bin");
+        let mut buffer = Vec::new();
+        let mut encoder = CombinedEncoder::new(&mut buffer);
+        encoder.encode(&metadata, &records[..5])?;
+        encoder.write_to_file(&file, false)?;
+
+        // Test
+        let mut writer = AppendWriter::open(&file)?;
+        writer.append_records(&records[5..10])?;
+        writer.commit()?;
+        let in_place = Decoder::<crate::compression::FileReader>::from_file(&file)?.decode()?;
+
+        let mut writer = AppendWriter::open(&file)?;
+        let mut new_mappings = SymbolMap::new();
+        new_mappings.add_instrument("TSLA", 2);
+        writer.add_mappings(&new_mappings)?;
+        let ohlcv = OhlcvMsg {
+            hd: RecordHeader::new::<OhlcvMsg>(2, 1),
+            open: 1,
+            high: 1,
+            low: 1,
+            close: 1,
+            volume: 1,
+        };
+        let mismatch = writer.append_record(&(&ohlcv).into());
+        writer.append_records(&records[10..])?;
+        writer.commit()?;
+        let mut decoder = Decoder::<crate::compression::FileReader>::from_file(&file)?;
+
+        // Validate
+        assert_eq!(in_place.len(), 10);
+        assert!(mismatch.is_err());
+        let metadata = decoder.metadata().unwrap();
+        assert_eq!(metadata.end, msgs[11].hd.ts_event);
+        assert_eq!(metadata.mappings.map[&1], "AAPL");
+        assert_eq!(metadata.mappings.map[&2], "TSLA");
+        assert_eq!(
+            decoder.decode()?,
+            msgs.into_iter().map(RecordEnum::Mbp1).collect::<Vec<_>>()
+        );
+        assert!(!PathBuf::from("tests/append.bin.tmp").exists());
+
+        // Cleanup
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_append_writer_rejects_truncated_file() -> anyhow::Result<()> {
+        let metadata = Metadata::new(Schema::Mbp1, 1, 1000, SymbolMap::new());
+        let msgs = mbp1_msgs(2);
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+        let file = PathBuf::from("tests/append_truncated.bin");
+        let mut buffer = Vec::new();
+        CombinedEncoder::new(&mut buffer).encode(&metadata, &records)?;
+        std::fs::write(&file, &buffer[..buffer.len() - 1])?;
+
+        // Test
+        let result = AppendWriter::open(&file);
+        crate::decode::repair_file(&file)?;
+        let repaired = AppendWriter::open(&file);
+
+        // Validate
+        assert!(result.is_err());
+        assert!(repaired.is_ok());
+
+        // Cleanup
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
 }
@@ -108,6 +108,8 @@ pub enum Schema {
     Tbbo = 7,
     Bbo1S = 8,
     Bbo1M = 9,
+    Mbp10 = 10,
+    Mbo = 11,
 }
 
 impl Schema {
@@ -122,6 +124,8 @@ impl Schema {
             Schema::Tbbo => "tbbo",
             Schema::Bbo1S => "bbo-1s",
             Schema::Bbo1M => "bbo-1m",
+            Schema::Mbp10 => "mbp-10",
+            Schema::Mbo => "mbo",
         }
     }
 }
@@ -140,6 +144,8 @@ impl FromStr for Schema {
             "tbbo" => Ok(Schema::Tbbo),
             "bbo-1s" => Ok(Schema::Bbo1S),
             "bbo-1m" => Ok(Schema::Bbo1M),
+            "mbp-10" => Ok(Schema::Mbp10),
+            "mbo" => Ok(Schema::Mbo),
             _ => Err(Error::Conversion(format!(
                 "Unknown Schema value: '{}'",
                 value
@@ -160,6 +166,8 @@ impl fmt::Display for Schema {
             Schema::Tbbo => write!(f, "tbbo"),
             Schema::Bbo1S => write!(f, "bbo-1s"),
             Schema::Bbo1M => write!(f, "bbo-1m"),
+            Schema::Mbp10 => write!(f, "mbp-10"),
+            Schema::Mbo => write!(f, "mbo"),
         }
     }
 }
@@ -178,6 +186,8 @@ pub enum RType {
     Trade = 0x03,
     Tbbo = 0x04,
     Bbo = 0x05,
+    Mbp10 = 0x06,
+    Mbo = 0x07,
 }
 
 impl RType {
@@ -188,6 +198,8 @@ impl RType {
             RType::Trade => "trade",
             RType::Tbbo => "tbbo",
             RType::Bbo => "bbo",
+            RType::Mbp10 => "mbp-10",
+            RType::Mbo => "mbo",
         }
     }
 }
@@ -202,6 +214,8 @@ impl TryFrom<u8> for RType {
             0x03 => Ok(RType::Trade),
             0x04 => Ok(RType::Tbbo),
             0x05 => Ok(RType::Bbo),
+            0x06 => Ok(RType::Mbp10),
+            0x07 => Ok(RType::Mbo),
             _ => Err(Error::Conversion(format!(
                 "Unknown RType value: '{}'",
                 value
@@ -222,6 +236,8 @@ impl From<Schema> for RType {
             Schema::Tbbo => RType::Tbbo,
             Schema::Bbo1S => RType::Bbo,
             Schema::Bbo1M => RType::Bbo,
+            Schema::Mbp10 => RType::Mbp10,
+            Schema::Mbo => RType::Mbo,
         }
     }
 }
@@ -236,6 +252,8 @@ impl FromStr for RType {
             "trade" => Ok(RType::Trade),
             "tbbo" => Ok(RType::Tbbo),
             "bbo" => Ok(RType::Bbo),
+            "mbp-10" => Ok(RType::Mbp10),
+            "mbo" => Ok(RType::Mbo),
             _ => Err(Error::Conversion(format!("Invalid value for RType: {}", s))),
         }
     }
@@ -249,6 +267,43 @@ impl fmt::Display for RType {
             RType::Trade => write!(f, "trade"),
             RType::Tbbo => write!(f, "tbbo"),
             RType::Bbo => write!(f, "bbo"),
+            RType::Mbp10 => write!(f, "mbp-10"),
+            RType::Mbo => write!(f, "mbo"),
+        }
+    }
+}
+
+/// How the records after the metadata block of an MBN file are stored, written in the header
+/// since version 3.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, TryFromPrimitive, IntoPrimitive)]
+pub enum FileLayout {
+    /// Records one after another, read by `Decoder`, `AsyncDecoder` and `MmapDecoder`.
+    #[default]
+    Records = 0,
+    /// CRC32 checksummed blocks, read by `ChecksumDecoder`.
+    Checksum = 1,
+    /// Delta-encoded columnar blocks, read by `ColumnarDecoder`.
+    Columnar = 2,
+}
+
+impl FileLayout {
+    /// Name of the decoder reading files of this layout.
+    pub fn decoder(&self) -> &'static str {
+        match self {
+            FileLayout::Records => "Decoder",
+            FileLayout::Checksum => "ChecksumDecoder",
+            FileLayout::Columnar => "ColumnarDecoder",
+        }
+    }
+}
+
+impl fmt::Display for FileLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileLayout::Records => write!(f, "records"),
+            FileLayout::Checksum => write!(f, "checksum"),
+            FileLayout::Columnar => write!(f, "columnar"),
         }
     }
 }
@@ -301,6 +356,8 @@ mod tests {
 
         // From str
         let _: Schema = Schema::from_str(schema_str).unwrap();
+        assert_eq!(Schema::from_str("mbp-10").unwrap(), Schema::Mbp10);
+        assert_eq!(RType::from(Schema::Mbp10), RType::Mbp10);
     }
 
     #[test]
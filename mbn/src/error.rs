@@ -5,7 +5,7 @@ use thiserror::Error;
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("IO error: {0}")]
-    Io(#[from] io::Error),
+    Io(io::Error),
     #[error("Encoding error: {0}")]
     Encode(String),
     #[error("Decoding error: {0}")]
@@ -20,6 +20,36 @@ pub enum Error {
     InvalidRecordType(&'static str),
     #[error("Date error: {0}")]
     DateError(String),
+    #[error("Order book error: {0}")]
+    Book(String),
+    /// The stream ended inside the record starting at `offset`, e.g. a recording that was
+    /// interrupted mid write.
+    #[error("Truncated record at byte {offset}: expected {expected} bytes, got {got}")]
+    Truncated {
+        offset: u64,
+        expected: usize,
+        got: usize,
+    },
+    /// The record at `offset` declares a length shorter than its header, so the records after
+    /// it can't be located.
+    #[error("Invalid record at byte {offset} with length {length} shorter than header")]
+    Framing { offset: u64, length: usize },
+    /// A shared memory ring reader fell behind and `missed` records were overwritten.
+    #[error("Ring overrun: {missed} records were overwritten before being read")]
+    Overrun { missed: u64 },
+}
+
+/// Unwraps errors of this crate that record decoders carry inside an `io::Error`, so
+/// `Error::Truncated` survives `?` through `io::Result` returning functions.
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        if !err.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            return Error::Io(err);
+        }
+        *err.into_inner()
+            .and_then(|inner| inner.downcast::<Error>().ok())
+            .expect("inner error checked above")
+    }
 }
 
 impl Error {
@@ -31,6 +61,21 @@ impl Error {
             error_string
         }
     }
+
+    /// Wraps the error in an `io::Error`, keeping it recoverable through `From<io::Error>`.
+    pub(crate) fn into_io(self) -> io::Error {
+        let kind = match self {
+            Error::Io(err) => return err,
+            Error::Truncated { .. } => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, self)
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+/// `InvalidData` error for malformed bytes read through `io::Result` returning functions.
+pub(crate) fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
@@ -0,0 +1,407 @@
+//! Sidecar index mapping `ts_event` to byte offsets in an MBN file.
+//!
+//! The index is stored next to the data file as `<file>.idx`: the magic `MBN_INDEX_MAGIC`, a
+//! version byte, then little-endian fields (see `Index::serialize`). It records a checkpoint
+//! every `every` records and the first and last record offset of each instrument. Offsets are
+//! absolute byte positions in the data file and assume records are sorted by `ts_event`. The
+//! length of the data file and a CRC-32 of its first bytes are stored to detect a stale index.
+use crate::decode::MetadataDecoder;
+use crate::error::{Error, Result};
+use crate::layout::ByteLayout;
+use crate::records::RecordHeader;
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+/// Signature at the start of every index file.
+pub const MBN_INDEX_MAGIC: &[u8; 4] = b"MBNI";
+/// Current version of the index layout. Version 2 adds `Index::fingerprint`.
+pub const MBN_INDEX_VERSION: u8 = 2;
+/// Bytes from the start of the data file covered by `Index::fingerprint`.
+pub const FINGERPRINT_LENGTH: usize = 4096;
+/// Records between checkpoints when none is given.
+pub const DEFAULT_CHECKPOINT_EVERY: u32 = 1024;
+
+/// Position of a record in the data file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub ts_event: u64,
+    pub offset: u64,
+}
+
+/// Offsets of the first and last record of an instrument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstrumentSpan {
+    pub first: u64,
+    pub last: u64,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Index {
+    /// Records between checkpoints.
+    pub every: u32,
+    /// Offset of the first record, right after the metadata block.
+    pub data_offset: u64,
+    /// Length of the indexed file in bytes, used to detect a stale index.
+    pub file_len: u64,
+    /// CRC-32 of the first `FINGERPRINT_LENGTH` bytes of the indexed file, i.e. its header and
+    /// first records, used with `file_len` to detect a stale index. Set when the index is built
+    /// or written with its data file, 0 before.
+    pub fingerprint: u32,
+    pub records: u64,
+    pub checkpoints: Vec<Checkpoint>,
+    pub instruments: BTreeMap<u32, InstrumentSpan>,
+}
+
+impl Index {
+    pub fn new(every: u32, data_offset: u64) -> Self {
+        Self {
+            every: every.max(1),
+            data_offset,
+            file_len: data_offset,
+            fingerprint: 0,
+            records: 0,
+            checkpoints: Vec::new(),
+            instruments: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the record written at the current end of the indexed file.
+    pub fn push(&mut self, header: &RecordHeader) {
+        let offset = self.file_len;
+        if self.records.is_multiple_of(self.every as u64) {
+            self.checkpoints.push(Checkpoint {
+                ts_event: header.ts_event,
+                offset,
+            });
+        }
+        self.instruments
+            .entry(header.instrument_id)
+            .and_modify(|span| {
+                span.last = offset;
+                span.count += 1;
+            })
+            .or_insert(InstrumentSpan {
+                first: offset,
+                last: offset,
+                count: 1,
+            });
+        self.records += 1;
+        self.file_len += header.record_size() as u64;
+    }
+
+    /// Offset to start scanning from for the first record with `ts_event >= ts`.
+    pub fn scan_offset(&self, ts: u64) -> u64 {
+        let idx = self.checkpoints.partition_point(|c| c.ts_event < ts);
+        match idx {
+            0 => self.data_offset,
+            _ => self.checkpoints[idx - 1].offset,
+        }
+    }
+
+    /// Builds the index by reading every record header of an MBN file from its start.
+    pub fn build<R: Read + Seek>(reader: &mut R, every: u32) -> Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let fingerprint = read_fingerprint(&mut *reader)?;
+        reader.seek(SeekFrom::Start(0))?;
+        MetadataDecoder::new(&mut *reader).decode()?;
+        let mut index = Index::new(every, reader.stream_position()?);
+        index.fingerprint = fingerprint;
+        while let Some(header) = read_header(reader)? {
+            index.push(&header);
+            reader.seek(SeekFrom::Start(index.file_len))?;
+        }
+        Ok(index)
+    }
+
+    /// Path of the sidecar index for `file_path`, i.e. `file_path` with `.idx` appended.
+    pub fn sidecar_path<P: AsRef<Path>>(file_path: P) -> PathBuf {
+        let mut path = file_path.as_ref().as_os_str().to_owned();
+        path.push(".idx");
+        PathBuf::from(path)
+    }
+
+    /// Loads the sidecar index of `file_path`, or builds and writes it if it is missing or was
+    /// written for a file of a different length or fingerprint.
+    pub fn load_or_build<P: AsRef<Path>>(file_path: P) -> Result<Self> {
+        let file_path = file_path.as_ref();
+        let sidecar = Self::sidecar_path(file_path);
+        let mut file = std::io::BufReader::new(std::fs::File::open(file_path)?);
+        let file_len = file.get_ref().metadata()?.len();
+        if let Ok(bytes) = std::fs::read(&sidecar) {
+            if let Ok(index) = Self::deserialize(&bytes) {
+                if index.file_len == file_len && index.fingerprint == read_fingerprint(&mut file)? {
+                    return Ok(index);
+                }
+            }
+        }
+        let index = Self::build(&mut file, DEFAULT_CHECKPOINT_EVERY)?;
+        index.write_to_file(&sidecar)?;
+        Ok(index)
+    }
+
+    pub fn write_to_file<P: AsRef<Path>>(&self, file_path: P) -> Result<()> {
+        std::fs::write(file_path, self.serialize())?;
+        Ok(())
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MBN_INDEX_MAGIC);
+        bytes.push(MBN_INDEX_VERSION);
+        bytes.extend_from_slice(&self.every.to_le_bytes());
+        bytes.extend_from_slice(&self.data_offset.to_le_bytes());
+        bytes.extend_from_slice(&self.file_len.to_le_bytes());
+        bytes.extend_from_slice(&self.fingerprint.to_le_bytes());
+        bytes.extend_from_slice(&self.records.to_le_bytes());
+        bytes.extend_from_slice(&(self.checkpoints.len() as u32).to_le_bytes());
+        for checkpoint in &self.checkpoints {
+            bytes.extend_from_slice(&checkpoint.ts_event.to_le_bytes());
+            bytes.extend_from_slice(&checkpoint.offset.to_le_bytes());
+        }
+        bytes.extend_from_slice(&(self.instruments.len() as u32).to_le_bytes());
+        for (id, span) in &self.instruments {
+            bytes.extend_from_slice(&id.to_le_bytes());
+            bytes.extend_from_slice(&span.first.to_le_bytes());
+            bytes.extend_from_slice(&span.last.to_le_bytes());
+            bytes.extend_from_slice(&span.count.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        let mut reader = IndexReader { bytes, offset: 0 };
+        if reader.take(MBN_INDEX_MAGIC.len())? != MBN_INDEX_MAGIC {
+            return Err(Error::Decode("Not an MBN index file".to_string()));
+        }
+        let version = reader.take(1)?[0];
+        if version != MBN_INDEX_VERSION {
+            return Err(Error::Decode(format!(
+                "Unsupported MBN index version {}",
+                version
+            )));
+        }
+        let every = reader.u32()?;
+        let data_offset = reader.u64()?;
+        let file_len = reader.u64()?;
+        let fingerprint = reader.u32()?;
+        let records = reader.u64()?;
+
+        let checkpoint_count = reader.u32()? as usize;
+        let mut checkpoints = Vec::with_capacity(checkpoint_count.min(bytes.len() / 16));
+        for _ in 0..checkpoint_count {
+            checkpoints.push(Checkpoint {
+                ts_event: reader.u64()?,
+                offset: reader.u64()?,
+            });
+        }
+
+        let instrument_count = reader.u32()?;
+        let mut instruments = BTreeMap::new();
+        for _ in 0..instrument_count {
+            let id = reader.u32()?;
+            instruments.insert(
+                id,
+                InstrumentSpan {
+                    first: reader.u64()?,
+                    last: reader.u64()?,
+                    count: reader.u64()?,
+                },
+            );
+        }
+
+        Ok(Self {
+            every,
+            data_offset,
+            file_len,
+            fingerprint,
+            records,
+            checkpoints,
+            instruments,
+        })
+    }
+}
+
+/// CRC-32 of the first `FINGERPRINT_LENGTH` bytes of a data file, see `Index::fingerprint`.
+pub fn fingerprint(data: &[u8]) -> u32 {
+    crc32fast::hash(&data[..data.len().min(FINGERPRINT_LENGTH)])
+}
+
+/// `fingerprint` of the data file read from the start by `reader`.
+fn read_fingerprint<R: Read>(reader: R) -> Result<u32> {
+    let mut prefix = Vec::new();
+    reader
+        .take(FINGERPRINT_LENGTH as u64)
+        .read_to_end(&mut prefix)?;
+    Ok(fingerprint(&prefix))
+}
+
+/// Reads the header of the record at the current position without consuming the record body.
+/// Returns `Ok(None)` at the end of the stream.
+pub(crate) fn read_header<R: Read>(reader: &mut R) -> Result<Option<RecordHeader>> {
+    let mut bytes = [0u8; RecordHeader::SIZE];
+    match reader.read_exact(&mut bytes) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let header = RecordHeader::read_le(&bytes)?;
+    if header.record_size() < RecordHeader::SIZE {
+        return Err(Error::Decode(format!(
+            "invalid record with length {} shorter than header",
+            header.record_size()
+        )));
+    }
+    Ok(Some(header))
+}
+
+/// Bounds-checked cursor over a serialized index.
+struct IndexReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> IndexReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let slice = self
+            .bytes
+            .get(self.offset..self.offset + n)
+            .ok_or_else(|| Error::Decode("Truncated MBN index".to_string()))?;
+        self.offset += n;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::CombinedEncoder;
+    use crate::enums::Schema;
+    use crate::metadata::Metadata;
+    use crate::record_ref::RecordRef;
+    use crate::records::OhlcvMsg;
+    use crate::symbols::SymbolMap;
+    use serial_test::serial;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    fn ohlcv(instrument_id: u32, ts_event: u64) -> OhlcvMsg {
+        OhlcvMsg {
+            hd: RecordHeader::new::<OhlcvMsg>(instrument_id, ts_event),
+            open: 100,
+            high: 200,
+            low: 50,
+            close: 150,
+            volume: 1000,
+        }
+    }
+
+    fn encode(every: u32) -> (Vec<u8>, Index) {
+        let metadata = Metadata::new(Schema::Ohlcv1S, 0, 10, SymbolMap::new());
+        let msgs: Vec<OhlcvMsg> = (0..10).map(|i| ohlcv(1 + i as u32 % 2, i)).collect();
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+
+        let mut buffer = Vec::new();
+        let mut encoder = CombinedEncoder::new(&mut buffer).with_index(every);
+        encoder.encode(&metadata, &records).unwrap();
+        let index = encoder.index().cloned().unwrap();
+        (buffer, index)
+    }
+
+    #[test]
+    fn test_index_from_encoder_matches_build() -> anyhow::Result<()> {
+        let (buffer, index) = encode(4);
+
+        // Test
+        let built = Index::build(&mut Cursor::new(&buffer), 4)?;
+
+        // Validate
+        assert_eq!(built.fingerprint, fingerprint(&buffer));
+        assert_eq!(
+            built,
+            Index {
+                fingerprint: built.fingerprint,
+                ..index.clone()
+            }
+        );
+        assert_eq!(index.file_len, buffer.len() as u64);
+        assert_eq!(index.records, 10);
+        assert_eq!(
+            index
+                .checkpoints
+                .iter()
+                .map(|c| c.ts_event)
+                .collect::<Vec<_>>(),
+            vec![0, 4, 8]
+        );
+        assert_eq!(index.instruments[&2].count, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_serialize() -> anyhow::Result<()> {
+        let (_, index) = encode(3);
+
+        // Test
+        let bytes = index.serialize();
+        let decoded = Index::deserialize(&bytes)?;
+
+        // Validate
+        assert_eq!(decoded, index);
+        assert!(Index::deserialize(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Index::deserialize(b"MBN\x01").is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_load_or_build_rebuilds_stale_sidecar() -> anyhow::Result<()> {
+        let (buffer, _) = encode(4);
+        let file = PathBuf::from("tests/stale_index.bin");
+        std::fs::write(&file, &buffer)?;
+        let index = Index::load_or_build(&file)?;
+
+        // Test
+        // Same length, different first record.
+        let mut changed = buffer.clone();
+        let first = index.data_offset as usize;
+        changed[first + 4..first + 8].copy_from_slice(&7u32.to_le_bytes());
+        std::fs::write(&file, &changed)?;
+        let rebuilt = Index::load_or_build(&file)?;
+
+        // Validate
+        assert_eq!(index.instruments[&1].first, index.data_offset);
+        assert_ne!(rebuilt.fingerprint, index.fingerprint);
+        assert_eq!(rebuilt.instruments[&7].first, index.data_offset);
+        assert_eq!(rebuilt.instruments[&1].count, 4);
+        assert_eq!(
+            Index::deserialize(&std::fs::read(Index::sidecar_path(&file))?)?,
+            rebuilt
+        );
+
+        // Cleanup
+        std::fs::remove_file(Index::sidecar_path(&file))?;
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_offset() {
+        let (_, index) = encode(4);
+
+        // Validate
+        assert_eq!(index.scan_offset(0), index.data_offset);
+        assert_eq!(index.scan_offset(4), index.checkpoints[0].offset);
+        assert_eq!(index.scan_offset(5), index.checkpoints[1].offset);
+        assert_eq!(index.scan_offset(100), index.checkpoints[2].offset);
+    }
+}
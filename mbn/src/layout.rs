@@ -0,0 +1,752 @@
+//! Explicit little-endian byte layout of each record.
+//!
+//! Every field is written at a fixed offset in little-endian order and padding is written as
+//! zeros. The offsets match the `repr(C)` layout of the structs on a little-endian host, so
+//! zero-copy `RecordRef` access and the explicit path agree on those hosts, while encoding
+//! and owned decoding never depend on the host's endianness, padding or compiler layout.
+use crate::enums::RType;
+use crate::error::{Error, Result};
+use crate::record_enum::{RecordEnum, RecordEnumRef};
+use crate::record_ref::RecordRef;
+use crate::records::{
+    BboMsg, BidAskPair, MboMsg, Mbp10Msg, Mbp1Msg, OhlcvMsg, RecordHeader, TradeMsg, UnknownMsg,
+};
+
+/// Trait for writing and reading a type field by field in little-endian order.
+pub trait ByteLayout: Sized {
+    /// Size in bytes of the encoded type.
+    const SIZE: usize;
+
+    /// Appends the little-endian encoding of `self` to `buffer`.
+    fn write_le(&self, buffer: &mut Vec<u8>);
+
+    /// Reads a value from the start of `bytes`, which must hold at least `SIZE` bytes.
+    fn read_le(bytes: &[u8]) -> Result<Self>;
+}
+
+/// Cursor reading little-endian values from a slice already checked to be long enough.
+struct LeReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> LeReader<'a> {
+    fn new<T: ByteLayout>(bytes: &'a [u8]) -> Result<Self> {
+        if bytes.len() < T::SIZE {
+            return Err(Error::Decode(format!(
+                "{} requires {} bytes, got {}",
+                std::any::type_name::<T>(),
+                T::SIZE,
+                bytes.len()
+            )));
+        }
+        Ok(Self { bytes, offset: 0 })
+    }
+
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let mut out = [0u8; N];
+        out.copy_from_slice(&self.bytes[self.offset..self.offset + N]);
+        self.offset += N;
+        out
+    }
+
+    fn skip(&mut self, n: usize) {
+        self.offset += n;
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.offset..]
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take::<1>()[0]
+    }
+
+    fn i8(&mut self) -> i8 {
+        i8::from_le_bytes(self.take())
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.take())
+    }
+
+    fn i32(&mut self) -> i32 {
+        i32::from_le_bytes(self.take())
+    }
+
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.take())
+    }
+
+    fn i64(&mut self) -> i64 {
+        i64::from_le_bytes(self.take())
+    }
+
+    fn bid_ask_pair(&mut self) -> BidAskPair {
+        BidAskPair {
+            bid_px: self.i64(),
+            ask_px: self.i64(),
+            bid_sz: self.u32(),
+            ask_sz: self.u32(),
+            bid_ct: self.u32(),
+            ask_ct: self.u32(),
+        }
+    }
+}
+
+/// Layout (16 bytes):
+///
+/// | offset | field         | type |
+/// |--------|---------------|------|
+/// | 0      | length        | u8   |
+/// | 1      | rtype         | u8   |
+/// | 2      | padding       | 2    |
+/// | 4      | instrument_id | u32  |
+/// | 8      | ts_event      | u64  |
+impl ByteLayout for RecordHeader {
+    const SIZE: usize = 16;
+
+    fn write_le(&self, buffer: &mut Vec<u8>) {
+        buffer.push(self.length);
+        buffer.push(self.rtype);
+        buffer.extend_from_slice(&[0; 2]);
+        buffer.extend_from_slice(&self.instrument_id.to_le_bytes());
+        buffer.extend_from_slice(&self.ts_event.to_le_bytes());
+    }
+
+    fn read_le(bytes: &[u8]) -> Result<Self> {
+        let mut reader = LeReader::new::<Self>(bytes)?;
+        let length = reader.u8();
+        let rtype = reader.u8();
+        reader.skip(2);
+        Ok(RecordHeader {
+            length,
+            rtype,
+            instrument_id: reader.u32(),
+            ts_event: reader.u64(),
+        })
+    }
+}
+
+/// Layout (32 bytes):
+///
+/// | offset | field  | type |
+/// |--------|--------|------|
+/// | 0      | bid_px | i64  |
+/// | 8      | ask_px | i64  |
+/// | 16     | bid_sz | u32  |
+/// | 20     | ask_sz | u32  |
+/// | 24     | bid_ct | u32  |
+/// | 28     | ask_ct | u32  |
+impl ByteLayout for BidAskPair {
+    const SIZE: usize = 32;
+
+    fn write_le(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.bid_px.to_le_bytes());
+        buffer.extend_from_slice(&self.ask_px.to_le_bytes());
+        buffer.extend_from_slice(&self.bid_sz.to_le_bytes());
+        buffer.extend_from_slice(&self.ask_sz.to_le_bytes());
+        buffer.extend_from_slice(&self.bid_ct.to_le_bytes());
+        buffer.extend_from_slice(&self.ask_ct.to_le_bytes());
+    }
+
+    fn read_le(bytes: &[u8]) -> Result<Self> {
+        let mut reader = LeReader::new::<Self>(bytes)?;
+        Ok(reader.bid_ask_pair())
+    }
+}
+
+/// Layout (88 bytes):
+///
+/// | offset | field         | type       |
+/// |--------|---------------|------------|
+/// | 0      | hd            | header     |
+/// | 16     | price         | i64        |
+/// | 24     | size          | u32        |
+/// | 28     | action        | i8         |
+/// | 29     | side          | i8         |
+/// | 30     | depth         | u8         |
+/// | 31     | flags         | u8         |
+/// | 32     | ts_recv       | u64        |
+/// | 40     | ts_in_delta   | i32        |
+/// | 44     | sequence      | u32        |
+/// | 48     | discriminator | u32        |
+/// | 52     | padding       | 4          |
+/// | 56     | levels[0]     | BidAskPair |
+impl ByteLayout for Mbp1Msg {
+    const SIZE: usize = 88;
+
+    fn write_le(&self, buffer: &mut Vec<u8>) {
+        self.hd.write_le(buffer);
+        buffer.extend_from_slice(&self.price.to_le_bytes());
+        buffer.extend_from_slice(&self.size.to_le_bytes());
+        buffer.extend_from_slice(&self.action.to_le_bytes());
+        buffer.extend_from_slice(&self.side.to_le_bytes());
+        buffer.push(self.depth);
+        buffer.push(self.flags);
+        buffer.extend_from_slice(&self.ts_recv.to_le_bytes());
+        buffer.extend_from_slice(&self.ts_in_delta.to_le_bytes());
+        buffer.extend_from_slice(&self.sequence.to_le_bytes());
+        buffer.extend_from_slice(&self.discriminator.to_le_bytes());
+        buffer.extend_from_slice(&[0; 4]);
+        self.levels[0].write_le(buffer);
+    }
+
+    fn read_le(bytes: &[u8]) -> Result<Self> {
+        let mut reader = LeReader::new::<Self>(bytes)?;
+        let hd = RecordHeader::read_le(reader.rest())?;
+        reader.skip(RecordHeader::SIZE);
+        let price = reader.i64();
+        let size = reader.u32();
+        let action = reader.i8();
+        let side = reader.i8();
+        let depth = reader.u8();
+        let flags = reader.u8();
+        let ts_recv = reader.u64();
+        let ts_in_delta = reader.i32();
+        let sequence = reader.u32();
+        let discriminator = reader.u32();
+        reader.skip(4);
+        let levels = [BidAskPair::read_le(reader.rest())?];
+        Ok(Mbp1Msg {
+            hd,
+            price,
+            size,
+            action,
+            side,
+            depth,
+            flags,
+            ts_recv,
+            ts_in_delta,
+            sequence,
+            discriminator,
+            levels,
+        })
+    }
+}
+
+/// Layout (376 bytes), identical to `Mbp1Msg` up to the levels:
+///
+/// | offset | field         | type            |
+/// |--------|---------------|-----------------|
+/// | 0      | hd            | header          |
+/// | 16     | price         | i64             |
+/// | 24     | size          | u32             |
+/// | 28     | action        | i8              |
+/// | 29     | side          | i8              |
+/// | 30     | depth         | u8              |
+/// | 31     | flags         | u8              |
+/// | 32     | ts_recv       | u64             |
+/// | 40     | ts_in_delta   | i32             |
+/// | 44     | sequence      | u32             |
+/// | 48     | discriminator | u32             |
+/// | 52     | padding       | 4               |
+/// | 56     | levels[0..10] | BidAskPair × 10 |
+impl ByteLayout for Mbp10Msg {
+    const SIZE: usize = 376;
+
+    fn write_le(&self, buffer: &mut Vec<u8>) {
+        self.hd.write_le(buffer);
+        buffer.extend_from_slice(&self.price.to_le_bytes());
+        buffer.extend_from_slice(&self.size.to_le_bytes());
+        buffer.extend_from_slice(&self.action.to_le_bytes());
+        buffer.extend_from_slice(&self.side.to_le_bytes());
+        buffer.push(self.depth);
+        buffer.push(self.flags);
+        buffer.extend_from_slice(&self.ts_recv.to_le_bytes());
+        buffer.extend_from_slice(&self.ts_in_delta.to_le_bytes());
+        buffer.extend_from_slice(&self.sequence.to_le_bytes());
+        buffer.extend_from_slice(&self.discriminator.to_le_bytes());
+        buffer.extend_from_slice(&[0; 4]);
+        for level in &self.levels {
+            level.write_le(buffer);
+        }
+    }
+
+    fn read_le(bytes: &[u8]) -> Result<Self> {
+        let mut reader = LeReader::new::<Self>(bytes)?;
+        let hd = RecordHeader::read_le(reader.rest())?;
+        reader.skip(RecordHeader::SIZE);
+        let price = reader.i64();
+        let size = reader.u32();
+        let action = reader.i8();
+        let side = reader.i8();
+        let depth = reader.u8();
+        let flags = reader.u8();
+        let ts_recv = reader.u64();
+        let ts_in_delta = reader.i32();
+        let sequence = reader.u32();
+        let discriminator = reader.u32();
+        reader.skip(4);
+        let levels = std::array::from_fn(|_| reader.bid_ask_pair());
+        Ok(Mbp10Msg {
+            hd,
+            price,
+            size,
+            action,
+            side,
+            depth,
+            flags,
+            ts_recv,
+            ts_in_delta,
+            sequence,
+            discriminator,
+            levels,
+        })
+    }
+}
+
+/// Layout (48 bytes):
+///
+/// | offset | field       | type   |
+/// |--------|-------------|--------|
+/// | 0      | hd          | header |
+/// | 16     | price       | i64    |
+/// | 24     | size        | u32    |
+/// | 28     | action      | i8     |
+/// | 29     | side        | i8     |
+/// | 30     | depth       | u8     |
+/// | 31     | flags       | u8     |
+/// | 32     | ts_recv     | u64    |
+/// | 40     | ts_in_delta | i32    |
+/// | 44     | sequence    | u32    |
+impl ByteLayout for TradeMsg {
+    const SIZE: usize = 48;
+
+    fn write_le(&self, buffer: &mut Vec<u8>) {
+        self.hd.write_le(buffer);
+        buffer.extend_from_slice(&self.price.to_le_bytes());
+        buffer.extend_from_slice(&self.size.to_le_bytes());
+        buffer.extend_from_slice(&self.action.to_le_bytes());
+        buffer.extend_from_slice(&self.side.to_le_bytes());
+        buffer.push(self.depth);
+        buffer.push(self.flags);
+        buffer.extend_from_slice(&self.ts_recv.to_le_bytes());
+        buffer.extend_from_slice(&self.ts_in_delta.to_le_bytes());
+        buffer.extend_from_slice(&self.sequence.to_le_bytes());
+    }
+
+    fn read_le(bytes: &[u8]) -> Result<Self> {
+        let mut reader = LeReader::new::<Self>(bytes)?;
+        let hd = RecordHeader::read_le(reader.rest())?;
+        reader.skip(RecordHeader::SIZE);
+        Ok(TradeMsg {
+            hd,
+            price: reader.i64(),
+            size: reader.u32(),
+            action: reader.i8(),
+            side: reader.i8(),
+            depth: reader.u8(),
+            flags: reader.u8(),
+            ts_recv: reader.u64(),
+            ts_in_delta: reader.i32(),
+            sequence: reader.u32(),
+        })
+    }
+}
+
+/// Layout (80 bytes):
+///
+/// | offset | field     | type       |
+/// |--------|-----------|------------|
+/// | 0      | hd        | header     |
+/// | 16     | price     | i64        |
+/// | 24     | size      | u32        |
+/// | 28     | side      | i8         |
+/// | 29     | flags     | u8         |
+/// | 30     | padding   | 2          |
+/// | 32     | ts_recv   | u64        |
+/// | 40     | sequence  | u32        |
+/// | 44     | padding   | 4          |
+/// | 48     | levels[0] | BidAskPair |
+impl ByteLayout for BboMsg {
+    const SIZE: usize = 80;
+
+    fn write_le(&self, buffer: &mut Vec<u8>) {
+        self.hd.write_le(buffer);
+        buffer.extend_from_slice(&self.price.to_le_bytes());
+        buffer.extend_from_slice(&self.size.to_le_bytes());
+        buffer.extend_from_slice(&self.side.to_le_bytes());
+        buffer.push(self.flags);
+        buffer.extend_from_slice(&[0; 2]);
+        buffer.extend_from_slice(&self.ts_recv.to_le_bytes());
+        buffer.extend_from_slice(&self.sequence.to_le_bytes());
+        buffer.extend_from_slice(&[0; 4]);
+        self.levels[0].write_le(buffer);
+    }
+
+    fn read_le(bytes: &[u8]) -> Result<Self> {
+        let mut reader = LeReader::new::<Self>(bytes)?;
+        let hd = RecordHeader::read_le(reader.rest())?;
+        reader.skip(RecordHeader::SIZE);
+        let price = reader.i64();
+        let size = reader.u32();
+        let side = reader.i8();
+        let flags = reader.u8();
+        reader.skip(2);
+        let ts_recv = reader.u64();
+        let sequence = reader.u32();
+        reader.skip(4);
+        let levels = [BidAskPair::read_le(reader.rest())?];
+        Ok(BboMsg {
+            hd,
+            price,
+            size,
+            side,
+            flags,
+            ts_recv,
+            sequence,
+            levels,
+        })
+    }
+}
+
+/// Layout (56 bytes):
+///
+/// | offset | field  | type   |
+/// |--------|--------|--------|
+/// | 0      | hd     | header |
+/// | 16     | open   | i64    |
+/// | 24     | high   | i64    |
+/// | 32     | low    | i64    |
+/// | 40     | close  | i64    |
+/// | 48     | volume | u64    |
+impl ByteLayout for OhlcvMsg {
+    const SIZE: usize = 56;
+
+    fn write_le(&self, buffer: &mut Vec<u8>) {
+        self.hd.write_le(buffer);
+        buffer.extend_from_slice(&self.open.to_le_bytes());
+        buffer.extend_from_slice(&self.high.to_le_bytes());
+        buffer.extend_from_slice(&self.low.to_le_bytes());
+        buffer.extend_from_slice(&self.close.to_le_bytes());
+        buffer.extend_from_slice(&self.volume.to_le_bytes());
+    }
+
+    fn read_le(bytes: &[u8]) -> Result<Self> {
+        let mut reader = LeReader::new::<Self>(bytes)?;
+        let hd = RecordHeader::read_le(reader.rest())?;
+        reader.skip(RecordHeader::SIZE);
+        Ok(OhlcvMsg {
+            hd,
+            open: reader.i64(),
+            high: reader.i64(),
+            low: reader.i64(),
+            close: reader.i64(),
+            volume: reader.u64(),
+        })
+    }
+}
+
+/// Layout (56 bytes):
+///
+/// | offset | field       | type   |
+/// |--------|-------------|--------|
+/// | 0      | hd          | header |
+/// | 16     | order_id    | u64    |
+/// | 24     | price       | i64    |
+/// | 32     | size        | u32    |
+/// | 36     | flags       | u8     |
+/// | 37     | channel_id  | u8     |
+/// | 38     | action      | i8     |
+/// | 39     | side        | i8     |
+/// | 40     | ts_recv     | u64    |
+/// | 48     | ts_in_delta | i32    |
+/// | 52     | sequence    | u32    |
+impl ByteLayout for MboMsg {
+    const SIZE: usize = 56;
+
+    fn write_le(&self, buffer: &mut Vec<u8>) {
+        self.hd.write_le(buffer);
+        buffer.extend_from_slice(&self.order_id.to_le_bytes());
+        buffer.extend_from_slice(&self.price.to_le_bytes());
+        buffer.extend_from_slice(&self.size.to_le_bytes());
+        buffer.push(self.flags);
+        buffer.push(self.channel_id);
+        buffer.extend_from_slice(&self.action.to_le_bytes());
+        buffer.extend_from_slice(&self.side.to_le_bytes());
+        buffer.extend_from_slice(&self.ts_recv.to_le_bytes());
+        buffer.extend_from_slice(&self.ts_in_delta.to_le_bytes());
+        buffer.extend_from_slice(&self.sequence.to_le_bytes());
+    }
+
+    fn read_le(bytes: &[u8]) -> Result<Self> {
+        let mut reader = LeReader::new::<Self>(bytes)?;
+        let hd = RecordHeader::read_le(reader.rest())?;
+        reader.skip(RecordHeader::SIZE);
+        Ok(MboMsg {
+            hd,
+            order_id: reader.u64(),
+            price: reader.i64(),
+            size: reader.u32(),
+            flags: reader.u8(),
+            channel_id: reader.u8(),
+            action: reader.i8(),
+            side: reader.i8(),
+            ts_recv: reader.u64(),
+            ts_in_delta: reader.i32(),
+            sequence: reader.u32(),
+        })
+    }
+}
+
+impl RecordEnum {
+    /// Appends the record in its explicit little-endian layout.
+    pub fn write_le(&self, buffer: &mut Vec<u8>) {
+        self.to_ref().write_le(buffer)
+    }
+
+    /// Reads a record from its explicit little-endian layout, dispatching on the rtype byte.
+    /// Unknown rtypes are kept as raw bytes in `RecordEnum::Unknown`.
+    pub fn read_le(bytes: &[u8]) -> Result<Self> {
+        let hd = RecordHeader::read_le(bytes)?;
+        let rtype = match hd.rtype() {
+            Ok(rtype) => rtype,
+            Err(_) => return Ok(RecordEnum::Unknown(UnknownMsg::new(bytes)?)),
+        };
+        match rtype {
+            RType::Mbp1 => Ok(RecordEnum::Mbp1(Mbp1Msg::read_le(bytes)?)),
+            RType::Ohlcv => Ok(RecordEnum::Ohlcv(OhlcvMsg::read_le(bytes)?)),
+            RType::Trade => Ok(RecordEnum::Trade(TradeMsg::read_le(bytes)?)),
+            RType::Tbbo => Ok(RecordEnum::Tbbo(Mbp1Msg::read_le(bytes)?)),
+            RType::Bbo => Ok(RecordEnum::Bbo(BboMsg::read_le(bytes)?)),
+            RType::Mbp10 => Ok(RecordEnum::Mbp10(Mbp10Msg::read_le(bytes)?)),
+            RType::Mbo => Ok(RecordEnum::Mbo(MboMsg::read_le(bytes)?)),
+        }
+    }
+}
+
+impl<'a> RecordEnumRef<'a> {
+    /// Appends the record in its explicit little-endian layout.
+    pub fn write_le(&self, buffer: &mut Vec<u8>) {
+        match self {
+            RecordEnumRef::Mbp1(msg) => msg.write_le(buffer),
+            RecordEnumRef::Ohlcv(msg) => msg.write_le(buffer),
+            RecordEnumRef::Trade(msg) => msg.write_le(buffer),
+            RecordEnumRef::Tbbo(msg) => msg.write_le(buffer),
+            RecordEnumRef::Bbo(msg) => msg.write_le(buffer),
+            RecordEnumRef::Mbp10(msg) => msg.write_le(buffer),
+            RecordEnumRef::Mbo(msg) => msg.write_le(buffer),
+            RecordEnumRef::Unknown(rec_ref) => buffer.extend_from_slice(rec_ref.as_ref()),
+        }
+    }
+}
+
+/// Appends the record behind `record` in its explicit little-endian layout. Records with an
+/// unknown rtype are copied through unchanged.
+pub fn write_record_le(record: &RecordRef, buffer: &mut Vec<u8>) -> Result<()> {
+    let record_enum = RecordEnumRef::from_ref(*record)
+        .ok_or(Error::InvalidRecordType("record shorter than its rtype"))?;
+    record_enum.write_le(buffer);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enums::{Action, Side};
+    use std::mem;
+
+    fn mbp1() -> Mbp1Msg {
+        Mbp1Msg {
+            hd: RecordHeader::new::<Mbp1Msg>(7, 1622471124),
+            price: -1000,
+            size: 10,
+            action: Action::Trade.into(),
+            side: Side::Ask.into(),
+            depth: 3,
+            flags: 0x80,
+            ts_recv: 123456789098765,
+            ts_in_delta: -12345,
+            sequence: 123456,
+            discriminator: 9,
+            levels: [BidAskPair {
+                bid_px: 1,
+                ask_px: 2,
+                bid_sz: 3,
+                ask_sz: 4,
+                bid_ct: 5,
+                ask_ct: 6,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_sizes_match_struct_sizes() {
+        assert_eq!(RecordHeader::SIZE, mem::size_of::<RecordHeader>());
+        assert_eq!(BidAskPair::SIZE, mem::size_of::<BidAskPair>());
+        assert_eq!(Mbp1Msg::SIZE, mem::size_of::<Mbp1Msg>());
+        assert_eq!(Mbp10Msg::SIZE, mem::size_of::<Mbp10Msg>());
+        assert_eq!(MboMsg::SIZE, mem::size_of::<MboMsg>());
+        assert_eq!(TradeMsg::SIZE, mem::size_of::<TradeMsg>());
+        assert_eq!(BboMsg::SIZE, mem::size_of::<BboMsg>());
+        assert_eq!(OhlcvMsg::SIZE, mem::size_of::<OhlcvMsg>());
+    }
+
+    #[test]
+    fn test_mbp1_field_offsets() {
+        let record = mbp1();
+
+        // Test
+        let mut bytes = Vec::new();
+        record.write_le(&mut bytes);
+
+        // Validate
+        assert_eq!(bytes.len(), Mbp1Msg::SIZE);
+        assert_eq!(
+            bytes[0] as usize * RecordHeader::LENGTH_MULTIPLIER,
+            Mbp1Msg::SIZE
+        );
+        assert_eq!(bytes[1], RType::Mbp1 as u8);
+        assert_eq!(&bytes[2..4], &[0, 0]);
+        assert_eq!(&bytes[4..8], &7u32.to_le_bytes());
+        assert_eq!(&bytes[8..16], &1622471124u64.to_le_bytes());
+        assert_eq!(&bytes[16..24], &(-1000i64).to_le_bytes());
+        assert_eq!(&bytes[24..28], &10u32.to_le_bytes());
+        assert_eq!(bytes[28], b'T');
+        assert_eq!(bytes[29], b'A');
+        assert_eq!(bytes[30], 3);
+        assert_eq!(bytes[31], 0x80);
+        assert_eq!(&bytes[32..40], &123456789098765u64.to_le_bytes());
+        assert_eq!(&bytes[40..44], &(-12345i32).to_le_bytes());
+        assert_eq!(&bytes[44..48], &123456u32.to_le_bytes());
+        assert_eq!(&bytes[48..52], &9u32.to_le_bytes());
+        assert_eq!(&bytes[52..56], &[0; 4]);
+        assert_eq!(&bytes[56..64], &1i64.to_le_bytes());
+        assert_eq!(&bytes[64..72], &2i64.to_le_bytes());
+        assert_eq!(&bytes[72..76], &3u32.to_le_bytes());
+        assert_eq!(&bytes[84..88], &6u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_bbo_field_offsets() {
+        let record = BboMsg {
+            hd: RecordHeader::new::<BboMsg>(1, 1725734014000000000),
+            price: 1000,
+            size: 10,
+            side: Side::Bid.into(),
+            flags: 1,
+            ts_recv: 1725734014000000001,
+            sequence: 42,
+            levels: [BidAskPair {
+                bid_px: 1,
+                ask_px: 2,
+                bid_sz: 2,
+                ask_sz: 2,
+                bid_ct: 1,
+                ask_ct: 3,
+            }],
+        };
+
+        // Test
+        let mut bytes = Vec::new();
+        record.write_le(&mut bytes);
+
+        // Validate
+        assert_eq!(bytes.len(), BboMsg::SIZE);
+        assert_eq!(bytes[28], b'B');
+        assert_eq!(bytes[29], 1);
+        assert_eq!(&bytes[30..32], &[0, 0]);
+        assert_eq!(&bytes[32..40], &1725734014000000001u64.to_le_bytes());
+        assert_eq!(&bytes[40..44], &42u32.to_le_bytes());
+        assert_eq!(&bytes[48..56], &1i64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_round_trip_all_records() -> Result<()> {
+        let levels = std::array::from_fn(|i| BidAskPair {
+            bid_px: 100 - i as i64,
+            ask_px: 101 + i as i64,
+            bid_sz: i as u32,
+            ask_sz: 2 * i as u32,
+            bid_ct: 3 * i as u32,
+            ask_ct: 4 * i as u32,
+        });
+        let records = vec![
+            RecordEnum::Mbp1(mbp1()),
+            RecordEnum::Mbp10(Mbp10Msg {
+                hd: RecordHeader::new::<Mbp10Msg>(3, 1622471124),
+                price: 100,
+                size: 5,
+                action: Action::Add.into(),
+                side: Side::Bid.into(),
+                depth: 9,
+                flags: 0,
+                ts_recv: 1622471125,
+                ts_in_delta: 12,
+                sequence: 7,
+                discriminator: 1,
+                levels,
+            }),
+            RecordEnum::Trade(TradeMsg {
+                hd: RecordHeader::new::<TradeMsg>(1, 1725734014000000000),
+                price: 1000,
+                size: 10,
+                action: Action::Trade.into(),
+                side: Side::Bid.into(),
+                depth: 0,
+                flags: 0,
+                ts_recv: 1725734014000000000,
+                ts_in_delta: 12345,
+                sequence: 123456,
+            }),
+            RecordEnum::Mbo(MboMsg {
+                hd: RecordHeader::new::<MboMsg>(4, 1622471124),
+                order_id: 987654321,
+                price: -250,
+                size: 3,
+                flags: 0x82,
+                channel_id: 5,
+                action: Action::Cancel.into(),
+                side: Side::Ask.into(),
+                ts_recv: 1622471125,
+                ts_in_delta: -7,
+                sequence: 11,
+            }),
+            RecordEnum::Ohlcv(OhlcvMsg {
+                hd: RecordHeader::new::<OhlcvMsg>(2, 162222293489348),
+                open: 909,
+                high: 11991,
+                low: 800,
+                close: 999,
+                volume: 123456765432,
+            }),
+        ];
+
+        for record in records {
+            // Test
+            let mut bytes = Vec::new();
+            record.write_le(&mut bytes);
+            let decoded = RecordEnum::read_le(&bytes)?;
+
+            // Validate
+            assert_eq!(decoded, record);
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(target_endian = "little")]
+    fn test_matches_in_memory_layout_on_little_endian() -> Result<()> {
+        let record = mbp1();
+
+        // Test
+        let decoded = Mbp1Msg::read_le(record.as_ref())?;
+
+        // Validate
+        assert_eq!(decoded, record);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_short_buffer() {
+        let mut bytes = Vec::new();
+        mbp1().write_le(&mut bytes);
+
+        // Test
+        let result = Mbp1Msg::read_le(&bytes[..40]);
+
+        // Validate
+        assert!(matches!(result, Err(Error::Decode(_))));
+    }
+}
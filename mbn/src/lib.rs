@@ -1,16 +1,39 @@
-pub const METADATA_LENGTH: usize = 100;
+/// Signature at the start of every MBN file.
+pub const MBN_MAGIC: &[u8; 3] = b"MBN";
+/// Current version of the MBN file layout, written after `MBN_MAGIC`. Version 2 pads the
+/// metadata block so records start 8 byte aligned, version 3 adds the `FileLayout` byte after
+/// the version. Files of earlier versions are still read.
+pub const MBN_VERSION: u8 = 3;
+/// Size in bytes of the little-endian u32 length prefix written before the metadata block.
+pub const METADATA_LENGTH_PREFIX: usize = 4;
 pub const PRICE_SCALE: i64 = 1_000_000_000;
 pub mod backtest;
+pub mod bars;
+pub mod bbo;
+pub mod block;
+pub mod book;
+pub mod checksum;
+pub mod codec;
+pub mod columnar;
+pub mod compression;
 pub mod decode;
 pub mod decode_iterator;
 pub mod encode;
 pub mod enums;
 pub mod error;
+pub mod index;
+pub mod layout;
 pub mod live;
+pub mod merge;
 pub mod metadata;
+pub mod mmap;
 pub mod record_enum;
 pub mod record_ref;
 pub mod records;
+pub mod replay;
+pub mod rolling;
+pub mod shm;
+pub mod subscriber;
 pub mod symbols;
 pub mod utils;
 
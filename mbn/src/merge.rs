@@ -0,0 +1,502 @@
+//! Time-ordered k-way merge of several MBN streams.
+use crate::compression::{AsyncFileReader, FileReader};
+use crate::decode::{AsyncDecoder, Decoder};
+use crate::error::{Error, Result};
+use crate::metadata::Metadata;
+use crate::record_enum::RecordEnum;
+use crate::record_ref::RecordRef;
+use crate::records::Record;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::io::Read;
+use std::path::Path;
+use tokio::io::AsyncBufRead;
+
+/// How records with the same `ts_event` are ordered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    /// In the order the inputs were given.
+    #[default]
+    Input,
+    /// By `ts_recv`, then `sequence`, then input order. Records without these fields (OHLCV and
+    /// unknown records) use `ts_event` and 0.
+    Sequence,
+}
+
+#[derive(Debug)]
+struct Head {
+    key: (u64, u64, u32, usize),
+    record: RecordEnum,
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for Head {}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+/// Holds the next record of every input, smallest key first.
+#[derive(Debug)]
+struct MergeQueue {
+    heap: BinaryHeap<Reverse<Head>>,
+    tie_break: TieBreak,
+    /// Inputs whose next record has to be read before the queue can be popped.
+    pending: Vec<usize>,
+}
+
+impl MergeQueue {
+    fn new(inputs: usize) -> Self {
+        Self {
+            heap: BinaryHeap::with_capacity(inputs),
+            tie_break: TieBreak::default(),
+            pending: (0..inputs).rev().collect(),
+        }
+    }
+
+    fn push(&mut self, input: usize, record: RecordEnum) {
+        let ts_event = record.header().ts_event;
+        let (ts_recv, sequence) = match self.tie_break {
+            TieBreak::Input => (0, 0),
+            TieBreak::Sequence => match &record {
+                RecordEnum::Mbp1(msg) | RecordEnum::Tbbo(msg) => (msg.ts_recv, msg.sequence),
+                RecordEnum::Trade(msg) => (msg.ts_recv, msg.sequence),
+                RecordEnum::Bbo(msg) => (msg.ts_recv, msg.sequence),
+                RecordEnum::Mbp10(msg) => (msg.ts_recv, msg.sequence),
+                RecordEnum::Mbo(msg) => (msg.ts_recv, msg.sequence),
+                RecordEnum::Ohlcv(_) | RecordEnum::Unknown(_) => (ts_event, 0),
+            },
+        };
+        self.heap.push(Reverse(Head {
+            key: (ts_event, ts_recv, sequence, input),
+            record,
+        }));
+    }
+
+    /// Removes the smallest record, marking its input to be read next.
+    fn pop(&mut self) -> Option<RecordEnum> {
+        let Reverse(head) = self.heap.pop()?;
+        self.pending.push(head.key.3);
+        Some(head.record)
+    }
+}
+
+/// Merges the metadata of all inputs, covering the union of their time ranges and mappings.
+///
+/// Returns `Ok(None)` if no input has metadata, and an error if the schemas differ or an
+/// instrument id maps to different tickers.
+pub fn merge_metadata<'a, I>(metadata: I) -> Result<Option<Metadata>>
+where
+    I: IntoIterator<Item = &'a Metadata>,
+{
+    let mut merged: Option<Metadata> = None;
+    for other in metadata {
+        match merged.as_mut() {
+            None => merged = Some(other.clone()),
+            Some(merged) => {
+                if merged.schema != other.schema {
+                    return Err(Error::Conversion(format!(
+                        "Cannot merge schema {} with schema {}",
+                        merged.schema, other.schema
+                    )));
+                }
+                merged.start = merged.start.min(other.start);
+                merged.end = merged.end.max(other.end);
+                merged.mappings.try_merge(&other.mappings)?;
+            }
+        }
+    }
+    Ok(merged)
+}
+
+fn to_owned(record_ref: RecordRef) -> std::io::Result<RecordEnum> {
+    RecordEnum::read_le(record_ref.as_ref()).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Failed to convert record reference to RecordEnum",
+        )
+    })
+}
+
+/// Reads several decoders as one stream ordered by `ts_event`.
+///
+/// Records of a single input keep their order, so each input is expected to be sorted.
+pub struct MergeDecoder<R> {
+    pub metadata: Option<Metadata>,
+    decoders: Vec<Decoder<R>>,
+    queue: MergeQueue,
+}
+
+impl<R: Read> MergeDecoder<R> {
+    pub fn new(decoders: Vec<Decoder<R>>) -> Result<Self> {
+        let metadata = merge_metadata(decoders.iter().filter_map(|d| d.metadata.as_ref()))?;
+        Ok(Self {
+            metadata,
+            queue: MergeQueue::new(decoders.len()),
+            decoders,
+        })
+    }
+
+    pub fn metadata(&mut self) -> Option<Metadata> {
+        self.metadata.clone()
+    }
+
+    /// Sets how records with the same `ts_event` are ordered, `TieBreak::Input` by default.
+    pub fn with_tie_break(mut self, tie_break: TieBreak) -> Self {
+        self.queue.tie_break = tie_break;
+        self
+    }
+
+    pub fn decode(&mut self) -> Result<Vec<RecordEnum>> {
+        let mut records = Vec::new();
+        for record in self {
+            records.push(record?);
+        }
+        Ok(records)
+    }
+
+    /// Accepts PathBuf, Path and str for each file path.
+    pub fn from_files<P: AsRef<Path>>(file_paths: &[P]) -> Result<MergeDecoder<FileReader>> {
+        let decoders = file_paths
+            .iter()
+            .map(Decoder::<FileReader>::from_file)
+            .collect::<Result<Vec<_>>>()?;
+        MergeDecoder::new(decoders)
+    }
+}
+
+impl<R: Read> Iterator for MergeDecoder<R> {
+    type Item = std::io::Result<RecordEnum>;
+
+    /// An input that fails is dropped from the merge, the other inputs keep going.
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(input) = self.queue.pending.pop() {
+            let record = match self.decoders[input].decode_ref() {
+                Ok(Some(record_ref)) => to_owned(record_ref).map(Some),
+                Ok(None) => Ok(None),
+                Err(e) => Err(e),
+            };
+            match record {
+                Ok(Some(record)) => self.queue.push(input, record),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        self.queue.pop().map(Ok)
+    }
+}
+
+/// Async counterpart of `MergeDecoder`.
+pub struct AsyncMergeDecoder<R> {
+    pub metadata: Option<Metadata>,
+    decoders: Vec<AsyncDecoder<R>>,
+    queue: MergeQueue,
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncMergeDecoder<R> {
+    pub fn new(decoders: Vec<AsyncDecoder<R>>) -> Result<Self> {
+        let metadata = merge_metadata(decoders.iter().filter_map(|d| d.metadata.as_ref()))?;
+        Ok(Self {
+            metadata,
+            queue: MergeQueue::new(decoders.len()),
+            decoders,
+        })
+    }
+
+    pub fn metadata(&mut self) -> Option<Metadata> {
+        self.metadata.clone()
+    }
+
+    /// Sets how records with the same `ts_event` are ordered, `TieBreak::Input` by default.
+    pub fn with_tie_break(mut self, tie_break: TieBreak) -> Self {
+        self.queue.tie_break = tie_break;
+        self
+    }
+
+    /// Returns the next record across all inputs, or `None` once every input is exhausted.
+    ///
+    /// Cancellation safe: an input is only marked as read once its record arrived, so dropping
+    /// the future, e.g. in `tokio::select!`, loses no records. An input that fails is dropped
+    /// from the merge.
+    pub async fn decode_next(&mut self) -> std::io::Result<Option<RecordEnum>> {
+        while let Some(&input) = self.queue.pending.last() {
+            let record = self.decoders[input].decode_ref().await;
+            self.queue.pending.pop();
+            if let Some(record) = record?.map(to_owned).transpose()? {
+                self.queue.push(input, record);
+            }
+        }
+        Ok(self.queue.pop())
+    }
+
+    pub async fn decode(&mut self) -> Result<Vec<RecordEnum>> {
+        let mut records = Vec::new();
+        while let Some(record) = self.decode_next().await? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// Accepts PathBuf, Path and str for each file path.
+    pub async fn from_files<P: AsRef<Path>>(
+        file_paths: &[P],
+    ) -> Result<AsyncMergeDecoder<AsyncFileReader>> {
+        let mut decoders = Vec::with_capacity(file_paths.len());
+        for file_path in file_paths {
+            decoders.push(AsyncDecoder::<AsyncFileReader>::from_file(file_path).await?);
+        }
+        AsyncMergeDecoder::new(decoders)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::CombinedEncoder;
+    use crate::enums::Schema;
+    use crate::records::{RecordHeader, TradeMsg};
+    use crate::symbols::SymbolMap;
+    use std::io::Cursor;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+
+    fn trade(instrument_id: u32, ts_event: u64, ts_recv: u64) -> TradeMsg {
+        TradeMsg {
+            hd: RecordHeader::new::<TradeMsg>(instrument_id, ts_event),
+            price: 100,
+            size: 1,
+            action: 0,
+            side: 0,
+            depth: 0,
+            flags: 0,
+            ts_recv,
+            ts_in_delta: 0,
+            sequence: 0,
+        }
+    }
+
+    fn encode(ticker: &str, instrument_id: u32, trades: &[TradeMsg]) -> Vec<u8> {
+        let mut mappings = SymbolMap::new();
+        mappings.add_instrument(ticker, instrument_id);
+        let metadata = Metadata::new(
+            Schema::Trade,
+            trades.first().map_or(0, |t| t.hd.ts_event),
+            trades.last().map_or(0, |t| t.hd.ts_event),
+            mappings,
+        );
+        let records: Vec<RecordRef> = trades.iter().map(|t| t.into()).collect();
+
+        let mut buffer = Vec::new();
+        CombinedEncoder::new(&mut buffer)
+            .encode(&metadata, &records)
+            .expect("Error on encoding");
+        buffer
+    }
+
+    fn ordered(records: &[RecordEnum]) -> Vec<(u32, u64)> {
+        records
+            .iter()
+            .map(|r| (r.header().instrument_id, r.header().ts_event))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_orders_by_ts_event() -> anyhow::Result<()> {
+        let a = encode("AAPL", 1, &[trade(1, 1, 1), trade(1, 4, 4), trade(1, 5, 5)]);
+        let b = encode("TSLA", 2, &[trade(2, 2, 2), trade(2, 3, 3), trade(2, 6, 6)]);
+        let c = encode("MSFT", 3, &[]);
+        let decoders = vec![
+            Decoder::new(Cursor::new(a))?,
+            Decoder::new(Cursor::new(b))?,
+            Decoder::new(Cursor::new(c))?,
+        ];
+
+        // Test
+        let mut merged = MergeDecoder::new(decoders)?;
+        let records = merged.decode()?;
+
+        // Validate
+        assert_eq!(
+            ordered(&records),
+            vec![(1, 1), (2, 2), (2, 3), (1, 4), (1, 5), (2, 6)]
+        );
+        let metadata = merged.metadata().unwrap();
+        assert_eq!((metadata.start, metadata.end), (0, 6));
+        assert_eq!(metadata.mappings.map.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_tie_break() -> anyhow::Result<()> {
+        let a = || encode("AAPL", 1, &[trade(1, 5, 9)]);
+        let b = || encode("TSLA", 2, &[trade(2, 5, 7)]);
+        let decoders = || -> anyhow::Result<_> {
+            Ok(vec![
+                Decoder::new(Cursor::new(a()))?,
+                Decoder::new(Cursor::new(b()))?,
+            ])
+        };
+
+        // Test
+        let by_input = MergeDecoder::new(decoders()?)?.decode()?;
+        let by_sequence = MergeDecoder::new(decoders()?)?
+            .with_tie_break(TieBreak::Sequence)
+            .decode()?;
+
+        // Validate
+        assert_eq!(ordered(&by_input), vec![(1, 5), (2, 5)]);
+        assert_eq!(ordered(&by_sequence), vec![(2, 5), (1, 5)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_ticker_collision() -> anyhow::Result<()> {
+        let a = encode("AAPL", 1, &[trade(1, 1, 1)]);
+        let b = encode("TSLA", 1, &[trade(1, 2, 2)]);
+        let decoders = vec![Decoder::new(Cursor::new(a))?, Decoder::new(Cursor::new(b))?];
+
+        // Test
+        let result = MergeDecoder::new(decoders);
+
+        // Validate
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_merge() -> anyhow::Result<()> {
+        let a = encode("AAPL", 1, &[trade(1, 1, 1), trade(1, 3, 3)]);
+        let b = encode("TSLA", 2, &[trade(2, 2, 2)]);
+        let decoders = vec![
+            AsyncDecoder::new(Cursor::new(a)).await?,
+            AsyncDecoder::new(Cursor::new(b)).await?,
+        ];
+
+        // Test
+        let records = AsyncMergeDecoder::new(decoders)?.decode().await?;
+
+        // Validate
+        assert_eq!(ordered(&records), vec![(1, 1), (2, 2), (1, 3)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_drops_input_after_error() -> anyhow::Result<()> {
+        let mut a = encode("AAPL", 1, &[trade(1, 1, 1)]);
+        let mut corrupt = encode("AAPL", 1, &[trade(1, 2, 2)]);
+        let record_len = std::mem::size_of::<TradeMsg>();
+        let mut corrupt = corrupt.split_off(corrupt.len() - record_len);
+        corrupt[1] = 0xEE;
+        a.extend_from_slice(&corrupt);
+        let tail = encode("AAPL", 1, &[trade(1, 4, 4)]);
+        a.extend_from_slice(&tail[tail.len() - record_len..]);
+        let b = encode("TSLA", 2, &[trade(2, 3, 3)]);
+        let decoders = vec![Decoder::new(Cursor::new(a))?, Decoder::new(Cursor::new(b))?];
+
+        // Test
+        let results: Vec<_> = MergeDecoder::new(decoders)?.collect();
+
+        // Validate
+        assert_eq!(results.iter().filter(|r| r.is_err()).count(), 1);
+        let records: Vec<RecordEnum> = results.into_iter().filter_map(|r| r.ok()).collect();
+        assert_eq!(ordered(&records), vec![(1, 1), (2, 3)]);
+        Ok(())
+    }
+
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("disk gone"))
+        }
+    }
+
+    #[test]
+    fn test_merge_failing_reader_terminates() -> anyhow::Result<()> {
+        let a = encode("AAPL", 1, &[trade(1, 1, 1)]);
+        let b = encode("TSLA", 2, &[trade(2, 2, 2), trade(2, 3, 3)]);
+        let decoders = vec![
+            Decoder::new(Cursor::new(a).chain(FailingReader))?,
+            Decoder::new(Cursor::new(b).chain(FailingReader))?,
+        ];
+
+        // Test
+        let results: Vec<_> = MergeDecoder::new(decoders)?.take(10).collect();
+
+        // Validate
+        assert_eq!(results.len(), 5);
+        assert_eq!(results.iter().filter(|r| r.is_err()).count(), 2);
+        let records: Vec<RecordEnum> = results.into_iter().filter_map(|r| r.ok()).collect();
+        assert_eq!(ordered(&records), vec![(1, 1), (2, 2), (2, 3)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_merge_drops_input_after_error() -> anyhow::Result<()> {
+        let mut a = encode("AAPL", 1, &[trade(1, 1, 1)]);
+        // A zero length byte is a record shorter than its header.
+        a.push(0);
+        let b = encode("TSLA", 2, &[trade(2, 2, 2)]);
+        let decoders = vec![
+            AsyncDecoder::new(Cursor::new(a)).await?,
+            AsyncDecoder::new(Cursor::new(b)).await?,
+        ];
+        let mut merged = AsyncMergeDecoder::new(decoders)?;
+
+        // Test
+        let mut results = Vec::new();
+        for _ in 0..5 {
+            results.push(merged.decode_next().await);
+        }
+
+        // Validate
+        assert_eq!(results.iter().filter(|r| r.is_err()).count(), 1);
+        let records: Vec<RecordEnum> = results
+            .into_iter()
+            .filter_map(|r| r.ok().flatten())
+            .collect();
+        assert_eq!(ordered(&records), vec![(1, 1), (2, 2)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_async_merge_cancellation_safe() -> anyhow::Result<()> {
+        let a = encode("AAPL", 1, &[trade(1, 1, 1), trade(1, 3, 3)]);
+        let b = encode("TSLA", 2, &[trade(2, 2, 2)]);
+        let (mut writer, reader) = tokio::io::duplex(1024);
+        let (mut b_writer, b_reader) = tokio::io::duplex(1024);
+        b_writer.write_all(&b).await?;
+        drop(b_writer);
+        let split = a.len() - std::mem::size_of::<TradeMsg>() * 2;
+        writer.write_all(&a[..split]).await?;
+        let decoders = vec![
+            AsyncDecoder::new(tokio::io::BufReader::new(reader)).await?,
+            AsyncDecoder::new(tokio::io::BufReader::new(b_reader)).await?,
+        ];
+        let mut merged = AsyncMergeDecoder::new(decoders)?;
+
+        // Test
+        let timed_out = tokio::time::timeout(Duration::from_millis(10), merged.decode_next()).await;
+        writer.write_all(&a[split..]).await?;
+        drop(writer);
+        let records = merged.decode().await?;
+
+        // Validate
+        assert!(timed_out.is_err());
+        assert_eq!(ordered(&records), vec![(1, 1), (2, 2), (1, 3)]);
+        Ok(())
+    }
+}
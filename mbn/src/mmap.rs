@@ -0,0 +1,388 @@
+//! Memory-mapped reader handing out records borrowed from the mapped file.
+use crate::decode::MetadataDecoder;
+use crate::error::{Error, Result};
+use crate::metadata::Metadata;
+use crate::record_enum::RecordEnumRef;
+use crate::record_ref::{AlignedBuffer, RecordRef};
+use crate::records::RecordHeader;
+use memmap2::Mmap;
+use std::fs::File;
+use std::mem;
+use std::path::Path;
+
+enum Storage {
+    Mapped(Mmap),
+    /// Copy of the records, each starting aligned, when some don't start at an aligned offset
+    /// in the file.
+    Copied(AlignedBuffer),
+}
+
+/// Random-access reader over a memory-mapped MBN file.
+///
+/// Records are validated once when the file is opened, then borrowed from the mapping without
+/// copying. `RecordRef` reinterprets the bytes in memory, which matches the explicit `layout`
+/// on little-endian hosts only. Records have to start on an 8 byte boundary to be borrowed,
+/// which `MetadataEncoder` guarantees by padding the metadata block. Files where a record
+/// starts misaligned, after an unpadded metadata block or an unknown record whose length isn't
+/// a multiple of 8, are copied into an aligned buffer once and `is_zero_copy` returns `false`.
+///
+/// The reader is `Send + Sync` and can be shared across threads, e.g. behind an `Arc`.
+pub struct MmapDecoder {
+    pub metadata: Option<Metadata>,
+    storage: Storage,
+    data_offset: usize,
+    /// Offset of each record from `data_offset`.
+    offsets: Vec<usize>,
+}
+
+impl MmapDecoder {
+    /// Maps the file at `file_path`.
+    ///
+    /// The file must not be truncated or modified while mapped, as with any memory map.
+    pub fn from_file<P: AsRef<Path>>(file_path: P) -> Result<Self> {
+        let file = File::open(file_path.as_ref())?;
+        if file.metadata()?.len() == 0 {
+            return Ok(Self {
+                metadata: None,
+                storage: Storage::Copied(AlignedBuffer::new()),
+                data_offset: 0,
+                offsets: Vec::new(),
+            });
+        }
+        // Safety: the map is read-only; the caller keeps the file unchanged while it is mapped.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Self::new(mmap)
+    }
+
+    pub fn new(mmap: Mmap) -> Result<Self> {
+        let mut rest: &[u8] = &mmap;
+        let metadata = MetadataDecoder::new(&mut rest).decode()?;
+        let data_offset = mmap.len() - rest.len();
+
+        let file_offsets = record_offsets(rest)?;
+        let aligned = (rest.as_ptr() as usize).is_multiple_of(ALIGNMENT)
+            && file_offsets
+                .iter()
+                .all(|offset| offset.is_multiple_of(ALIGNMENT));
+        let (storage, offsets) = if aligned {
+            (Storage::Mapped(mmap), file_offsets.clone())
+        } else {
+            let (buffer, offsets) = copy_aligned(rest, &file_offsets);
+            (Storage::Copied(buffer), offsets)
+        };
+
+        let decoder = Self {
+            metadata,
+            storage,
+            data_offset,
+            offsets,
+        };
+        for (&offset, &file_offset) in decoder.offsets.iter().zip(&file_offsets) {
+            RecordRef::try_new_opaque(&decoder.records()[offset..]).map_err(|e| {
+                Error::Decode(format!(
+                    "Invalid record at byte {} of records: {}",
+                    file_offset, e
+                ))
+            })?;
+        }
+        Ok(decoder)
+    }
+
+    pub fn metadata(&self) -> Option<Metadata> {
+        self.metadata.clone()
+    }
+
+    /// Whether records are borrowed from the mapping rather than from an aligned copy.
+    pub fn is_zero_copy(&self) -> bool {
+        matches!(self.storage, Storage::Mapped(_))
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Returns the record at `index`, including records with an unknown rtype.
+    pub fn get(&self, index: usize) -> Option<RecordRef<'_>> {
+        let offset = *self.offsets.get(index)?;
+        // Safety: every offset was validated by `RecordRef::try_new_opaque` on open and the
+        // storage is immutable.
+        Some(unsafe { RecordRef::new(&self.records()[offset..]) })
+    }
+
+    /// Returns the record at `index` as a typed reference, `None` if out of range or unknown.
+    pub fn get_enum(&self, index: usize) -> Option<RecordEnumRef<'_>> {
+        self.get(index).and_then(RecordEnumRef::from_ref)
+    }
+
+    pub fn iter(&self) -> MmapIter<'_> {
+        MmapIter {
+            decoder: self,
+            index: 0,
+        }
+    }
+
+    fn records(&self) -> &[u8] {
+        match &self.storage {
+            Storage::Mapped(mmap) => &mmap[self.data_offset..],
+            Storage::Copied(buffer) => buffer.as_slice(),
+        }
+    }
+}
+
+/// Alignment a record needs to be borrowed as a `RecordRef`.
+const ALIGNMENT: usize = mem::align_of::<RecordHeader>();
+
+/// Returns the offset of every record in `records`, following the length in each header.
+fn record_offsets(records: &[u8]) -> Result<Vec<usize>> {
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+    while offset < records.len() {
+        let length = records[offset] as usize * RecordHeader::LENGTH_MULTIPLIER;
+        if length < mem::size_of::<RecordHeader>() || length > records.len() - offset {
+            return Err(Error::Decode(format!(
+                "Invalid record at byte {} of records: length {} does not fit the {} bytes left",
+                offset,
+                length,
+                records.len() - offset
+            )));
+        }
+        offsets.push(offset);
+        offset += length;
+    }
+    Ok(offsets)
+}
+
+/// Copies the records at `offsets` into an aligned buffer, each starting on an `ALIGNMENT`
+/// boundary, and returns the buffer with their offsets in it.
+fn copy_aligned(records: &[u8], offsets: &[usize]) -> (AlignedBuffer, Vec<usize>) {
+    let ends = offsets.iter().skip(1).copied().chain([records.len()]);
+    let spans: Vec<(usize, usize)> = offsets.iter().copied().zip(ends).collect();
+    let mut aligned_offsets = Vec::with_capacity(spans.len());
+    let mut len = 0;
+    for (start, end) in &spans {
+        aligned_offsets.push(len);
+        len += (end - start).next_multiple_of(ALIGNMENT);
+    }
+
+    let mut buffer = AlignedBuffer::new();
+    buffer.resize(len);
+    let bytes = buffer.as_mut_slice();
+    for ((start, end), &offset) in spans.into_iter().zip(&aligned_offsets) {
+        bytes[offset..offset + end - start].copy_from_slice(&records[start..end]);
+    }
+    (buffer, aligned_offsets)
+}
+
+/// Iterator over the records of an `MmapDecoder`, returned by `MmapDecoder::iter`.
+pub struct MmapIter<'a> {
+    decoder: &'a MmapDecoder,
+    index: usize,
+}
+
+impl<'a> Iterator for MmapIter<'a> {
+    type Item = RecordRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.decoder.get(self.index)?;
+        self.index += 1;
+        Some(record)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.decoder.len() - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for MmapIter<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::{CombinedEncoder, RecordEncoder};
+    use crate::enums::Schema;
+    use crate::records::OhlcvMsg;
+    use crate::symbols::SymbolMap;
+    use serial_test::serial;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    fn write_file(file: &Path, ticker: &str) -> Vec<OhlcvMsg> {
+        let mut mappings = SymbolMap::new();
+        mappings.add_instrument(ticker, 1);
+        let metadata = Metadata::new(Schema::Ohlcv1S, 0, 10, mappings);
+        let msgs: Vec<OhlcvMsg> = (0..10)
+            .map(|i| OhlcvMsg {
+                hd: RecordHeader::new::<OhlcvMsg>(1, i),
+                open: 100,
+                high: 200,
+                low: 50,
+                close: 150,
+                volume: i,
+            })
+            .collect();
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+
+        let mut buffer = Vec::new();
+        let mut encoder = CombinedEncoder::new(&mut buffer);
+        encoder.encode(&metadata, &records).unwrap();
+        encoder.write_to_file(file, false).unwrap();
+        msgs
+    }
+
+    #[test]
+    #[serial]
+    fn test_mmap_random_access() -> anyhow::Result<()> {
+        // Tickers of different lengths give metadata blocks of different unpadded lengths.
+        for ticker in ["AAP", "AAPL", "AAPL1", "AAPL12"] {
+            let file = PathBuf::from("tests/mmap.bin");
+            let msgs = write_file(&file, ticker);
+
+            // Test
+            let decoder = MmapDecoder::from_file(&file)?;
+
+            // Validate
+            assert_eq!(decoder.len(), 10);
+            assert!(decoder.is_zero_copy());
+            assert_eq!(decoder.metadata().unwrap().mappings.map[&1], ticker);
+            assert_eq!(decoder.get(3).unwrap().get::<OhlcvMsg>(), Some(&msgs[3]));
+            assert!(matches!(
+                decoder.get_enum(9),
+                Some(RecordEnumRef::Ohlcv(msg)) if msg == &msgs[9]
+            ));
+            assert!(decoder.get(10).is_none());
+            assert_eq!(decoder.iter().count(), 10);
+
+            // Cleanup
+            std::fs::remove_file(&file)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_mmap_unpadded_metadata() -> anyhow::Result<()> {
+        let file = PathBuf::from("tests/mmap_unpadded.bin");
+        let mut mappings = SymbolMap::new();
+        mappings.add_instrument("AAPL", 1);
+        let serialized = Metadata::new(Schema::Ohlcv1S, 0, 10, mappings).serialize();
+        assert!(!serialized.len().is_multiple_of(8));
+        let msg = OhlcvMsg {
+            hd: RecordHeader::new::<OhlcvMsg>(1, 1),
+            open: 100,
+            high: 200,
+            low: 50,
+            close: 150,
+            volume: 1,
+        };
+
+        // Version 1 metadata block written without padding, as by earlier encoders.
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(crate::MBN_MAGIC);
+        buffer.push(1);
+        buffer.extend_from_slice(&(serialized.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(&serialized);
+        RecordEncoder::new(&mut buffer).encode_record(&(&msg).into())?;
+        std::fs::write(&file, &buffer)?;
+
+        // Test
+        let decoder = MmapDecoder::from_file(&file)?;
+
+        // Validate
+        assert!(!decoder.is_zero_copy());
+        assert_eq!(decoder.len(), 1);
+        assert_eq!(decoder.get(0).unwrap().get::<OhlcvMsg>(), Some(&msg));
+
+        // Cleanup
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_mmap_unknown_record_unaligned_length() -> anyhow::Result<()> {
+        let file = PathBuf::from("tests/mmap_unknown.bin");
+        let msgs = write_file(&file, "AAPL");
+        // Unknown rtype of 5 words, leaving the records after it 4 bytes off alignment.
+        let mut unknown = vec![0u8; 5 * RecordHeader::LENGTH_MULTIPLIER];
+        unknown[0] = 5;
+        unknown[1] = 0xEE;
+        unknown[16] = 42;
+        let mut buffer = std::fs::read(&file)?;
+        let split = buffer.len() - 5 * mem::size_of::<OhlcvMsg>();
+        let tail = buffer.split_off(split);
+        buffer.extend_from_slice(&unknown);
+        buffer.extend_from_slice(&tail);
+        std::fs::write(&file, &buffer)?;
+
+        // Test
+        let decoder = MmapDecoder::from_file(&file)?;
+
+        // Validate
+        assert!(!decoder.is_zero_copy());
+        assert_eq!(decoder.len(), 11);
+        assert_eq!(decoder.get(4).unwrap().get::<OhlcvMsg>(), Some(&msgs[4]));
+        let record = decoder.get(5).unwrap();
+        assert_eq!(record.header().rtype, 0xEE);
+        assert_eq!(record.as_ref(), &unknown[..]);
+        assert_eq!(decoder.get(6).unwrap().get::<OhlcvMsg>(), Some(&msgs[5]));
+        assert_eq!(decoder.get(10).unwrap().get::<OhlcvMsg>(), Some(&msgs[9]));
+
+        // Cleanup
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_mmap_shared_across_threads() -> anyhow::Result<()> {
+        let file = PathBuf::from("tests/mmap_threads.bin");
+        write_file(&file, "AAPL");
+        let decoder = Arc::new(MmapDecoder::from_file(&file)?);
+
+        // Test
+        let volumes: Vec<u64> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..2)
+                .map(|half| {
+                    let decoder = Arc::clone(&decoder);
+                    scope.spawn(move || {
+                        (half * 5..half * 5 + 5)
+                            .map(|i| decoder.get(i).unwrap().get::<OhlcvMsg>().unwrap().volume)
+                            .sum::<u64>()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        // Validate
+        assert_eq!(volumes, vec![10, 35]);
+
+        // Cleanup
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_mmap_truncated_record() -> anyhow::Result<()> {
+        let file = PathBuf::from("tests/mmap_truncated.bin");
+        write_file(&file, "AAPL");
+        let bytes = std::fs::read(&file)?;
+        std::fs::write(&file, &bytes[..bytes.len() - 4])?;
+
+        // Test
+        let result = MmapDecoder::from_file(&file);
+
+        // Validate
+        assert!(result.is_err());
+
+        // Cleanup
+        std::fs::remove_file(&file)?;
+        Ok(())
+    }
+}
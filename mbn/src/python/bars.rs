@@ -0,0 +1,65 @@
+use crate::bars::{EmptyBars, OhlcvBuilder, Session};
+use crate::enums::Schema;
+use crate::record_enum::RecordEnumRef;
+use crate::records::{Mbp1Msg, OhlcvMsg, TradeMsg};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+#[pymethods]
+impl OhlcvBuilder {
+    #[new]
+    #[pyo3(signature = (schema, fill_empty=false, session_start=None, session_end=None))]
+    fn py_new(
+        schema: Schema,
+        fill_empty: bool,
+        session_start: Option<u64>,
+        session_end: Option<u64>,
+    ) -> PyResult<Self> {
+        let mut builder =
+            OhlcvBuilder::new(schema).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        if fill_empty {
+            builder = builder.with_empty_bars(EmptyBars::Fill);
+        }
+        match (session_start, session_end) {
+            (Some(start), Some(end)) => {
+                let session =
+                    Session::new(start, end).map_err(|e| PyValueError::new_err(e.to_string()))?;
+                builder = builder.with_session(session);
+            }
+            (None, None) => {}
+            _ => {
+                return Err(PyValueError::new_err(
+                    "session_start and session_end must be set together",
+                ))
+            }
+        }
+        Ok(builder)
+    }
+
+    /// Adds a record and returns the bars it closed, records without trades are ignored.
+    #[pyo3(name = "update")]
+    fn py_update(&mut self, record: &Bound<PyAny>) -> Vec<OhlcvMsg> {
+        if let Ok(trade) = record.extract::<TradeMsg>() {
+            self.update(RecordEnumRef::Trade(&trade))
+        } else if let Ok(mbp1) = record.extract::<Mbp1Msg>() {
+            self.update(RecordEnumRef::Mbp1(&mbp1))
+        } else {
+            Vec::new()
+        }
+    }
+
+    #[pyo3(name = "flush")]
+    fn py_flush(&mut self) -> Vec<OhlcvMsg> {
+        self.flush()
+    }
+
+    /// Builds bars from a full list of records, including the bars still open at the end.
+    fn resample(&mut self, records: Vec<Bound<PyAny>>) -> Vec<OhlcvMsg> {
+        let mut bars = Vec::new();
+        for record in &records {
+            bars.extend(self.py_update(record));
+        }
+        bars.extend(self.flush());
+        bars
+    }
+}
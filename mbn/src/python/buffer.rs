@@ -1,3 +1,4 @@
+use crate::compression::is_zstd;
 use crate::decode::Decoder;
 use crate::metadata::Metadata;
 use crate::utils::unix_nanos_to_date;
@@ -20,8 +21,10 @@ impl BufferStore {
     pub fn py_new(data: &Bound<PyBytes>) -> PyResult<Self> {
         let buffer = data.as_bytes().to_vec();
         let cursor = Cursor::new(buffer.clone());
-        let mut decoder = Decoder::new(cursor)?;
-        let metadata = decoder.metadata().unwrap();
+        let mut decoder = Decoder::new(cursor).map_err(|e| PyIOError::new_err(e.to_string()))?;
+        let metadata = decoder
+            .metadata()
+            .ok_or_else(|| PyIOError::new_err("Buffer does not contain a metadata block"))?;
 
         Ok(BufferStore {
             buffer,
@@ -140,6 +143,16 @@ impl BufferStore {
                         let bid_px: i64 = bid_px_obj.extract()?;
                         dict.set_item("bid_px", (bid_px as f64) / (PRICE_SCALE as f64))?;
                     }
+                    // Flattened MBP-10 levels
+                    for i in 0..10 {
+                        for side in ["bid_px", "ask_px"] {
+                            let key = format!("{}_{:02}", side, i);
+                            if let Some(px_obj) = dict.get_item(&key)? {
+                                let px: i64 = px_obj.extract()?;
+                                dict.set_item(&key, (px as f64) / (PRICE_SCALE as f64))?;
+                            }
+                        }
+                    }
                 }
 
                 Ok(dict.to_object(py))
@@ -155,9 +168,14 @@ impl BufferStore {
         std::fs::write(file_path, &self.buffer).map_err(|e| PyIOError::new_err(e.to_string()))
     }
 
+    /// Zstd compressed files are decompressed on load.
     #[staticmethod]
     pub fn from_file(file_path: &str, py: Python) -> PyResult<Self> {
-        let buffer = std::fs::read(file_path).map_err(|e| PyIOError::new_err(e.to_string()))?;
+        let mut buffer = std::fs::read(file_path).map_err(|e| PyIOError::new_err(e.to_string()))?;
+        if is_zstd(&buffer) {
+            buffer = zstd::decode_all(buffer.as_slice())
+                .map_err(|e| PyIOError::new_err(e.to_string()))?;
+        }
         let py_bytes = PyBytes::new_bound(py, &buffer);
         Ok(BufferStore::py_new(&py_bytes)?)
     }
@@ -3,6 +3,7 @@ use crate::encode::MetadataEncoder;
 use crate::enums::Schema;
 use crate::metadata::Metadata;
 use crate::symbols::SymbolMap;
+use pyo3::exceptions::PyIOError;
 use pyo3::prelude::*;
 use pyo3::types::{PyBytes, PyType};
 
@@ -39,7 +40,9 @@ impl Metadata {
     fn py_decode(_cls: &Bound<PyType>, data: &Bound<PyBytes>) -> PyResult<Metadata> {
         let reader = std::io::BufReader::new(data.as_bytes());
         let mut decoder = MetadataDecoder::new(reader);
-        let metadata = decoder.decode()?.unwrap();
-        Ok(metadata)
+        decoder
+            .decode()
+            .map_err(|e| PyIOError::new_err(e.to_string()))?
+            .ok_or_else(|| PyIOError::new_err("Bytes do not contain a metadata block"))
     }
 }
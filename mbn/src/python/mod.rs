@@ -1,8 +1,10 @@
 pub mod backtest;
+pub mod bars;
 pub mod buffer;
 pub mod encode;
 pub mod enums;
 pub mod live;
 pub mod metadata;
 pub mod records;
+pub mod subscriber;
 pub mod symbols;
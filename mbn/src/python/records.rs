@@ -1,6 +1,9 @@
 use crate::enums::{Action, RType, Side};
-use crate::records::{BboMsg, BidAskPair, Mbp1Msg, OhlcvMsg, RecordHeader, TradeMsg};
+use crate::records::{
+    BboMsg, BidAskPair, MboMsg, Mbp10Msg, Mbp1Msg, OhlcvMsg, RecordHeader, TradeMsg,
+};
 use crate::PRICE_SCALE;
+use pyo3::exceptions::{PyIndexError, PyValueError};
 use pyo3::prelude::*;
 use pyo3::types::PyDict;
 
@@ -89,8 +92,10 @@ impl Mbp1Msg {
     }
 
     #[getter]
-    fn rtype(&self) -> RType {
-        self.hd.rtype()
+    fn rtype(&self) -> PyResult<RType> {
+        self.hd
+            .rtype()
+            .map_err(|e| PyValueError::new_err(e.to_string()))
     }
 
     #[getter]
@@ -139,6 +144,224 @@ impl Mbp1Msg {
     }
 }
 
+#[pymethods]
+impl Mbp10Msg {
+    #[new]
+    #[allow(clippy::too_many_arguments)]
+    fn py_new(
+        instrument_id: u32,
+        ts_event: u64,
+        price: i64,
+        size: u32,
+        action: Action,
+        side: Side,
+        flags: u8,
+        depth: u8,
+        ts_recv: u64,
+        ts_in_delta: i32,
+        sequence: u32,
+        discriminator: u32,
+        levels: [BidAskPair; 10],
+    ) -> Self {
+        Mbp10Msg {
+            hd: RecordHeader::new::<Self>(instrument_id, ts_event),
+            price,
+            size,
+            action: action.into(),
+            side: side.into(),
+            flags,
+            depth,
+            ts_recv,
+            ts_in_delta,
+            sequence,
+            discriminator,
+            levels,
+        }
+    }
+
+    #[setter]
+    fn set_instrument_id(&mut self, instrument_id: u32) {
+        self.hd.instrument_id = instrument_id;
+    }
+
+    #[getter]
+    fn instrument_id(&self) -> u32 {
+        self.hd.instrument_id
+    }
+
+    #[getter]
+    fn ts_event(&self) -> u64 {
+        self.hd.ts_event
+    }
+
+    #[getter]
+    fn rtype(&self) -> PyResult<RType> {
+        self.hd
+            .rtype()
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    #[getter]
+    fn pretty_price(&self) -> f64 {
+        self.price as f64 / PRICE_SCALE as f64
+    }
+
+    #[getter]
+    fn pretty_action(&self) -> Action {
+        Action::try_from(self.action as u8).unwrap()
+    }
+
+    #[getter]
+    fn pretty_side(&self) -> Side {
+        Side::try_from(self.side as u8).unwrap()
+    }
+
+    /// Book level at `index`, 0 being the top of the book.
+    fn level(&self, index: usize) -> PyResult<BidAskPair> {
+        self.levels.get(index).cloned().ok_or_else(|| {
+            PyIndexError::new_err(format!(
+                "level {} out of range for {} levels",
+                index,
+                self.levels.len()
+            ))
+        })
+    }
+
+    fn __str__(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn __dict__(&self, py: Python) -> Py<PyDict> {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("length", self.hd.length).unwrap();
+        dict.set_item("rtype", self.hd.rtype).unwrap();
+        dict.set_item("instrument_id", self.hd.instrument_id)
+            .unwrap();
+        dict.set_item("ts_event", self.hd.ts_event).unwrap();
+        dict.set_item("price", self.price).unwrap();
+        dict.set_item("size", self.size).unwrap();
+        dict.set_item("action", self.action).unwrap();
+        dict.set_item("side", self.side).unwrap();
+        dict.set_item("flags", self.flags).unwrap();
+        dict.set_item("depth", self.depth).unwrap();
+        dict.set_item("ts_recv", self.ts_recv).unwrap();
+        dict.set_item("ts_in_delta", self.ts_in_delta).unwrap();
+        dict.set_item("sequence", self.sequence).unwrap();
+        dict.set_item("discriminator", self.discriminator).unwrap();
+        // Levels are flattened with a two digit suffix, e.g. bid_px_00 to bid_px_09
+        for (i, level) in self.levels.iter().enumerate() {
+            dict.set_item(format!("bid_px_{:02}", i), level.bid_px)
+                .unwrap();
+            dict.set_item(format!("ask_px_{:02}", i), level.ask_px)
+                .unwrap();
+            dict.set_item(format!("bid_sz_{:02}", i), level.bid_sz)
+                .unwrap();
+            dict.set_item(format!("ask_sz_{:02}", i), level.ask_sz)
+                .unwrap();
+            dict.set_item(format!("bid_ct_{:02}", i), level.bid_ct)
+                .unwrap();
+            dict.set_item(format!("ask_ct_{:02}", i), level.ask_ct)
+                .unwrap();
+        }
+        dict.into()
+    }
+}
+
+#[pymethods]
+impl MboMsg {
+    #[new]
+    #[allow(clippy::too_many_arguments)]
+    fn py_new(
+        instrument_id: u32,
+        ts_event: u64,
+        order_id: u64,
+        price: i64,
+        size: u32,
+        action: Action,
+        side: Side,
+        flags: u8,
+        channel_id: u8,
+        ts_recv: u64,
+        ts_in_delta: i32,
+        sequence: u32,
+    ) -> Self {
+        MboMsg {
+            hd: RecordHeader::new::<Self>(instrument_id, ts_event),
+            order_id,
+            price,
+            size,
+            flags,
+            channel_id,
+            action: action.into(),
+            side: side.into(),
+            ts_recv,
+            ts_in_delta,
+            sequence,
+        }
+    }
+
+    #[setter]
+    fn set_instrument_id(&mut self, instrument_id: u32) {
+        self.hd.instrument_id = instrument_id;
+    }
+
+    #[getter]
+    fn instrument_id(&self) -> u32 {
+        self.hd.instrument_id
+    }
+
+    #[getter]
+    fn ts_event(&self) -> u64 {
+        self.hd.ts_event
+    }
+
+    #[getter]
+    fn rtype(&self) -> PyResult<RType> {
+        self.hd
+            .rtype()
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    #[getter]
+    fn pretty_price(&self) -> f64 {
+        self.price as f64 / PRICE_SCALE as f64
+    }
+
+    #[getter]
+    fn pretty_action(&self) -> Action {
+        Action::try_from(self.action as u8).unwrap()
+    }
+
+    #[getter]
+    fn pretty_side(&self) -> Side {
+        Side::try_from(self.side as u8).unwrap()
+    }
+
+    fn __str__(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn __dict__(&self, py: Python) -> Py<PyDict> {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("length", self.hd.length).unwrap();
+        dict.set_item("rtype", self.hd.rtype).unwrap();
+        dict.set_item("instrument_id", self.hd.instrument_id)
+            .unwrap();
+        dict.set_item("ts_event", self.hd.ts_event).unwrap();
+        dict.set_item("order_id", self.order_id).unwrap();
+        dict.set_item("price", self.price).unwrap();
+        dict.set_item("size", self.size).unwrap();
+        dict.set_item("action", self.action).unwrap();
+        dict.set_item("side", self.side).unwrap();
+        dict.set_item("flags", self.flags).unwrap();
+        dict.set_item("channel_id", self.channel_id).unwrap();
+        dict.set_item("ts_recv", self.ts_recv).unwrap();
+        dict.set_item("ts_in_delta", self.ts_in_delta).unwrap();
+        dict.set_item("sequence", self.sequence).unwrap();
+        dict.into()
+    }
+}
+
 #[pymethods]
 impl TradeMsg {
     #[new]
@@ -186,8 +409,10 @@ impl TradeMsg {
     }
 
     #[getter]
-    fn rtype(&self) -> RType {
-        self.hd.rtype()
+    fn rtype(&self) -> PyResult<RType> {
+        self.hd
+            .rtype()
+            .map_err(|e| PyValueError::new_err(e.to_string()))
     }
 
     #[getter]
@@ -271,8 +496,10 @@ impl BboMsg {
     }
 
     #[getter]
-    fn rtype(&self) -> RType {
-        self.hd.rtype()
+    fn rtype(&self) -> PyResult<RType> {
+        self.hd
+            .rtype()
+            .map_err(|e| PyValueError::new_err(e.to_string()))
     }
 
     #[getter]
@@ -350,8 +577,10 @@ impl OhlcvMsg {
     }
 
     #[getter]
-    fn rtype(&self) -> RType {
-        self.hd.rtype()
+    fn rtype(&self) -> PyResult<RType> {
+        self.hd
+            .rtype()
+            .map_err(|e| PyValueError::new_err(e.to_string()))
     }
 
     #[getter]
@@ -0,0 +1,110 @@
+use crate::metadata::Metadata;
+use crate::replay::Subscription;
+use crate::subscriber::{Backoff, SequenceEvent, Subscriber};
+use futures::StreamExt;
+use pyo3::exceptions::PyIOError;
+use pyo3::prelude::*;
+use std::time::Duration;
+use tokio::runtime::{Builder, Runtime};
+
+/// How long `__next__` waits before checking for Python signals such as KeyboardInterrupt.
+const SIGNAL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Blocking iterator over a `Subscriber`, driven by its own single threaded runtime.
+#[pyclass(name = "Subscriber", module = "mbn")]
+pub struct PySubscriber {
+    runtime: Runtime,
+    subscriber: Subscriber,
+}
+
+#[pymethods]
+impl PySubscriber {
+    /// `address` is `host:port` or `unix:<path>`.
+    #[new]
+    #[pyo3(signature = (address, instrument_ids=None, tickers=None, handshake=false, reconnect_on_eof=true, max_retries=None, reset_on_reconnect=false))]
+    fn py_new(
+        address: &str,
+        instrument_ids: Option<Vec<u32>>,
+        tickers: Option<Vec<String>>,
+        handshake: bool,
+        reconnect_on_eof: bool,
+        max_retries: Option<u32>,
+        reset_on_reconnect: bool,
+    ) -> PyResult<Self> {
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| PyIOError::new_err(e.to_string()))?;
+        let mut subscriber = Subscriber::new(address)
+            .with_handshake(handshake)
+            .with_reconnect_on_eof(reconnect_on_eof)
+            .with_reset_on_reconnect(reset_on_reconnect)
+            .with_backoff(Backoff {
+                max_retries,
+                ..Backoff::default()
+            });
+        if instrument_ids.is_some() || tickers.is_some() {
+            subscriber = subscriber.with_subscription(Subscription {
+                instrument_ids: instrument_ids.unwrap_or_default(),
+                tickers: tickers.unwrap_or_default(),
+            });
+        }
+        Ok(PySubscriber {
+            runtime,
+            subscriber,
+        })
+    }
+
+    #[getter]
+    fn metadata(&self) -> Option<Metadata> {
+        self.subscriber.metadata()
+    }
+
+    #[getter]
+    fn reconnects(&self) -> u64 {
+        self.subscriber.reconnects()
+    }
+
+    /// Returns the gaps, duplicates and resets detected since the last call.
+    fn take_events(&mut self) -> Vec<SequenceEvent> {
+        self.subscriber.take_events()
+    }
+
+    /// Forgets the sequence numbers seen so far.
+    fn reset_sequences(&mut self) {
+        self.subscriber.reset_sequences();
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Blocks until the next record, releasing the GIL while waiting.
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        loop {
+            let PySubscriber {
+                runtime,
+                subscriber,
+            } = self;
+            // The timeout is created inside `block_on`, as it needs the runtime's timer.
+            let next = py.allow_threads(|| {
+                runtime.block_on(async {
+                    tokio::time::timeout(SIGNAL_CHECK_INTERVAL, subscriber.next()).await
+                })
+            });
+            match next {
+                Ok(Some(Ok(record))) => return Ok(Some(record.into_py(py))),
+                Ok(Some(Err(e))) => return Err(PyIOError::new_err(e.to_string())),
+                Ok(None) => return Ok(None),
+                Err(_) => py.check_signals()?,
+            }
+        }
+    }
+}
+
+#[pymethods]
+impl SequenceEvent {
+    fn __repr__(&self) -> String {
+        format!("{:?}", self)
+    }
+}
@@ -1,13 +1,18 @@
 use crate::enums::RType;
 use crate::error::{Error, Result};
 use crate::record_ref::RecordRef;
-use crate::records::{BboMsg, Mbp1Msg, OhlcvMsg, Record, RecordHeader, TbboMsg, TradeMsg};
+use crate::records::{
+    BboMsg, MboMsg, Mbp10Msg, Mbp1Msg, OhlcvMsg, Record, RecordHeader, TbboMsg, TradeMsg,
+    UnknownMsg,
+};
 use serde::Serialize;
 use std::hash::Hash;
 
 #[cfg(feature = "python")]
-use pyo3::prelude::*;
+use pyo3::{prelude::*, types::PyBytes};
 
+// Variants hold records by value to match `RecordRef` access, so Mbp10 sets the size.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize)]
 pub enum RecordEnum {
     Mbp1(Mbp1Msg),
@@ -15,11 +20,21 @@ pub enum RecordEnum {
     Trade(TradeMsg),
     Tbbo(TbboMsg),
     Bbo(BboMsg),
+    Mbp10(Mbp10Msg),
+    Mbo(MboMsg),
+    /// Record with an rtype unknown to this version of the crate.
+    Unknown(UnknownMsg),
 }
 
 impl RecordEnum {
+    /// Copies the record behind `rec_ref`. Records with an unknown rtype are returned as
+    /// `RecordEnum::Unknown`.
     pub fn from_ref(rec_ref: RecordRef) -> Result<Self> {
-        match rec_ref.header().rtype() {
+        let rtype = match rec_ref.header().rtype() {
+            Ok(rtype) => rtype,
+            Err(_) => return Ok(RecordEnum::Unknown(UnknownMsg::from(rec_ref))),
+        };
+        match rtype {
             RType::Mbp1 => rec_ref
                 .get::<Mbp1Msg>()
                 .map(|msg| RecordEnum::Mbp1(msg.clone()))
@@ -40,6 +55,14 @@ impl RecordEnum {
                 .get::<BboMsg>()
                 .map(|msg| RecordEnum::Bbo(msg.clone()))
                 .ok_or(Error::InvalidRecordType("Bbo")),
+            RType::Mbp10 => rec_ref
+                .get::<Mbp10Msg>()
+                .map(|msg| RecordEnum::Mbp10(msg.clone()))
+                .ok_or(Error::InvalidRecordType("Mbp10")),
+            RType::Mbo => rec_ref
+                .get::<MboMsg>()
+                .map(|msg| RecordEnum::Mbo(msg.clone()))
+                .ok_or(Error::InvalidRecordType("Mbo")),
         }
     }
 
@@ -50,6 +73,10 @@ impl RecordEnum {
             RecordEnum::Tbbo(record) => record.into(),
             RecordEnum::Bbo(record) => record.into(),
             RecordEnum::Trade(record) => record.into(),
+            RecordEnum::Mbp10(record) => record.into(),
+            RecordEnum::Mbo(record) => record.into(),
+            // Safety: the bytes are aligned and hold the complete record from its header.
+            RecordEnum::Unknown(record) => unsafe { RecordRef::new(record.as_ref()) },
         }
     }
 
@@ -60,6 +87,9 @@ impl RecordEnum {
             RecordEnum::Trade(msg) => RecordEnumRef::Trade(msg),
             RecordEnum::Tbbo(msg) => RecordEnumRef::Tbbo(msg),
             RecordEnum::Bbo(msg) => RecordEnumRef::Bbo(msg),
+            RecordEnum::Mbp10(msg) => RecordEnumRef::Mbp10(msg),
+            RecordEnum::Mbo(msg) => RecordEnumRef::Mbo(msg),
+            RecordEnum::Unknown(msg) => RecordEnumRef::Unknown(msg.into()),
         }
     }
     pub fn msg(&self) -> &dyn Record {
@@ -69,6 +99,9 @@ impl RecordEnum {
             RecordEnum::Trade(msg) => msg as &dyn Record,
             RecordEnum::Tbbo(msg) => msg as &dyn Record,
             RecordEnum::Bbo(msg) => msg as &dyn Record,
+            RecordEnum::Mbp10(msg) => msg as &dyn Record,
+            RecordEnum::Mbo(msg) => msg as &dyn Record,
+            RecordEnum::Unknown(msg) => msg as &dyn Record,
         }
     }
 }
@@ -82,6 +115,8 @@ impl PartialEq<dbn::RecordEnum> for RecordEnum {
             (RecordEnum::Bbo(mbn_msg), dbn::RecordEnum::Mbp1(dbn_msg)) => mbn_msg.eq(dbn_msg),
             (RecordEnum::Trade(mbn_msg), dbn::RecordEnum::Trade(dbn_msg)) => mbn_msg.eq(dbn_msg),
             (RecordEnum::Ohlcv(mbn_msg), dbn::RecordEnum::Ohlcv(dbn_msg)) => mbn_msg.eq(dbn_msg),
+            (RecordEnum::Mbp10(mbn_msg), dbn::RecordEnum::Mbp10(dbn_msg)) => mbn_msg.eq(dbn_msg),
+            (RecordEnum::Mbo(mbn_msg), dbn::RecordEnum::Mbo(dbn_msg)) => mbn_msg.eq(dbn_msg),
             _ => false,
         }
     }
@@ -95,6 +130,9 @@ impl AsRef<[u8]> for RecordEnum {
             RecordEnum::Trade(msg) => msg.as_ref(),
             RecordEnum::Tbbo(msg) => msg.as_ref(),
             RecordEnum::Bbo(msg) => msg.as_ref(),
+            RecordEnum::Mbp10(msg) => msg.as_ref(),
+            RecordEnum::Mbo(msg) => msg.as_ref(),
+            RecordEnum::Unknown(msg) => msg.as_ref(),
         }
     }
 }
@@ -107,6 +145,9 @@ impl Record for RecordEnum {
             RecordEnum::Trade(msg) => &msg.hd,
             RecordEnum::Tbbo(msg) => &msg.hd,
             RecordEnum::Bbo(msg) => &msg.hd,
+            RecordEnum::Mbp10(msg) => &msg.hd,
+            RecordEnum::Mbo(msg) => &msg.hd,
+            RecordEnum::Unknown(msg) => &msg.hd,
         }
     }
 }
@@ -120,6 +161,9 @@ impl IntoPy<Py<PyAny>> for RecordEnum {
             RecordEnum::Trade(msg) => msg.into_py(py).into(),
             RecordEnum::Tbbo(msg) => msg.into_py(py).into(),
             RecordEnum::Bbo(msg) => msg.into_py(py).into(),
+            RecordEnum::Mbp10(msg) => msg.into_py(py),
+            RecordEnum::Mbo(msg) => msg.into_py(py),
+            RecordEnum::Unknown(msg) => PyBytes::new_bound(py, msg.as_ref()).into(),
         }
     }
 }
@@ -131,17 +175,23 @@ pub enum RecordEnumRef<'a> {
     Trade(&'a TradeMsg),
     Tbbo(&'a TbboMsg),
     Bbo(&'a BboMsg),
+    Mbp10(&'a Mbp10Msg),
+    Mbo(&'a MboMsg),
+    Unknown(RecordRef<'a>),
 }
 
 //TODO: Adjust the from_ref to match RecordEnum
 impl<'a> RecordEnumRef<'a> {
     pub fn from_ref(rec_ref: RecordRef<'a>) -> Option<Self> {
         match rec_ref.header().rtype() {
-            RType::Mbp1 => rec_ref.get::<Mbp1Msg>().map(RecordEnumRef::Mbp1),
-            RType::Ohlcv => rec_ref.get::<OhlcvMsg>().map(RecordEnumRef::Ohlcv),
-            RType::Trade => rec_ref.get::<TradeMsg>().map(RecordEnumRef::Trade),
-            RType::Tbbo => rec_ref.get::<TbboMsg>().map(RecordEnumRef::Tbbo),
-            RType::Bbo => rec_ref.get::<BboMsg>().map(RecordEnumRef::Bbo),
+            Ok(RType::Mbp1) => rec_ref.get::<Mbp1Msg>().map(RecordEnumRef::Mbp1),
+            Ok(RType::Ohlcv) => rec_ref.get::<OhlcvMsg>().map(RecordEnumRef::Ohlcv),
+            Ok(RType::Trade) => rec_ref.get::<TradeMsg>().map(RecordEnumRef::Trade),
+            Ok(RType::Tbbo) => rec_ref.get::<TbboMsg>().map(RecordEnumRef::Tbbo),
+            Ok(RType::Bbo) => rec_ref.get::<BboMsg>().map(RecordEnumRef::Bbo),
+            Ok(RType::Mbp10) => rec_ref.get::<Mbp10Msg>().map(RecordEnumRef::Mbp10),
+            Ok(RType::Mbo) => rec_ref.get::<MboMsg>().map(RecordEnumRef::Mbo),
+            Err(_) => Some(RecordEnumRef::Unknown(rec_ref)),
         }
     }
 
@@ -152,6 +202,9 @@ impl<'a> RecordEnumRef<'a> {
             RecordEnumRef::Trade(msg) => RecordEnum::Trade((*msg).clone()),
             RecordEnumRef::Tbbo(msg) => RecordEnum::Tbbo((*msg).clone()),
             RecordEnumRef::Bbo(msg) => RecordEnum::Bbo((*msg).clone()),
+            RecordEnumRef::Mbp10(msg) => RecordEnum::Mbp10((*msg).clone()),
+            RecordEnumRef::Mbo(msg) => RecordEnum::Mbo((*msg).clone()),
+            RecordEnumRef::Unknown(rec_ref) => RecordEnum::Unknown(UnknownMsg::from(*rec_ref)),
         }
     }
 }
@@ -164,6 +217,9 @@ impl<'a> Record for RecordEnumRef<'a> {
             RecordEnumRef::Trade(msg) => &msg.hd,
             RecordEnumRef::Bbo(msg) => &msg.hd,
             RecordEnumRef::Tbbo(msg) => &msg.hd,
+            RecordEnumRef::Mbp10(msg) => &msg.hd,
+            RecordEnumRef::Mbo(msg) => &msg.hd,
+            RecordEnumRef::Unknown(rec_ref) => rec_ref.header(),
         }
     }
 }
@@ -1,5 +1,11 @@
-use crate::records::{HasRType, Record, RecordHeader};
+use crate::enums::RType;
+use crate::error::{Error, Result};
+use crate::records::{
+    BboMsg, HasRType, MboMsg, Mbp10Msg, Mbp1Msg, OhlcvMsg, Record, RecordHeader, TradeMsg,
+};
+use serde::{Serialize, Serializer};
 use std::marker::PhantomData;
+use std::mem;
 use std::ptr::NonNull;
 use std::slice;
 
@@ -14,6 +20,11 @@ unsafe impl<'a> Send for RecordRef<'a> {}
 unsafe impl<'a> Sync for RecordRef<'a> {}
 
 impl<'a> RecordRef<'a> {
+    /// Creates a reference to the record at the start of `buffer` without validation.
+    ///
+    /// # Safety
+    /// `buffer` must be aligned to `RecordHeader` and hold the complete record declared by its
+    /// header length; for a known rtype that length must cover the record type. Prefer `RecordRef::try_new` for untrusted input.
     pub unsafe fn new(buffer: &'a [u8]) -> Self {
         debug_assert!(buffer.len() >= std::mem::size_of::<RecordHeader>());
         let raw_ptr = buffer.as_ptr() as *mut RecordHeader;
@@ -24,6 +35,72 @@ impl<'a> RecordRef<'a> {
         }
     }
 
+    /// Creates a reference to the record at the start of `buffer`, checking that the buffer is
+    /// aligned, holds the full length declared in the header, and that the rtype is known and
+    /// fits in that length.
+    pub fn try_new(buffer: &'a [u8]) -> Result<Self> {
+        let header = Self::check_buffer(buffer)?;
+        let length = header.record_size();
+        let rtype = RType::try_from(header.rtype)?;
+        let expected = record_size_of(&rtype);
+        if length < expected {
+            return Err(Error::Decode(format!(
+                "record length {} is shorter than {} bytes required by rtype {}",
+                length, expected, rtype
+            )));
+        }
+
+        // Safety: validated above.
+        Ok(unsafe { Self::new(buffer) })
+    }
+
+    /// Like `try_new`, but accepts rtypes unknown to this crate. Typed access through `get`
+    /// returns `None` for those records, the raw bytes remain available through `as_ref`.
+    pub(crate) fn try_new_opaque(buffer: &'a [u8]) -> Result<Self> {
+        let header = Self::check_buffer(buffer)?;
+        if let Ok(rtype) = header.rtype() {
+            let expected = record_size_of(&rtype);
+            if header.record_size() < expected {
+                return Err(Error::Decode(format!(
+                    "record length {} is shorter than {} bytes required by rtype {}",
+                    header.record_size(),
+                    expected,
+                    rtype
+                )));
+            }
+        }
+
+        // Safety: validated above.
+        Ok(unsafe { Self::new(buffer) })
+    }
+
+    /// Checks size and alignment of `buffer` and returns the header it starts with.
+    fn check_buffer(buffer: &'a [u8]) -> Result<&'a RecordHeader> {
+        if buffer.len() < mem::size_of::<RecordHeader>() {
+            return Err(Error::Decode(format!(
+                "buffer of {} bytes is shorter than a record header",
+                buffer.len()
+            )));
+        }
+        if !(buffer.as_ptr() as usize).is_multiple_of(mem::align_of::<RecordHeader>()) {
+            return Err(Error::Decode(
+                "buffer is not aligned for a record header".to_string(),
+            ));
+        }
+
+        // Safety: length and alignment checked above, every bit pattern is a valid header.
+        let header = unsafe { &*(buffer.as_ptr() as *const RecordHeader) };
+        let length = header.record_size();
+        if length < mem::size_of::<RecordHeader>() || length > buffer.len() {
+            return Err(Error::Decode(format!(
+                "record length {} does not fit buffer of {} bytes",
+                length,
+                buffer.len()
+            )));
+        }
+        Ok(header)
+    }
+
     pub fn header(&self) -> &'a RecordHeader {
         unsafe { self.ptr.as_ref() }
     }
@@ -60,6 +137,53 @@ where
     }
 }
 
+/// Size of the record struct behind each rtype.
+fn record_size_of(rtype: &RType) -> usize {
+    match rtype {
+        RType::Mbp1 | RType::Tbbo => mem::size_of::<Mbp1Msg>(),
+        RType::Ohlcv => mem::size_of::<OhlcvMsg>(),
+        RType::Trade => mem::size_of::<TradeMsg>(),
+        RType::Bbo => mem::size_of::<BboMsg>(),
+        RType::Mbp10 => mem::size_of::<Mbp10Msg>(),
+        RType::Mbo => mem::size_of::<MboMsg>(),
+    }
+}
+
+/// Byte buffer backed by `u64` words so records read into it are aligned for `RecordRef`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct AlignedBuffer {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl AlignedBuffer {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resizes to `len` bytes, zero filling any newly added space.
+    pub(crate) fn resize(&mut self, len: usize) {
+        self.words.resize(len.div_ceil(mem::size_of::<u64>()), 0);
+        self.len = len;
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        // Safety: the words are initialised and `len` never exceeds their size in bytes.
+        unsafe { slice::from_raw_parts(self.words.as_ptr() as *const u8, self.len) }
+    }
+
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [u8] {
+        // Safety: as above, and the borrow is unique.
+        unsafe { slice::from_raw_parts_mut(self.words.as_mut_ptr() as *mut u8, self.len) }
+    }
+}
+
+impl Serialize for AlignedBuffer {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.as_slice())
+    }
+}
+
 impl<'a> AsRef<[u8]> for RecordRef<'a> {
     fn as_ref(&self) -> &'a [u8] {
         unsafe { slice::from_raw_parts(self.ptr.as_ptr() as *const u8, self.record_size()) }
@@ -91,4 +215,85 @@ mod tests {
         let decoded_record: &OhlcvMsg = new_ref.get().unwrap();
         assert_eq!(&record, decoded_record);
     }
+
+    fn ohlcv_bytes() -> AlignedBuffer {
+        let record = OhlcvMsg {
+            hd: RecordHeader::new::<OhlcvMsg>(2, 162222293489348),
+            open: 909,
+            high: 11991,
+            low: 800,
+            close: 999,
+            volume: 123456765432,
+        };
+        let record_ref = RecordRef::from(&record);
+        let bytes = record_ref.as_ref();
+        let mut buffer = AlignedBuffer::new();
+        buffer.resize(bytes.len());
+        buffer.as_mut_slice().copy_from_slice(bytes);
+        buffer
+    }
+
+    #[test]
+    fn test_try_new() -> anyhow::Result<()> {
+        let buffer = ohlcv_bytes();
+
+        // Test
+        let record_ref = RecordRef::try_new(buffer.as_slice())?;
+
+        // Validate
+        let decoded: &OhlcvMsg = record_ref.get().unwrap();
+        assert_eq!(decoded.volume, 123456765432);
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_new_short_buffer() {
+        let buffer = ohlcv_bytes();
+
+        // Test
+        let header_only = RecordRef::try_new(&buffer.as_slice()[..8]);
+        let truncated = RecordRef::try_new(&buffer.as_slice()[..40]);
+
+        // Validate
+        assert!(matches!(header_only, Err(Error::Decode(_))));
+        assert!(matches!(truncated, Err(Error::Decode(_))));
+    }
+
+    #[test]
+    fn test_try_new_unknown_rtype() {
+        let mut buffer = ohlcv_bytes();
+        buffer.as_mut_slice()[1] = 0xEE;
+
+        // Test
+        let result = RecordRef::try_new(buffer.as_slice());
+
+        // Validate
+        assert!(matches!(result, Err(Error::Conversion(_))));
+    }
+
+    #[test]
+    fn test_try_new_length_shorter_than_rtype() {
+        let mut buffer = ohlcv_bytes();
+        buffer.as_mut_slice()[0] = 6; // 24 bytes, an Ohlcv record needs 56
+
+        // Test
+        let result = RecordRef::try_new(buffer.as_slice());
+
+        // Validate
+        assert!(matches!(result, Err(Error::Decode(_))));
+    }
+
+    #[test]
+    fn test_try_new_misaligned() {
+        let source = ohlcv_bytes();
+        let mut buffer = AlignedBuffer::new();
+        buffer.resize(source.as_slice().len() + 1);
+        buffer.as_mut_slice()[1..].copy_from_slice(source.as_slice());
+
+        // Test
+        let result = RecordRef::try_new(&buffer.as_slice()[1..]);
+
+        // Validate
+        assert!(matches!(result, Err(Error::Decode(_))));
+    }
 }
@@ -1,4 +1,7 @@
 use crate::enums::RType;
+use crate::error::{Error, Result};
+use crate::layout::ByteLayout;
+use crate::record_ref::{AlignedBuffer, RecordRef};
 use databento::dbn;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
@@ -51,8 +54,9 @@ impl RecordHeader {
         self.length as usize * Self::LENGTH_MULTIPLIER
     }
 
-    pub fn rtype(&self) -> RType {
-        RType::try_from(self.rtype).unwrap()
+    /// Returns the record type, or `Error::Conversion` if the byte is not a known `RType`.
+    pub fn rtype(&self) -> Result<RType> {
+        RType::try_from(self.rtype)
     }
 
     pub fn from_dbn<R: HasRType>(header: dbn::RecordHeader) -> Self {
@@ -202,6 +206,92 @@ impl PartialEq<dbn::Mbp1Msg> for Mbp1Msg {
     }
 }
 
+/// Mbp10Msg struct, market by price with the top ten levels of the book.
+#[repr(C)]
+#[cfg_attr(feature = "python", pyclass(get_all, set_all, dict, module = "mbn"))]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, FromRow)]
+pub struct Mbp10Msg {
+    pub hd: RecordHeader,
+    pub price: i64,
+    pub size: u32,
+    pub action: c_char,
+    pub side: c_char,
+    pub depth: u8,
+    pub flags: u8,
+    pub ts_recv: u64,
+    pub ts_in_delta: i32,
+    pub sequence: u32,
+    /// Differentiates records that are otherwise the same but not duplicates.
+    pub discriminator: u32,
+    pub levels: [BidAskPair; 10],
+}
+
+impl Record for Mbp10Msg {
+    fn header(&self) -> &RecordHeader {
+        &self.hd
+    }
+}
+
+impl HasRType for Mbp10Msg {
+    fn has_rtype(rtype: u8) -> bool {
+        rtype == RType::Mbp10 as u8
+    }
+
+    fn rtype_byte() -> u8 {
+        RType::Mbp10 as u8
+    }
+}
+
+impl AsRef<[u8]> for Mbp10Msg {
+    fn as_ref(&self) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(
+                (self as *const Mbp10Msg) as *const u8,
+                mem::size_of::<Mbp10Msg>(),
+            )
+        }
+    }
+}
+
+impl From<dbn::Mbp10Msg> for Mbp10Msg {
+    fn from(item: dbn::Mbp10Msg) -> Self {
+        Mbp10Msg {
+            hd: RecordHeader::new::<Mbp10Msg>(item.hd.instrument_id, item.hd.ts_event),
+            price: item.price,
+            size: item.size,
+            action: item.action,
+            side: item.side,
+            depth: item.depth,
+            flags: item.flags.raw(),
+            ts_recv: item.ts_recv,
+            ts_in_delta: item.ts_in_delta,
+            sequence: item.sequence,
+            discriminator: 0,
+            levels: item.levels.map(BidAskPair::from),
+        }
+    }
+}
+
+impl PartialEq<dbn::Mbp10Msg> for Mbp10Msg {
+    fn eq(&self, other: &dbn::Mbp10Msg) -> bool {
+        self.hd.ts_event == other.hd.ts_event
+            && self.price == other.price
+            && self.size == other.size
+            && self.action == other.action
+            && self.side == other.side
+            && self.depth == other.depth
+            && self.flags == other.flags.raw()
+            && self.ts_recv == other.ts_recv
+            && self.ts_in_delta == other.ts_in_delta
+            && self.sequence == other.sequence
+            && self
+                .levels
+                .iter()
+                .zip(other.levels.iter())
+                .all(|(level, other)| level == other)
+    }
+}
+
 #[repr(C)]
 #[cfg_attr(feature = "python", pyclass(get_all, set_all, dict, module = "mbn"))]
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, FromRow)]
@@ -428,6 +518,139 @@ impl PartialEq<dbn::OhlcvMsg> for OhlcvMsg {
     }
 }
 
+/// MboMsg struct, market by order with one record per order book event.
+#[repr(C)]
+#[cfg_attr(feature = "python", pyclass(get_all, set_all, dict, module = "mbn"))]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, FromRow)]
+pub struct MboMsg {
+    pub hd: RecordHeader,
+    /// The order ID assigned at the venue.
+    pub order_id: u64,
+    pub price: i64,
+    pub size: u32,
+    pub flags: u8,
+    /// A channel ID within the venue.
+    pub channel_id: u8,
+    pub action: c_char,
+    pub side: c_char,
+    pub ts_recv: u64,
+    pub ts_in_delta: i32,
+    pub sequence: u32,
+}
+
+impl Record for MboMsg {
+    fn header(&self) -> &RecordHeader {
+        &self.hd
+    }
+}
+
+impl HasRType for MboMsg {
+    fn has_rtype(rtype: u8) -> bool {
+        rtype == RType::Mbo as u8
+    }
+
+    fn rtype_byte() -> u8 {
+        RType::Mbo as u8
+    }
+}
+
+impl AsRef<[u8]> for MboMsg {
+    fn as_ref(&self) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(
+                (self as *const MboMsg) as *const u8,
+                mem::size_of::<MboMsg>(),
+            )
+        }
+    }
+}
+
+impl From<dbn::MboMsg> for MboMsg {
+    fn from(item: dbn::MboMsg) -> Self {
+        MboMsg {
+            hd: RecordHeader::new::<MboMsg>(item.hd.instrument_id, item.hd.ts_event),
+            order_id: item.order_id,
+            price: item.price,
+            size: item.size,
+            flags: item.flags.raw(),
+            channel_id: item.channel_id,
+            action: item.action,
+            side: item.side,
+            ts_recv: item.ts_recv,
+            ts_in_delta: item.ts_in_delta,
+            sequence: item.sequence,
+        }
+    }
+}
+
+impl PartialEq<dbn::MboMsg> for MboMsg {
+    fn eq(&self, other: &dbn::MboMsg) -> bool {
+        self.hd.ts_event == other.hd.ts_event
+            && self.order_id == other.order_id
+            && self.price == other.price
+            && self.size == other.size
+            && self.flags == other.flags.raw()
+            && self.channel_id == other.channel_id
+            && self.action == other.action
+            && self.side == other.side
+            && self.ts_recv == other.ts_recv
+            && self.ts_in_delta == other.ts_in_delta
+            && self.sequence == other.sequence
+    }
+}
+
+/// Record with an rtype unknown to this version of the crate, e.g. written by a newer
+/// producer. Keeps the raw bytes, header included, so it can be passed through unchanged.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+pub struct UnknownMsg {
+    pub hd: RecordHeader,
+    bytes: AlignedBuffer,
+}
+
+impl UnknownMsg {
+    /// Copies the record at the start of `bytes`, using the length in its header.
+    pub fn new(bytes: &[u8]) -> Result<Self> {
+        let hd = RecordHeader::read_le(bytes)?;
+        let length = hd.record_size();
+        if length < RecordHeader::SIZE || length > bytes.len() {
+            return Err(Error::Decode(format!(
+                "record length {} does not fit buffer of {} bytes",
+                length,
+                bytes.len()
+            )));
+        }
+        let mut buffer = AlignedBuffer::new();
+        buffer.resize(length);
+        buffer.as_mut_slice().copy_from_slice(&bytes[..length]);
+        Ok(Self { hd, bytes: buffer })
+    }
+}
+
+impl<'a> From<RecordRef<'a>> for UnknownMsg {
+    fn from(rec_ref: RecordRef<'a>) -> Self {
+        let bytes = rec_ref.as_ref();
+        let mut buffer = AlignedBuffer::new();
+        buffer.resize(bytes.len());
+        buffer.as_mut_slice().copy_from_slice(bytes);
+        Self {
+            hd: rec_ref.header().clone(),
+            bytes: buffer,
+        }
+    }
+}
+
+impl Record for UnknownMsg {
+    fn header(&self) -> &RecordHeader {
+        &self.hd
+    }
+}
+
+impl AsRef<[u8]> for UnknownMsg {
+    fn as_ref(&self) -> &[u8] {
+        self.bytes.as_slice()
+    }
+}
+
 /// Transmutes entire byte slices header and record
 pub unsafe fn transmute_record_bytes<T: HasRType>(bytes: &[u8]) -> Option<T> {
     assert!(
@@ -518,6 +741,18 @@ mod tests {
         assert_eq!(rtype.as_str(), "mbp-1");
     }
 
+    #[test]
+    fn test_unknown_rtype() {
+        let mut hd = RecordHeader::new::<OhlcvMsg>(1, 1622471124);
+        hd.rtype = 0xEE;
+
+        // Test
+        let rtype = hd.rtype();
+
+        // Validate
+        assert!(rtype.is_err());
+    }
+
     #[test]
     fn test_record_header_transmute() {
         // Test
@@ -615,6 +850,76 @@ mod tests {
         assert_eq!(decoded_record, record);
     }
 
+    #[test]
+    fn test_mbp10_from_dbn() {
+        let levels = std::array::from_fn(|i| dbn::BidAskPair {
+            bid_px: 1000 - i as i64,
+            ask_px: 1001 + i as i64,
+            bid_sz: 10 + i as u32,
+            ask_sz: 20 + i as u32,
+            bid_ct: 1,
+            ask_ct: 2,
+        });
+        let dbn_msg = dbn::Mbp10Msg {
+            hd: dbn::RecordHeader::new::<dbn::Mbp10Msg>(1, 1, 1, 1725734014000000000),
+            price: 1000,
+            size: 10,
+            action: Action::Add as i8,
+            side: Side::Bid as i8,
+            flags: FlagSet::empty(),
+            depth: 3,
+            ts_recv: 1725734014000000000,
+            ts_in_delta: 12345,
+            sequence: 123456,
+            levels,
+        };
+
+        // Test
+        let record = Mbp10Msg::from(dbn_msg.clone());
+
+        // Validate
+        assert!(record == dbn_msg);
+        let mut flagged = dbn_msg.clone();
+        flagged.flags = FlagSet::new(dbn::flags::LAST);
+        assert!(record != flagged);
+        assert_eq!(record.hd.rtype().unwrap(), RType::Mbp10);
+        assert_eq!(record.hd.record_size(), mem::size_of::<Mbp10Msg>());
+        assert_eq!(record.levels[9].ask_px, 1010);
+
+        let bytes = unsafe { as_u8_slice(&record) };
+        let decoded_record: Mbp10Msg = unsafe { transmute_record_bytes(bytes).unwrap() };
+        assert_eq!(decoded_record, record);
+    }
+
+    #[test]
+    fn test_mbo_from_dbn() {
+        let dbn_msg = dbn::MboMsg {
+            hd: dbn::RecordHeader::new::<dbn::MboMsg>(1, 1, 1, 1725734014000000000),
+            order_id: 123456789,
+            price: 1000,
+            size: 10,
+            flags: FlagSet::empty(),
+            channel_id: 4,
+            action: Action::Add as i8,
+            side: Side::Ask as i8,
+            ts_recv: 1725734014000000000,
+            ts_in_delta: 12345,
+            sequence: 123456,
+        };
+
+        // Test
+        let record = MboMsg::from(dbn_msg.clone());
+
+        // Validate
+        assert!(record == dbn_msg);
+        assert_eq!(record.hd.rtype().unwrap(), RType::Mbo);
+        assert_eq!(record.hd.record_size(), mem::size_of::<MboMsg>());
+
+        let bytes = unsafe { as_u8_slice(&record) };
+        let decoded_record: MboMsg = unsafe { transmute_record_bytes(bytes).unwrap() };
+        assert_eq!(decoded_record, record);
+    }
+
     #[test]
     fn test_transmute_record_trade() {
         let record = TradeMsg {
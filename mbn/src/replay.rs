@@ -0,0 +1,535 @@
+//! Replay server streaming MBN files to socket clients, e.g. for strategy integration tests.
+//!
+//! A client connects and sends a `Subscription` as one line of JSON. The server answers with
+//! the metadata handshake of `MbnCodec`, whose mappings hold only the subscribed instruments,
+//! followed by their records in `ts_event` order, and closes the connection at the end of the
+//! data. Files are streamed with a decoder per subscription, which starts at the first
+//! subscribed record when the file has a sidecar `Index`. An invalid subscription, e.g. naming an unknown ticker, is answered with one line
+//! `ERR <reason>` instead, read back by `Subscription::read_rejection`.
+use crate::codec::MbnCodec;
+use crate::compression::{AsyncFileReader, FileReader};
+use crate::decode::{AsyncDecoder, Decoder};
+use crate::error::{Error, Result};
+use crate::index::Index;
+use crate::merge::{merge_metadata, AsyncMergeDecoder};
+use crate::metadata::Metadata;
+use crate::record_enum::RecordEnum;
+use crate::symbols::SymbolMap;
+use futures::SinkExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::TcpListener;
+use tokio::time::Instant;
+use tokio_util::codec::FramedWrite;
+
+/// Longest subscription line accepted by `read_from`, in bytes.
+pub const MAX_SUBSCRIPTION_LEN: u64 = 64 * 1024;
+/// Start of the line sent instead of the handshake when a subscription is rejected.
+const REJECTED_PREFIX: &str = "ERR ";
+
+/// Instruments a client asks for, by id or by ticker. Empty means every instrument.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subscription {
+    #[serde(default)]
+    pub instrument_ids: Vec<u32>,
+    #[serde(default)]
+    pub tickers: Vec<String>,
+}
+
+impl Subscription {
+    /// Subscription to every instrument.
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn with_instrument_id(mut self, instrument_id: u32) -> Self {
+        self.instrument_ids.push(instrument_id);
+        self
+    }
+
+    pub fn with_ticker(mut self, ticker: &str) -> Self {
+        self.tickers.push(ticker.to_string());
+        self
+    }
+
+    /// Sends the subscription as one line of JSON.
+    pub async fn write_to<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> Result<()> {
+        let mut line = serde_json::to_vec(self)
+            .map_err(|e| Error::Encode(format!("Invalid subscription: {}", e)))?;
+        line.push(b'\n');
+        writer.write_all(&line).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Reads a subscription sent by `write_to`, failing on lines longer than
+    /// `MAX_SUBSCRIPTION_LEN`.
+    pub async fn read_from<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Self> {
+        let mut line = String::new();
+        reader
+            .take(MAX_SUBSCRIPTION_LEN)
+            .read_line(&mut line)
+            .await?;
+        if line.len() as u64 == MAX_SUBSCRIPTION_LEN && !line.ends_with('\n') {
+            return Err(Error::Decode(format!(
+                "Subscription exceeds {} bytes",
+                MAX_SUBSCRIPTION_LEN
+            )));
+        }
+        serde_json::from_str(&line)
+            .map_err(|e| Error::Decode(format!("Invalid subscription: {}", e)))
+    }
+
+    /// Returns the reason sent by a `ReplayServer` that rejected the subscription as an error,
+    /// and leaves the stream untouched if it starts with the handshake instead.
+    pub async fn read_rejection<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<()> {
+        let available = reader.fill_buf().await?;
+        let prefix = available.len().min(REJECTED_PREFIX.len());
+        if available.is_empty() || available[..prefix] != REJECTED_PREFIX.as_bytes()[..prefix] {
+            return Ok(());
+        }
+        let mut line = String::new();
+        reader
+            .take(MAX_SUBSCRIPTION_LEN)
+            .read_line(&mut line)
+            .await?;
+        let reason = line.trim_end().trim_start_matches(REJECTED_PREFIX);
+        Err(Error::CustomError(format!(
+            "Subscription rejected: {}",
+            reason
+        )))
+    }
+
+    /// Instrument ids selected in `mappings`, `None` for every instrument. Fails on a ticker
+    /// missing from `mappings`.
+    fn resolve(&self, mappings: &SymbolMap) -> Result<Option<HashSet<u32>>> {
+        if self.instrument_ids.is_empty() && self.tickers.is_empty() {
+            return Ok(None);
+        }
+        let mut ids: HashSet<u32> = self.instrument_ids.iter().copied().collect();
+        for ticker in &self.tickers {
+            let id = mappings
+                .map
+                .iter()
+                .find(|(_, t)| *t == ticker)
+                .map(|(id, _)| *id)
+                .ok_or_else(|| Error::CustomError(format!("Unknown ticker {}", ticker)))?;
+            ids.insert(id);
+        }
+        Ok(Some(ids))
+    }
+}
+
+/// How fast records are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Pace {
+    /// As fast as the client reads them.
+    #[default]
+    Max,
+    /// Keeping the `ts_event` gaps between records divided by the speed, e.g. `Speed(1.0)`
+    /// replays in real time and `Speed(10.0)` ten times faster.
+    Speed(f64),
+}
+
+/// A replayed file and its sidecar index, if it has one.
+struct ReplayFile {
+    path: PathBuf,
+    index: Option<Index>,
+}
+
+/// Where a `ReplayServer` takes its records from.
+enum Source {
+    Records(Vec<RecordEnum>),
+    Files(Vec<ReplayFile>),
+}
+
+/// Serves the records of one or more MBN files to every client that connects.
+pub struct ReplayServer {
+    metadata: Metadata,
+    source: Source,
+    pace: Pace,
+}
+
+impl ReplayServer {
+    /// Replays `records`, which are expected to be sorted by `ts_event`.
+    pub fn new(metadata: Metadata, records: Vec<RecordEnum>) -> Self {
+        Self {
+            metadata,
+            source: Source::Records(records),
+            pace: Pace::default(),
+        }
+    }
+
+    /// Replays the files merged in `ts_event` order, see `AsyncMergeDecoder`. Only the metadata
+    /// and existing sidecar indexes are read here, the records are read for each subscription.
+    pub fn from_files<P: AsRef<Path>>(file_paths: &[P]) -> Result<Self> {
+        let mut files = Vec::with_capacity(file_paths.len());
+        let mut metadata = Vec::with_capacity(file_paths.len());
+        for path in file_paths {
+            let path = path.as_ref();
+            metadata.extend(Decoder::<FileReader>::from_file(path)?.metadata());
+            // The index only saves reading, a file whose index can't be loaded is read whole.
+            let index = Index::sidecar_path(path)
+                .exists()
+                .then(|| Index::load_or_build(path).ok())
+                .flatten();
+            files.push(ReplayFile {
+                path: path.to_path_buf(),
+                index,
+            });
+        }
+        let metadata = merge_metadata(metadata.iter())?
+            .ok_or_else(|| Error::Decode("No metadata in replay files".to_string()))?;
+        Ok(Self {
+            metadata,
+            source: Source::Files(files),
+            pace: Pace::default(),
+        })
+    }
+
+    /// Sets the replay speed, `Pace::Max` by default.
+    pub fn with_pace(mut self, pace: Pace) -> Self {
+        self.pace = pace;
+        self
+    }
+
+    /// Accepts TCP clients until the listener fails, replaying to each on its own task.
+    pub async fn serve_tcp(self, listener: TcpListener) -> Result<()> {
+        let server = Arc::new(self);
+        loop {
+            let (stream, addr) = listener.accept().await?;
+            let server = Arc::clone(&server);
+            tokio::spawn(async move {
+                if let Err(e) = server.serve_connection(stream).await {
+                    tracing::warn!("Replay to {} failed: {}", addr, e);
+                }
+            });
+        }
+    }
+
+    /// Accepts Unix socket clients until the listener fails, replaying to each on its own task.
+    #[cfg(unix)]
+    pub async fn serve_unix(self, listener: tokio::net::UnixListener) -> Result<()> {
+        let server = Arc::new(self);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let server = Arc::clone(&server);
+            tokio::spawn(async move {
+                if let Err(e) = server.serve_connection(stream).await {
+                    tracing::warn!("Replay to unix socket client failed: {}", e);
+                }
+            });
+        }
+    }
+
+    /// Reads the subscription of one client and replays its records over `stream`. An invalid
+    /// subscription is answered with the reason it was rejected, which is also returned.
+    pub async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: S,
+    ) -> Result<()> {
+        let mut reader = BufReader::new(stream);
+        let ids = match Subscription::read_from(&mut reader).await {
+            Ok(subscription) => subscription.resolve(&self.metadata.mappings),
+            Err(e) => Err(e),
+        };
+        let ids = match ids {
+            Ok(ids) => ids,
+            Err(e) => return Err(reject(reader.into_inner(), e).await),
+        };
+        let selected = |record: &RecordEnum| {
+            ids.as_ref()
+                .is_none_or(|ids| ids.contains(&record.msg().header().instrument_id))
+        };
+
+        let mut metadata = self.metadata.clone();
+        if let Some(ids) = &ids {
+            metadata.mappings.map.retain(|id, _| ids.contains(id));
+        }
+        let mut framed =
+            FramedWrite::new(reader.into_inner(), MbnCodec::new().with_handshake(true));
+        framed.send(metadata).await?;
+
+        let mut pacer = Pacer::new(self.pace);
+        match &self.source {
+            Source::Records(records) => {
+                for record in records.iter().filter(|record| selected(record)) {
+                    pacer.send(&mut framed, record).await?;
+                }
+            }
+            Source::Files(files) => {
+                let mut decoders = Vec::with_capacity(files.len());
+                for file in files {
+                    let first = match (&file.index, &ids) {
+                        (Some(index), Some(ids)) => {
+                            let spans = ids.iter().filter_map(|id| index.instruments.get(id));
+                            match spans.map(|span| span.first).min() {
+                                Some(first) => Some(first),
+                                // None of the subscribed instruments are in this file.
+                                None => continue,
+                            }
+                        }
+                        _ => None,
+                    };
+                    let mut decoder =
+                        AsyncDecoder::<AsyncFileReader>::from_file(&file.path).await?;
+                    if let Some(first) = first {
+                        decoder.seek_to_offset(first).await?;
+                    }
+                    decoders.push(decoder);
+                }
+                let mut merged = AsyncMergeDecoder::new(decoders)?;
+                while let Some(record) = merged.decode_next().await? {
+                    if selected(&record) {
+                        pacer.send(&mut framed, &record).await?;
+                    }
+                }
+            }
+        }
+        SinkExt::<&RecordEnum>::close(&mut framed).await?;
+        Ok(())
+    }
+}
+
+/// Sends records at a `Pace`, relative to the first record sent.
+struct Pacer {
+    pace: Pace,
+    start: Instant,
+    first_ts: Option<u64>,
+}
+
+impl Pacer {
+    fn new(pace: Pace) -> Self {
+        Self {
+            pace,
+            start: Instant::now(),
+            first_ts: None,
+        }
+    }
+
+    async fn send<S: AsyncWrite + Unpin>(
+        &mut self,
+        framed: &mut FramedWrite<S, MbnCodec>,
+        record: &RecordEnum,
+    ) -> Result<()> {
+        match self.pace {
+            Pace::Speed(speed) if speed > 0.0 => {
+                let ts = record.msg().header().ts_event;
+                let first_ts = *self.first_ts.get_or_insert(ts);
+                let offset = ts.saturating_sub(first_ts) as f64 / speed;
+                tokio::time::sleep_until(self.start + Duration::from_nanos(offset as u64)).await;
+                framed.send(record).await?;
+            }
+            _ => framed.feed(record).await?,
+        }
+        Ok(())
+    }
+}
+
+/// Sends `error` to the client as the reason its subscription was rejected and closes the
+/// stream. Failing to send is ignored, as the client may already be gone; `error` is returned
+/// for the server to log.
+async fn reject<S: AsyncWrite + Unpin>(mut stream: S, error: Error) -> Error {
+    let line = format!("{}{}\n", REJECTED_PREFIX, error.extract_message());
+    if stream.write_all(line.as_bytes()).await.is_ok() {
+        let _ = stream.shutdown().await;
+    }
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::CombinedEncoder;
+    use crate::enums::Schema;
+    use crate::record_ref::RecordRef;
+    use crate::records::{OhlcvMsg, RecordHeader};
+    use futures::StreamExt;
+    use serial_test::serial;
+    use tokio::net::TcpStream;
+    use tokio_util::codec::FramedRead;
+
+    fn ohlcv(instrument_id: u32, ts_event: u64) -> OhlcvMsg {
+        OhlcvMsg {
+            hd: RecordHeader::new::<OhlcvMsg>(instrument_id, ts_event),
+            open: 100,
+            high: 200,
+            low: 50,
+            close: 150,
+            volume: ts_event,
+        }
+    }
+
+    fn server(gap: u64) -> ReplayServer {
+        let mut mappings = SymbolMap::new();
+        mappings.add_instrument("AAPL", 1);
+        mappings.add_instrument("TSLA", 2);
+        let metadata = Metadata::new(Schema::Ohlcv1S, 0, 10 * gap, mappings);
+        let records = (0..10)
+            .map(|i| RecordEnum::Ohlcv(ohlcv(1 + i as u32 % 2, i * gap)))
+            .collect();
+        ReplayServer::new(metadata, records)
+    }
+
+    async fn subscribe<S: AsyncRead + AsyncWrite + Unpin>(
+        mut stream: S,
+        subscription: Subscription,
+    ) -> Result<(Metadata, Vec<RecordEnum>)> {
+        subscription.write_to(&mut stream).await?;
+        let mut reader = BufReader::new(stream);
+        Subscription::read_rejection(&mut reader).await?;
+        let mut framed = FramedRead::new(reader, MbnCodec::new().with_handshake(true));
+        let mut records = Vec::new();
+        while let Some(record) = framed.next().await {
+            records.push(record?);
+        }
+        let metadata = framed
+            .decoder()
+            .metadata()
+            .ok_or_else(|| Error::Decode("Replay ended without metadata".to_string()))?;
+        Ok((metadata, records))
+    }
+
+    #[tokio::test]
+    async fn test_replay_tcp_subscription() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(server(1).serve_tcp(listener));
+
+        // Test
+        let (all_metadata, all) =
+            subscribe(TcpStream::connect(addr).await?, Subscription::all()).await?;
+        let (metadata, tsla) = subscribe(
+            TcpStream::connect(addr).await?,
+            Subscription::all().with_ticker("TSLA"),
+        )
+        .await?;
+        let unknown = subscribe(
+            TcpStream::connect(addr).await?,
+            Subscription::all().with_ticker("MSFT"),
+        )
+        .await;
+
+        // Validate
+        assert_eq!(all.len(), 10);
+        assert_eq!(all_metadata.mappings.map.len(), 2);
+        assert_eq!(tsla.len(), 5);
+        assert!(tsla.iter().all(|r| r.msg().header().instrument_id == 2));
+        assert_eq!(metadata.mappings.map.keys().collect::<Vec<_>>(), vec![&2]);
+        let err = unknown.unwrap_err().to_string();
+        assert!(
+            err.contains("Subscription rejected: Unknown ticker MSFT"),
+            "{}",
+            err
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_subscription_too_long() -> anyhow::Result<()> {
+        let server = server(1);
+        let (mut client, connection) = tokio::io::duplex(1024);
+        let line = vec![b' '; MAX_SUBSCRIPTION_LEN as usize];
+
+        // Test
+        let (served, rejection) = tokio::join!(server.serve_connection(connection), async {
+            client.write_all(&line).await?;
+            Subscription::read_rejection(&mut BufReader::new(client)).await
+        });
+
+        // Validate
+        assert!(served.is_err());
+        let err = rejection.unwrap_err().to_string();
+        assert!(err.contains("exceeds"), "{}", err);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_paced() -> anyhow::Result<()> {
+        // 10 records 10ms apart in `ts_event`.
+        let server = server(10_000_000).with_pace(Pace::Speed(2.0));
+        let (client, connection) = tokio::io::duplex(1024);
+        let subscription = Subscription::all().with_instrument_id(1);
+
+        // Test
+        let start = std::time::Instant::now();
+        let (served, received) = tokio::join!(
+            server.serve_connection(connection),
+            subscribe(client, subscription)
+        );
+        let elapsed = start.elapsed();
+
+        // Validate
+        served?;
+        let (_, records) = received?;
+        assert_eq!(records.len(), 5);
+        // Instrument 1 spans 80ms of `ts_event`, replayed at twice the speed.
+        assert!(elapsed >= Duration::from_millis(40), "{:?}", elapsed);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_replay_files_seek_with_index() -> anyhow::Result<()> {
+        let indexed = PathBuf::from("tests/replay_indexed.bin");
+        let plain = PathBuf::from("tests/replay_plain.bin");
+        let mut mappings = SymbolMap::new();
+        mappings.add_instrument("AAPL", 1);
+        mappings.add_instrument("TSLA", 2);
+        let msgs: Vec<OhlcvMsg> = (0..10).map(|i| ohlcv(1 + i as u32 / 5, i)).collect();
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+        let mut encoder = CombinedEncoder::new(Vec::new()).with_index(2);
+        encoder.encode(&Metadata::new(Schema::Ohlcv1S, 0, 9, mappings), &records)?;
+        encoder.write_to_file(&indexed, false)?;
+        // An unknown rtype in the first AAPL record, which a TSLA subscription seeks past.
+        let data_offset = encoder.index().unwrap().data_offset as usize;
+        let mut bytes = encoder.into_inner();
+        bytes[data_offset + 1] = 0xEE;
+        std::fs::write(&indexed, &bytes)?;
+
+        let mut mappings = SymbolMap::new();
+        mappings.add_instrument("MSFT", 3);
+        let msgs = [ohlcv(3, 4), ohlcv(3, 6)];
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+        let mut buffer = Vec::new();
+        CombinedEncoder::new(&mut buffer)
+            .encode(&Metadata::new(Schema::Ohlcv1S, 4, 6, mappings), &records)?;
+        std::fs::write(&plain, &buffer)?;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(ReplayServer::from_files(&[&indexed, &plain])?.serve_tcp(listener));
+
+        // Test
+        let (_, tsla) = subscribe(
+            TcpStream::connect(addr).await?,
+            Subscription::all().with_ticker("TSLA").with_ticker("MSFT"),
+        )
+        .await?;
+        let all = subscribe(TcpStream::connect(addr).await?, Subscription::all()).await;
+
+        // Validate
+        let ordered: Vec<(u32, u64)> = tsla
+            .iter()
+            .map(|r| (r.msg().header().instrument_id, r.msg().header().ts_event))
+            .collect();
+        assert_eq!(
+            ordered,
+            vec![(3, 4), (2, 5), (2, 6), (3, 6), (2, 7), (2, 8), (2, 9)]
+        );
+        // Subscribing to everything reads the corrupt record, which ends the replay.
+        assert!(all?.1.is_empty());
+
+        // Cleanup
+        std::fs::remove_file(Index::sidecar_path(&indexed))?;
+        std::fs::remove_file(&indexed)?;
+        std::fs::remove_file(&plain)?;
+        Ok(())
+    }
+}
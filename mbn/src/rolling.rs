@@ -0,0 +1,581 @@
+//! Sinks recording a stream of records into a directory of MBN files, starting a new file at
+//! UTC day or hour boundaries or once a file reaches a size.
+//!
+//! The open file is written to `<file>.partial` by a `CombinedEncoder`, behind a provisional
+//! header holding every known mapping. When the file is rotated the header is replaced by one
+//! whose `start` and `end` are the first and last `ts_event` in it and whose mappings hold only
+//! the instruments that appear in it, zero padded to the provisional length so it is patched in
+//! place, and the file is renamed to its final path. A `.partial` file left by a crash can still
+//! be read with `Decoder`.
+use crate::encode::{
+    encoded_metadata_length, AsyncCombinedEncoder, CombinedEncoder, MetadataEncoder,
+};
+use crate::enums::{RType, Schema};
+use crate::error::{Error, Result};
+use crate::metadata::Metadata;
+use crate::record_ref::RecordRef;
+use crate::records::RecordHeader;
+use crate::symbols::SymbolMap;
+use chrono::{TimeZone, Utc};
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+const NANOS_PER_HOUR: u64 = 3_600_000_000_000;
+const NANOS_PER_DAY: u64 = 24 * NANOS_PER_HOUR;
+
+/// When a rolling sink starts a new file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    /// At every UTC midnight, by `ts_event`.
+    Daily,
+    /// At every full UTC hour, by `ts_event`.
+    Hourly,
+    /// Before a record would take the records of a file past this many bytes.
+    Bytes(u64),
+}
+
+impl Rotation {
+    /// Index of the time period `ts_event` falls into, constant for `Bytes`.
+    fn period(&self, ts_event: u64) -> u64 {
+        match self {
+            Rotation::Daily => ts_event / NANOS_PER_DAY,
+            Rotation::Hourly => ts_event / NANOS_PER_HOUR,
+            Rotation::Bytes(_) => 0,
+        }
+    }
+
+    fn file_stamp(&self, ts_event: u64) -> String {
+        let format = match self {
+            Rotation::Daily => "%Y%m%d",
+            Rotation::Hourly => "%Y%m%dT%H",
+            Rotation::Bytes(_) => "%Y%m%dT%H%M%S",
+        };
+        Utc.timestamp_nanos(ts_event as i64)
+            .format(format)
+            .to_string()
+    }
+}
+
+/// The file currently being written.
+struct Segment {
+    path: PathBuf,
+    partial: PathBuf,
+    period: u64,
+    start: u64,
+    end: u64,
+    bytes: u64,
+    instruments: BTreeSet<u32>,
+    /// Length of the provisional header, where the records start.
+    data_offset: u64,
+}
+
+impl Segment {
+    fn push(&mut self, header: &RecordHeader) {
+        self.start = self.start.min(header.ts_event);
+        self.end = self.end.max(header.ts_event);
+        self.bytes += header.record_size() as u64;
+        self.instruments.insert(header.instrument_id);
+    }
+}
+
+/// Configuration and bookkeeping shared by `RollingSink` and `AsyncRollingSink`.
+struct Rolling {
+    dir: PathBuf,
+    schema: Schema,
+    rotation: Rotation,
+    mappings: SymbolMap,
+    files: Vec<PathBuf>,
+}
+
+impl Rolling {
+    fn new(dir: &Path, schema: Schema, mappings: SymbolMap, rotation: Rotation) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            schema,
+            rotation,
+            mappings,
+            files: Vec::new(),
+        }
+    }
+
+    fn check(&self, header: &RecordHeader) -> Result<()> {
+        if header.rtype != RType::from(self.schema) as u8 {
+            return Err(Error::Encode(format!(
+                "Record rtype {:#04x} does not match schema {}",
+                header.rtype, self.schema
+            )));
+        }
+        Ok(())
+    }
+
+    fn needs_rotation(&self, segment: &Segment, header: &RecordHeader) -> bool {
+        match self.rotation {
+            Rotation::Bytes(max) => {
+                segment.bytes > 0 && segment.bytes + header.record_size() as u64 > max
+            }
+            // Late records stay in the open file rather than reopening a finished period.
+            _ => self.rotation.period(header.ts_event) > segment.period,
+        }
+    }
+
+    /// Starts a segment for `header` at a path not used by an existing or previous file.
+    fn segment(&self, header: &RecordHeader) -> Segment {
+        let stem = format!(
+            "{}_{}",
+            self.schema,
+            self.rotation.file_stamp(header.ts_event)
+        );
+        let mut path = self.dir.join(format!("{}.bin", stem));
+        let mut n = 1;
+        while path.exists() || partial_path(&path).exists() || self.files.contains(&path) {
+            path = self.dir.join(format!("{}_{}.bin", stem, n));
+            n += 1;
+        }
+        Segment {
+            partial: partial_path(&path),
+            path,
+            period: self.rotation.period(header.ts_event),
+            start: header.ts_event,
+            end: header.ts_event,
+            bytes: 0,
+            instruments: BTreeSet::new(),
+            data_offset: 0,
+        }
+    }
+
+    /// Header written when a segment is opened, before its instruments and end are known.
+    fn provisional_metadata(&self, segment: &Segment) -> Metadata {
+        Metadata::new(
+            self.schema,
+            segment.start,
+            segment.end,
+            self.mappings.clone(),
+        )
+    }
+
+    /// Final header of `segment`, padded to the provisional one unless it grew longer, e.g.
+    /// after `add_mappings`.
+    fn header(&self, segment: &Segment) -> io::Result<Vec<u8>> {
+        let mut header = Vec::new();
+        MetadataEncoder::new(&mut header)
+            .with_min_length(segment.data_offset as usize)
+            .encode_metadata(&self.metadata(segment))?;
+        Ok(header)
+    }
+
+    fn metadata(&self, segment: &Segment) -> Metadata {
+        let mut mappings = SymbolMap::new();
+        for id in &segment.instruments {
+            if let Some(ticker) = self.mappings.map.get(id) {
+                mappings.add_instrument(ticker, *id);
+            }
+        }
+        Metadata::new(self.schema, segment.start, segment.end, mappings)
+    }
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    PathBuf::from(partial)
+}
+
+/// Records a stream of records into rotating MBN files in a directory. The open file is
+/// completed on drop, ignoring errors; call `finish` to handle them.
+pub struct RollingSink {
+    rolling: Rolling,
+    current: Option<(Segment, CombinedEncoder<BufWriter<File>>)>,
+}
+
+impl RollingSink {
+    /// Creates `dir` if needed. `mappings` holds the tickers of every instrument that may be
+    /// recorded, each file keeps only those it contains.
+    pub fn new<P: AsRef<Path>>(
+        dir: P,
+        schema: Schema,
+        mappings: SymbolMap,
+        rotation: Rotation,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            rolling: Rolling::new(dir.as_ref(), schema, mappings, rotation),
+            current: None,
+        })
+    }
+
+    /// Adds tickers for instruments that start trading while recording.
+    pub fn add_mappings(&mut self, mappings: &SymbolMap) -> Result<()> {
+        self.rolling.mappings.try_merge(mappings)
+    }
+
+    /// Files completed so far, in the order they were written.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.rolling.files
+    }
+
+    pub fn encode_record(&mut self, record: &RecordRef) -> Result<()> {
+        let header = record.header();
+        self.rolling.check(header)?;
+        if let Some((segment, _)) = &self.current {
+            if self.rolling.needs_rotation(segment, header) {
+                self.rotate()?;
+            }
+        }
+        if self.current.is_none() {
+            let mut segment = self.rolling.segment(header);
+            let metadata = self.rolling.provisional_metadata(&segment);
+            // Opened for reading too, to copy the records if the final header is longer.
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&segment.partial)?;
+            let mut encoder = CombinedEncoder::new(BufWriter::new(file));
+            encoder.encode_metadata(&metadata)?;
+            segment.data_offset = encoded_metadata_length(&metadata);
+            self.current = Some((segment, encoder));
+        }
+        let (segment, encoder) = self.current.as_mut().unwrap();
+        encoder.encode_record(record)?;
+        segment.push(header);
+        Ok(())
+    }
+
+    pub fn encode_records(&mut self, records: &[RecordRef]) -> Result<()> {
+        for record in records {
+            self.encode_record(record)?;
+        }
+        Ok(())
+    }
+
+    /// Completes the open file, if any, and returns its path. The next record starts a new one.
+    pub fn rotate(&mut self) -> Result<Option<PathBuf>> {
+        let Some((segment, encoder)) = self.current.take() else {
+            return Ok(None);
+        };
+        let mut partial = encoder
+            .into_inner()
+            .into_inner()
+            .map_err(|e| e.into_error())?;
+        let header = self.rolling.header(&segment)?;
+
+        // The records are synced before the header is patched, so a crash leaves a readable
+        // partial file.
+        partial.sync_data()?;
+        if header.len() as u64 == segment.data_offset {
+            partial.seek(SeekFrom::Start(0))?;
+            partial.write_all(&header)?;
+            partial.sync_all()?;
+            drop(partial);
+            std::fs::rename(&segment.partial, &segment.path)?;
+        } else {
+            partial.seek(SeekFrom::Start(segment.data_offset))?;
+            let mut file = BufWriter::new(File::create(&segment.path)?);
+            file.write_all(&header)?;
+            io::copy(&mut partial, &mut file)?;
+            file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            drop(partial);
+            std::fs::remove_file(&segment.partial)?;
+        }
+
+        self.rolling.files.push(segment.path.clone());
+        Ok(Some(segment.path))
+    }
+
+    /// Completes the open file and returns every file written.
+    pub fn finish(mut self) -> Result<Vec<PathBuf>> {
+        self.rotate()?;
+        Ok(std::mem::take(&mut self.rolling.files))
+    }
+}
+
+impl Drop for RollingSink {
+    fn drop(&mut self) {
+        let _ = self.rotate();
+    }
+}
+
+/// Async counterpart of `RollingSink`. Files can't be completed on drop, `finish` has to be
+/// called or the open file is left as `<file>.partial`.
+pub struct AsyncRollingSink {
+    rolling: Rolling,
+    current: Option<(
+        Segment,
+        AsyncCombinedEncoder<tokio::io::BufWriter<tokio::fs::File>>,
+    )>,
+}
+
+impl AsyncRollingSink {
+    /// Creates `dir` if needed. `mappings` holds the tickers of every instrument that may be
+    /// recorded, each file keeps only those it contains.
+    pub async fn new<P: AsRef<Path>>(
+        dir: P,
+        schema: Schema,
+        mappings: SymbolMap,
+        rotation: Rotation,
+    ) -> Result<Self> {
+        tokio::fs::create_dir_all(dir.as_ref()).await?;
+        Ok(Self {
+            rolling: Rolling::new(dir.as_ref(), schema, mappings, rotation),
+            current: None,
+        })
+    }
+
+    /// Adds tickers for instruments that start trading while recording.
+    pub fn add_mappings(&mut self, mappings: &SymbolMap) -> Result<()> {
+        self.rolling.mappings.try_merge(mappings)
+    }
+
+    /// Files completed so far, in the order they were written.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.rolling.files
+    }
+
+    pub async fn encode_record<'a>(&mut self, record: &'a RecordRef<'a>) -> Result<()> {
+        let header = record.header();
+        self.rolling.check(header)?;
+        if let Some((segment, _)) = &self.current {
+            if self.rolling.needs_rotation(segment, header) {
+                self.rotate().await?;
+            }
+        }
+        if self.current.is_none() {
+            let mut segment = self.rolling.segment(header);
+            let metadata = self.rolling.provisional_metadata(&segment);
+            let file = tokio::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&segment.partial)
+                .await?;
+            let mut encoder = AsyncCombinedEncoder::new(tokio::io::BufWriter::new(file));
+            encoder.encode_metadata(&metadata).await?;
+            segment.data_offset = encoded_metadata_length(&metadata);
+            self.current = Some((segment, encoder));
+        }
+        let (segment, encoder) = self.current.as_mut().unwrap();
+        encoder.encode_record(record).await?;
+        segment.push(header);
+        Ok(())
+    }
+
+    pub async fn encode_records<'a>(&mut self, records: &'a [RecordRef<'a>]) -> Result<()> {
+        for record in records {
+            self.encode_record(record).await?;
+        }
+        Ok(())
+    }
+
+    /// Completes the open file, if any, and returns its path. The next record starts a new one.
+    pub async fn rotate(&mut self) -> Result<Option<PathBuf>> {
+        let Some((segment, encoder)) = self.current.take() else {
+            return Ok(None);
+        };
+        let mut writer = encoder.into_inner();
+        writer.flush().await?;
+        let mut partial = writer.into_inner();
+        let header = self.rolling.header(&segment)?;
+
+        partial.sync_data().await?;
+        if header.len() as u64 == segment.data_offset {
+            partial.seek(SeekFrom::Start(0)).await?;
+            partial.write_all(&header).await?;
+            partial.flush().await?;
+            partial.sync_all().await?;
+            drop(partial);
+            tokio::fs::rename(&segment.partial, &segment.path).await?;
+        } else {
+            partial.seek(SeekFrom::Start(segment.data_offset)).await?;
+            let mut file = tokio::io::BufWriter::new(tokio::fs::File::create(&segment.path).await?);
+            file.write_all(&header).await?;
+            tokio::io::copy(&mut partial, &mut file).await?;
+            file.flush().await?;
+            file.get_ref().sync_all().await?;
+            drop(partial);
+            tokio::fs::remove_file(&segment.partial).await?;
+        }
+
+        self.rolling.files.push(segment.path.clone());
+        Ok(Some(segment.path))
+    }
+
+    /// Completes the open file and returns every file written.
+    pub async fn finish(mut self) -> Result<Vec<PathBuf>> {
+        self.rotate().await?;
+        Ok(self.rolling.files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::FileReader;
+    use crate::decode::Decoder;
+    use crate::records::{OhlcvMsg, TradeMsg};
+    use serial_test::serial;
+
+    fn ohlcv(instrument_id: u32, ts_event: u64) -> OhlcvMsg {
+        OhlcvMsg {
+            hd: RecordHeader::new::<OhlcvMsg>(instrument_id, ts_event),
+            open: 100,
+            high: 200,
+            low: 50,
+            close: 150,
+            volume: 1000,
+        }
+    }
+
+    fn mappings() -> SymbolMap {
+        let mut mappings = SymbolMap::new();
+        mappings.add_instrument("AAPL", 1);
+        mappings.add_instrument("TSLA", 2);
+        mappings
+    }
+
+    #[test]
+    #[serial]
+    fn test_rolling_sink_hourly() -> anyhow::Result<()> {
+        let dir = PathBuf::from("tests/rolling_hourly");
+        // 2021-11-01 00:00:00 UTC
+        let midnight = 1_635_724_800_000_000_000;
+        let msgs = [
+            ohlcv(1, midnight + 10),
+            ohlcv(1, midnight + NANOS_PER_HOUR - 1),
+            ohlcv(2, midnight + NANOS_PER_HOUR),
+            ohlcv(2, midnight + 3 * NANOS_PER_HOUR + 5),
+        ];
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+
+        // Test
+        let mut sink = RollingSink::new(&dir, Schema::Ohlcv1S, mappings(), Rotation::Hourly)?;
+        sink.encode_records(&records)?;
+        let trade = TradeMsg {
+            hd: RecordHeader::new::<TradeMsg>(1, midnight),
+            price: 100,
+            size: 1,
+            action: b'T' as i8,
+            side: b'A' as i8,
+            depth: 0,
+            flags: 0,
+            ts_recv: midnight,
+            ts_in_delta: 0,
+            sequence: 0,
+        };
+        let mismatch = sink.encode_record(&(&trade).into());
+        let files = sink.finish()?;
+
+        // Validate
+        assert!(mismatch.is_err());
+        let names: Vec<_> = files
+            .iter()
+            .map(|f| f.file_name().unwrap().to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "ohlcv-1s_20211101T00.bin",
+                "ohlcv-1s_20211101T01.bin",
+                "ohlcv-1s_20211101T03.bin"
+            ]
+        );
+        let mut decoder = Decoder::<FileReader>::from_file(&files[0])?;
+        let metadata = decoder.metadata().unwrap();
+        assert_eq!(metadata.start, msgs[0].hd.ts_event);
+        assert_eq!(metadata.end, msgs[1].hd.ts_event);
+        assert_eq!(metadata.mappings.map.len(), 1);
+        assert_eq!(metadata.mappings.map[&1], "AAPL");
+        assert_eq!(decoder.decode()?.len(), 2);
+        let metadata = Decoder::<FileReader>::from_file(&files[1])?
+            .metadata()
+            .unwrap();
+        assert_eq!(metadata.mappings.map.keys().collect::<Vec<_>>(), vec![&2]);
+        assert_eq!(std::fs::read_dir(&dir)?.count(), 3);
+
+        // Cleanup
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_rolling_sink_drop_with_added_mappings() -> anyhow::Result<()> {
+        let dir = PathBuf::from("tests/rolling_drop");
+        let mut mappings = SymbolMap::new();
+        mappings.add_instrument("AAPL", 1);
+        let mut added = SymbolMap::new();
+        added.add_instrument("A_TICKER_LONG_ENOUGH_TO_GROW_THE_HEADER", 3);
+
+        // Test
+        let mut sink = RollingSink::new(&dir, Schema::Ohlcv1S, mappings, Rotation::Daily)?;
+        sink.encode_record(&(&ohlcv(1, 1)).into())?;
+        sink.add_mappings(&added)?;
+        sink.encode_record(&(&ohlcv(3, 2)).into())?;
+        drop(sink);
+
+        // Validate
+        let files: Vec<PathBuf> = std::fs::read_dir(&dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<io::Result<_>>()?;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "bin");
+        let mut decoder = Decoder::<FileReader>::from_file(&files[0])?;
+        let metadata = decoder.metadata().unwrap();
+        assert_eq!((metadata.start, metadata.end), (1, 2));
+        assert_eq!(metadata.mappings.map.len(), 2);
+        assert_eq!(decoder.decode()?.len(), 2);
+
+        // Cleanup
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_async_rolling_sink_bytes() -> anyhow::Result<()> {
+        let dir = PathBuf::from("tests/rolling_bytes");
+        let record_size = std::mem::size_of::<OhlcvMsg>() as u64;
+        let msgs: Vec<OhlcvMsg> = (0..5).map(|i| ohlcv(1 + i as u32 % 2, i)).collect();
+        let records: Vec<RecordRef> = msgs.iter().map(|m| m.into()).collect();
+
+        // Test
+        let mut sink = AsyncRollingSink::new(
+            &dir,
+            Schema::Ohlcv1S,
+            mappings(),
+            Rotation::Bytes(2 * record_size),
+        )
+        .await?;
+        sink.encode_records(&records).await?;
+        let files = sink.finish().await?;
+
+        // Validate
+        assert_eq!(files.len(), 3);
+        // Records in the same second share a timestamp, later files get a suffix.
+        assert!(files[1].to_str().unwrap().ends_with("_1.bin"));
+        let mut decoded = Vec::new();
+        for file in &files {
+            let mut decoder = Decoder::<FileReader>::from_file(file)?;
+            let metadata = decoder.metadata().unwrap();
+            let records = decoder.decode()?;
+            assert_eq!(metadata.start, records[0].msg().header().ts_event);
+            decoded.extend(records);
+        }
+        assert_eq!(decoded.len(), 5);
+        assert_eq!(
+            Decoder::<FileReader>::from_file(&files[2])?
+                .metadata()
+                .unwrap()
+                .mappings
+                .map
+                .len(),
+            1
+        );
+
+        // Cleanup
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
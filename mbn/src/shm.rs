@@ -0,0 +1,576 @@
+//! Single-producer, multi-consumer ring buffer in shared memory, handing records to other
+//! processes on the same host without sockets.
+//!
+//! The ring is a file, normally under `/dev/shm`, mapped by one `ShmRingWriter` and any number
+//! of `ShmRingReader`s. Each slot holds one record, written from `RecordRef::as_ref()`, and a
+//! stamp used as a seqlock: the writer marks the slot as being written, copies the record and
+//! publishes it with the position it was written at. Readers keep their own cursor and never
+//! block the writer, so a reader that falls more than `capacity` records behind is overrun and
+//! gets `Error::Overrun` before resuming at the oldest record still in the ring.
+//!
+//! Records are stored in host memory order, the ring is only meant for processes on one host.
+use crate::error::{Error, Result};
+use crate::record_enum::RecordEnumRef;
+use crate::record_ref::{AlignedBuffer, RecordRef};
+use crate::records::{BboMsg, MboMsg, Mbp10Msg, Mbp1Msg, OhlcvMsg, RecordHeader, TradeMsg};
+use memmap2::MmapMut;
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::ptr::NonNull;
+use std::slice;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+
+/// Identifies a ring file.
+pub const SHM_RING_MAGIC: &[u8; 4] = b"MBNQ";
+const SHM_RING_VERSION: u32 = 1;
+
+const WORD: usize = mem::size_of::<u64>();
+
+/// Slot size fitting every record type of the crate.
+pub const DEFAULT_SLOT_SIZE: usize = max_record_size();
+
+const fn max_record_size() -> usize {
+    let sizes = [
+        mem::size_of::<Mbp1Msg>(),
+        mem::size_of::<OhlcvMsg>(),
+        mem::size_of::<TradeMsg>(),
+        mem::size_of::<BboMsg>(),
+        mem::size_of::<Mbp10Msg>(),
+        mem::size_of::<MboMsg>(),
+    ];
+    let mut max = 0;
+    let mut i = 0;
+    while i < sizes.len() {
+        if sizes[i] > max {
+            max = sizes[i];
+        }
+        i += 1;
+    }
+    max.next_multiple_of(WORD)
+}
+
+/// Start of the ring file. The write position sits on its own cache line so readers polling
+/// it don't share a line with the static fields.
+#[repr(C)]
+struct RingHeader {
+    magic: [u8; 4],
+    version: u32,
+    capacity: u64,
+    slot_size: u64,
+    _padding: [u8; 40],
+    /// Number of records written, i.e. the position of the next record.
+    write_position: AtomicU64,
+    _write_padding: [u8; 56],
+}
+
+const HEADER_LENGTH: usize = mem::size_of::<RingHeader>();
+
+/// Addresses of the header and slots in a mapped ring.
+struct Ring {
+    base: NonNull<u8>,
+    capacity: u64,
+    slot_size: usize,
+}
+
+impl Ring {
+    /// Length of a ring file, `None` if it overflows a u64.
+    fn file_length(capacity: u64, slot_size: usize) -> Option<u64> {
+        (slot_size as u64)
+            .checked_add(WORD as u64)?
+            .checked_mul(capacity)?
+            .checked_add(HEADER_LENGTH as u64)
+    }
+
+    fn header(&self) -> &RingHeader {
+        // Safety: the mapping is page aligned and at least `HEADER_LENGTH` long.
+        unsafe { &*(self.base.as_ptr() as *const RingHeader) }
+    }
+
+    fn write_position(&self) -> &AtomicU64 {
+        &self.header().write_position
+    }
+
+    /// Stamp and data words of the slot used by `position`.
+    fn slot(&self, position: u64) -> (&AtomicU64, &[AtomicU64]) {
+        let index = (position & (self.capacity - 1)) as usize;
+        let offset = HEADER_LENGTH + index * (WORD + self.slot_size);
+        // Safety: `offset` is 8 byte aligned and the slot lies within the file length checked
+        // when the ring was mapped. Every access to the slot goes through these atomics.
+        unsafe {
+            let stamp = self.base.as_ptr().add(offset) as *const AtomicU64;
+            let words = slice::from_raw_parts(stamp.add(1), self.slot_size / WORD);
+            (&*stamp, words)
+        }
+    }
+}
+
+/// Stamp of a slot holding the record written at `position`. Odd stamps mark a slot being
+/// written.
+fn published(position: u64) -> u64 {
+    2 * position + 2
+}
+
+/// Producer side of a ring. Only one writer may exist per ring file.
+pub struct ShmRingWriter {
+    path: PathBuf,
+    ring: Ring,
+    position: u64,
+    _mmap: MmapMut,
+}
+
+// Safety: the writer owns its mapping, `Ring` only points into it.
+unsafe impl Send for ShmRingWriter {}
+
+impl ShmRingWriter {
+    /// Creates the ring file at `path` with `capacity` slots of `DEFAULT_SLOT_SIZE`. An existing
+    /// ring is replaced, its readers see no further records.
+    pub fn create<P: AsRef<Path>>(path: P, capacity: u64) -> Result<Self> {
+        Self::create_with_slot_size(path, capacity, DEFAULT_SLOT_SIZE)
+    }
+
+    /// Creates the ring with slots of `slot_size` bytes, e.g. the size of the only record type
+    /// a feed publishes. `capacity` must be a power of two and `slot_size` a multiple of 8.
+    pub fn create_with_slot_size<P: AsRef<Path>>(
+        path: P,
+        capacity: u64,
+        slot_size: usize,
+    ) -> Result<Self> {
+        if !capacity.is_power_of_two() {
+            return Err(Error::Encode(format!(
+                "Ring capacity {} is not a power of two",
+                capacity
+            )));
+        }
+        if slot_size < mem::size_of::<RecordHeader>() || !slot_size.is_multiple_of(WORD) {
+            return Err(Error::Encode(format!(
+                "Ring slot size {} is not a multiple of {} holding a record header",
+                slot_size, WORD
+            )));
+        }
+        let length = Ring::file_length(capacity, slot_size).ok_or_else(|| {
+            Error::Encode(format!(
+                "Ring of {} slots of {} bytes exceeds the maximum file length",
+                capacity, slot_size
+            ))
+        })?;
+        // Replace rather than truncate an existing ring, readers still mapping it would fault.
+        match fs::remove_file(path.as_ref()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path.as_ref())?;
+        file.set_len(length)?;
+        // Safety: the file was just created with the full ring length; other processes only
+        // access it through the atomics of `Ring`.
+        let mut mmap = unsafe { MmapMut::map_mut(&file)? };
+        let ring = Ring {
+            base: NonNull::new(mmap.as_mut_ptr()).expect("mapping is not null"),
+            capacity,
+            slot_size,
+        };
+        // Safety: no reader can validate the ring before the magic is written, so the header
+        // is not shared yet.
+        unsafe {
+            let header = &mut *(mmap.as_mut_ptr() as *mut RingHeader);
+            header.version = SHM_RING_VERSION;
+            header.capacity = capacity;
+            header.slot_size = slot_size as u64;
+            header.write_position = AtomicU64::new(0);
+        }
+        fence(Ordering::Release);
+        mmap[..SHM_RING_MAGIC.len()].copy_from_slice(SHM_RING_MAGIC);
+        mmap.flush()?;
+
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            ring,
+            position: 0,
+            _mmap: mmap,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.ring.capacity
+    }
+
+    /// Number of records written, i.e. the position of the next record.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Publishes the record and returns its position. Fails if it is larger than a slot.
+    pub fn write(&mut self, record: &RecordRef) -> Result<u64> {
+        let bytes = record.as_ref();
+        if bytes.len() > self.ring.slot_size {
+            return Err(Error::Encode(format!(
+                "Record of {} bytes does not fit in ring slots of {} bytes",
+                bytes.len(),
+                self.ring.slot_size
+            )));
+        }
+        let position = self.position;
+        let (stamp, words) = self.ring.slot(position);
+        stamp.store(published(position) - 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        for (word, chunk) in words.iter().zip(bytes.chunks(WORD)) {
+            let mut buffer = [0u8; WORD];
+            buffer[..chunk.len()].copy_from_slice(chunk);
+            word.store(u64::from_ne_bytes(buffer), Ordering::Relaxed);
+        }
+        stamp.store(published(position), Ordering::Release);
+        self.position += 1;
+        self.ring
+            .write_position()
+            .store(self.position, Ordering::Release);
+        Ok(position)
+    }
+
+    pub fn write_records(&mut self, records: &[RecordRef]) -> Result<()> {
+        for record in records {
+            self.write(record)?;
+        }
+        Ok(())
+    }
+}
+
+/// Consumer side of a ring, reading from its own cursor.
+pub struct ShmRingReader {
+    ring: Ring,
+    cursor: u64,
+    buffer: AlignedBuffer,
+    _mmap: MmapMut,
+}
+
+// Safety: the reader owns its mapping, `Ring` only points into it.
+unsafe impl Send for ShmRingReader {}
+
+impl ShmRingReader {
+    /// Maps the ring at `path`, starting at the next record written. The file is mapped read
+    /// and write, as atomic loads are only defined on writable memory, but the reader never
+    /// writes to it.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path.as_ref())?;
+        let length = file.metadata()?.len();
+        if length < HEADER_LENGTH as u64 {
+            return Err(Error::Decode(
+                "Ring file is shorter than its header".to_string(),
+            ));
+        }
+        // Safety: the writer never shrinks the file and all shared fields are read through
+        // atomics; the static header fields are written before the magic.
+        let mut mmap = unsafe { MmapMut::map_mut(&file)? };
+        if &mmap[..SHM_RING_MAGIC.len()] != SHM_RING_MAGIC {
+            return Err(Error::Decode("Missing ring signature".to_string()));
+        }
+        fence(Ordering::Acquire);
+        // Safety: the mapping is page aligned and holds the header, checked above.
+        let header = unsafe { &*(mmap.as_ptr() as *const RingHeader) };
+        if header.version != SHM_RING_VERSION {
+            return Err(Error::Decode(format!(
+                "Unsupported ring version {}",
+                header.version
+            )));
+        }
+        let capacity = header.capacity;
+        let slot_size = header.slot_size as usize;
+        if !capacity.is_power_of_two()
+            || slot_size < mem::size_of::<RecordHeader>()
+            || !slot_size.is_multiple_of(WORD)
+        {
+            return Err(Error::Decode("Invalid ring header".to_string()));
+        }
+        match Ring::file_length(capacity, slot_size) {
+            Some(expected) if length >= expected => {}
+            Some(_) => return Err(Error::Decode("Ring file is truncated".to_string())),
+            None => {
+                return Err(Error::Decode(
+                    "Ring header overflows the file length".to_string(),
+                ))
+            }
+        }
+        let ring = Ring {
+            base: NonNull::new(mmap.as_mut_ptr()).expect("mapping is not null"),
+            capacity,
+            slot_size,
+        };
+        let cursor = ring.write_position().load(Ordering::Acquire);
+        let mut buffer = AlignedBuffer::new();
+        buffer.resize(slot_size);
+
+        Ok(Self {
+            ring,
+            cursor,
+            buffer,
+            _mmap: mmap,
+        })
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.ring.capacity
+    }
+
+    /// Position of the next record to read.
+    pub fn cursor(&self) -> u64 {
+        self.cursor
+    }
+
+    /// Moves the cursor, e.g. to `oldest()` to read the records still in the ring.
+    pub fn seek(&mut self, position: u64) {
+        self.cursor = position;
+    }
+
+    /// Position of the oldest record still in the ring.
+    pub fn oldest(&self) -> u64 {
+        self.ring
+            .write_position()
+            .load(Ordering::Acquire)
+            .saturating_sub(self.ring.capacity)
+    }
+
+    /// Records written but not read yet.
+    pub fn lag(&self) -> u64 {
+        self.ring
+            .write_position()
+            .load(Ordering::Acquire)
+            .saturating_sub(self.cursor)
+    }
+
+    /// Returns the next record, or `None` when the reader is caught up with the writer.
+    ///
+    /// Returns `Error::Overrun` if records were overwritten before they were read; the cursor
+    /// then moves to the oldest record left and reading can continue.
+    pub fn next_record(&mut self) -> Result<Option<RecordEnumRef<'_>>> {
+        let write_position = self.ring.write_position().load(Ordering::Acquire);
+        if self.cursor >= write_position {
+            return Ok(None);
+        }
+        if write_position - self.cursor > self.ring.capacity {
+            return Err(self.skip_overrun());
+        }
+
+        let (stamp, words) = self.ring.slot(self.cursor);
+        let expected = published(self.cursor);
+        if stamp.load(Ordering::Acquire) != expected {
+            return Err(self.skip_overrun());
+        }
+        // The length may be torn if the slot is overwritten meanwhile, the stamp check below
+        // discards the copy in that case.
+        let first = words[0].load(Ordering::Relaxed).to_ne_bytes();
+        let length = (first[0] as usize * RecordHeader::LENGTH_MULTIPLIER).min(self.ring.slot_size);
+        self.buffer.resize(length);
+        for (chunk, word) in self.buffer.as_mut_slice().chunks_mut(WORD).zip(words) {
+            chunk.copy_from_slice(&word.load(Ordering::Relaxed).to_ne_bytes()[..chunk.len()]);
+        }
+        fence(Ordering::Acquire);
+        if stamp.load(Ordering::Relaxed) != expected {
+            return Err(self.skip_overrun());
+        }
+        let position = self.cursor;
+        self.cursor += 1;
+
+        let record = RecordRef::try_new_opaque(self.buffer.as_slice()).map_err(|e| {
+            Error::Decode(format!(
+                "Invalid record at ring position {}: {}",
+                position, e
+            ))
+        })?;
+        RecordEnumRef::from_ref(record)
+            .map(Some)
+            .ok_or(Error::InvalidRecordType("ring record"))
+    }
+
+    /// Moves past the records overwritten since the cursor and reports how many were missed.
+    fn skip_overrun(&mut self) -> Error {
+        let oldest = self.oldest().max(self.cursor + 1);
+        let missed = oldest - self.cursor;
+        self.cursor = oldest;
+        Error::Overrun { missed }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record_enum::RecordEnum;
+    use crate::records::{OhlcvMsg, Record};
+    use serial_test::serial;
+
+    fn ohlcv(i: u64) -> OhlcvMsg {
+        OhlcvMsg {
+            hd: RecordHeader::new::<OhlcvMsg>(1, i),
+            open: 100,
+            high: 200,
+            low: 50,
+            close: 150,
+            volume: i,
+        }
+    }
+
+    fn read_all(reader: &mut ShmRingReader) -> Result<Vec<RecordEnum>> {
+        let mut records = Vec::new();
+        while let Some(record) = reader.next_record()? {
+            records.push(record.to_owned());
+        }
+        Ok(records)
+    }
+
+    #[test]
+    #[serial]
+    fn test_shm_ring_multiple_readers() -> anyhow::Result<()> {
+        let path = PathBuf::from("tests/ring.shm");
+        let mut writer = ShmRingWriter::create(&path, 16)?;
+        let mut first = ShmRingReader::open(&path)?;
+        let mut second = ShmRingReader::open(&path)?;
+        let msgs: Vec<OhlcvMsg> = (0..10).map(ohlcv).collect();
+
+        // Test
+        for msg in &msgs[..5] {
+            writer.write(&RecordRef::from(msg))?;
+        }
+        let first_records = read_all(&mut first)?;
+        for msg in &msgs[5..] {
+            writer.write(&RecordRef::from(msg))?;
+        }
+        let second_records = read_all(&mut second)?;
+        let first_rest = read_all(&mut first)?;
+        let late = ShmRingReader::open(&path)?;
+
+        // Validate
+        let expected: Vec<RecordEnum> = msgs.into_iter().map(RecordEnum::Ohlcv).collect();
+        assert_eq!(first_records, expected[..5]);
+        assert_eq!(first_rest, expected[5..]);
+        assert_eq!(second_records, expected);
+        assert_eq!(late.cursor(), 10);
+        assert_eq!(late.lag(), 0);
+
+        // Cleanup
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_shm_ring_overrun() -> anyhow::Result<()> {
+        let path = PathBuf::from("tests/ring_overrun.shm");
+        let mut writer = ShmRingWriter::create(&path, 4)?;
+        let mut reader = ShmRingReader::open(&path)?;
+
+        // Test
+        for i in 0..10 {
+            writer.write(&RecordRef::from(&ohlcv(i)))?;
+        }
+        let overrun = reader.next_record().map(|r| r.map(|r| r.to_owned()));
+        let records = read_all(&mut reader)?;
+        let too_large = ShmRingWriter::create_with_slot_size(
+            "tests/ring_small.shm",
+            4,
+            mem::size_of::<RecordHeader>(),
+        )?
+        .write(&RecordRef::from(&ohlcv(0)));
+
+        // Validate
+        assert!(matches!(overrun, Err(Error::Overrun { missed: 6 })));
+        let volumes: Vec<u64> = records
+            .iter()
+            .map(|r| match r {
+                RecordEnum::Ohlcv(msg) => msg.volume,
+                _ => panic!("Unexpected record"),
+            })
+            .collect();
+        assert_eq!(volumes, vec![6, 7, 8, 9]);
+        assert!(too_large.is_err());
+        assert!(ShmRingWriter::create("tests/ring_small.shm", 3).is_err());
+
+        // Cleanup
+        fs::remove_file(&path)?;
+        fs::remove_file("tests/ring_small.shm")?;
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_shm_ring_invalid_header() -> anyhow::Result<()> {
+        let path = PathBuf::from("tests/ring_invalid.shm");
+        drop(ShmRingWriter::create(&path, 4)?);
+        let valid = fs::read(&path)?;
+        // `capacity` and `slot_size` are the u64s at bytes 8 and 16 of the header.
+        let with_header =
+            |capacity: u64, slot_size: u64| -> anyhow::Result<Result<ShmRingReader>> {
+                let mut bytes = valid.clone();
+                bytes[8..16].copy_from_slice(&capacity.to_ne_bytes());
+                bytes[16..24].copy_from_slice(&slot_size.to_ne_bytes());
+                fs::write(&path, bytes)?;
+                Ok(ShmRingReader::open(&path))
+            };
+
+        // Test
+        let empty_slots = with_header(4, 0)?;
+        let overflow = with_header(1 << 62, DEFAULT_SLOT_SIZE as u64)?;
+        let truncated = with_header(8, DEFAULT_SLOT_SIZE as u64)?;
+
+        // Validate
+        assert!(matches!(empty_slots, Err(Error::Decode(_))));
+        assert!(matches!(overflow, Err(Error::Decode(_))));
+        assert!(matches!(truncated, Err(Error::Decode(_))));
+        assert!(ShmRingWriter::create(&path, 1 << 62).is_err());
+
+        // Cleanup
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_shm_ring_concurrent_reader() -> anyhow::Result<()> {
+        let path = PathBuf::from("tests/ring_concurrent.shm");
+        let mut writer = ShmRingWriter::create(&path, 64)?;
+        let mut reader = ShmRingReader::open(&path)?;
+        let total = 100_000;
+
+        // Test
+        let producer = std::thread::spawn(move || -> Result<()> {
+            for i in 0..total {
+                writer.write(&RecordRef::from(&ohlcv(i)))?;
+            }
+            Ok(())
+        });
+        let mut received = 0;
+        let mut missed = 0;
+        let mut last = None;
+        while received + missed < total {
+            match reader.next_record() {
+                Ok(Some(record)) => {
+                    let ts = record.header().ts_event;
+                    assert!(last.is_none_or(|last| ts > last), "{} after {:?}", ts, last);
+                    last = Some(ts);
+                    received += 1;
+                }
+                Ok(None) => std::hint::spin_loop(),
+                Err(Error::Overrun { missed: n }) => missed += n,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        producer.join().unwrap()?;
+
+        // Validate
+        assert_eq!(received + missed, total);
+        assert_eq!(last, Some(total - 1));
+
+        // Cleanup
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,849 @@
+//! Async client for live MBN record streams over TCP or Unix sockets.
+//!
+//! `Subscriber` decodes records with `AsyncRecordDecoder` and reconnects with exponential
+//! backoff when the connection drops. Per-instrument `sequence` numbers of `Mbp1Msg`,
+//! `TradeMsg` and `BboMsg` are tracked across connections: gaps are reported as
+//! `SequenceEvent::Gap` and records already seen, e.g. resent by the server after a reconnect,
+//! are dropped and reported as `SequenceEvent::Duplicate`. A first record after a reconnect
+//! that is further behind than the reset threshold is taken as a server restart, reported as
+//! `SequenceEvent::Reset` and kept. Feeds that restart their sequence numbers on every
+//! connection can reset the tracking with `with_reset_on_reconnect`.
+use crate::decode::{AsyncMetadataDecoder, AsyncRecordDecoder, UnknownRecordPolicy};
+use crate::error::{Error, Result};
+use crate::metadata::Metadata;
+use crate::record_enum::RecordEnum;
+use crate::replay::Subscription;
+use futures::future::BoxFuture;
+use futures::{FutureExt, Stream};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, BufReader};
+
+#[cfg(feature = "python")]
+use pyo3::pyclass;
+
+/// Address of a record stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// `host:port`
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl From<&str> for Endpoint {
+    /// Addresses prefixed with `unix:` are Unix socket paths, anything else is `host:port`.
+    fn from(address: &str) -> Self {
+        #[cfg(unix)]
+        if let Some(path) = address.strip_prefix("unix:") {
+            return Endpoint::Unix(PathBuf::from(path));
+        }
+        Endpoint::Tcp(address.to_string())
+    }
+}
+
+trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+type Connection = AsyncRecordDecoder<BufReader<Box<dyn Transport>>>;
+
+impl Endpoint {
+    async fn connect(&self) -> Result<Box<dyn Transport>> {
+        Ok(match self {
+            Endpoint::Tcp(address) => {
+                let stream = tokio::net::TcpStream::connect(address).await?;
+                stream.set_nodelay(true)?;
+                Box::new(stream)
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => Box::new(tokio::net::UnixStream::connect(path).await?),
+        })
+    }
+}
+
+/// Delay between connection attempts, doubling from `initial` up to `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    /// Consecutive failed attempts before the stream gives up, `None` to retry forever.
+    pub max_retries: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(5),
+            max_retries: None,
+        }
+    }
+}
+
+impl Backoff {
+    fn delay(&self, attempt: u32) -> Duration {
+        self.initial
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max)
+    }
+}
+
+/// Irregularity in the `sequence` numbers of an instrument.
+#[cfg_attr(feature = "python", pyclass(module = "mbn", eq))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SequenceEvent {
+    /// Records between `expected` and `received` were missed.
+    Gap {
+        instrument_id: u32,
+        expected: u32,
+        received: u32,
+    },
+    /// A record at or before the last sequence seen was received again.
+    Duplicate { instrument_id: u32, sequence: u32 },
+    /// The first record after a reconnect was far behind `last`, so the server is taken to have
+    /// restarted its sequence numbers. Tracking continues from `received`.
+    Reset {
+        instrument_id: u32,
+        last: u32,
+        received: u32,
+    },
+}
+
+/// Sequence numbers a first record after a reconnect may be behind before it counts as a reset
+/// rather than a duplicate, when none is given.
+pub const DEFAULT_RESET_THRESHOLD: u32 = 1024;
+
+/// Last `sequence` seen per instrument.
+#[derive(Debug)]
+pub struct SequenceTracker {
+    last: HashMap<u32, u32>,
+    /// Instruments without a record since the last reconnect.
+    reconnected: HashSet<u32>,
+    reset_threshold: u32,
+}
+
+impl Default for SequenceTracker {
+    fn default() -> Self {
+        Self {
+            last: HashMap::new(),
+            reconnected: HashSet::new(),
+            reset_threshold: DEFAULT_RESET_THRESHOLD,
+        }
+    }
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how far behind the last sequence a first record after a reconnect has to be to be
+    /// reported as `SequenceEvent::Reset`, `DEFAULT_RESET_THRESHOLD` by default.
+    pub fn with_reset_threshold(mut self, reset_threshold: u32) -> Self {
+        self.reset_threshold = reset_threshold;
+        self
+    }
+
+    /// Checks the record against the last sequence of its instrument. Returns `None` for
+    /// records in order and records without a sequence number.
+    pub fn check(&mut self, record: &RecordEnum) -> Option<SequenceEvent> {
+        let sequence = match record {
+            RecordEnum::Mbp1(msg) | RecordEnum::Tbbo(msg) => msg.sequence,
+            RecordEnum::Trade(msg) => msg.sequence,
+            RecordEnum::Bbo(msg) => msg.sequence,
+            _ => return None,
+        };
+        let instrument_id = record.msg().header().instrument_id;
+        let Some(last) = self.last.get_mut(&instrument_id) else {
+            self.last.insert(instrument_id, sequence);
+            return None;
+        };
+        let after_reconnect = self.reconnected.remove(&instrument_id);
+        // Sequence numbers wrap around after `u32::MAX`, so they are compared as serial
+        // numbers: `sequence` is behind `last` if their wrapping difference is not positive.
+        let expected = last.wrapping_add(1);
+        if sequence.wrapping_sub(*last) as i32 <= 0 {
+            if after_reconnect && last.wrapping_sub(sequence) > self.reset_threshold {
+                let event = SequenceEvent::Reset {
+                    instrument_id,
+                    last: *last,
+                    received: sequence,
+                };
+                *last = sequence;
+                return Some(event);
+            }
+            return Some(SequenceEvent::Duplicate {
+                instrument_id,
+                sequence,
+            });
+        }
+        *last = sequence;
+        (sequence != expected).then_some(SequenceEvent::Gap {
+            instrument_id,
+            expected,
+            received: sequence,
+        })
+    }
+
+    /// Forgets the last sequence of every instrument, e.g. after the server restarted.
+    pub fn reset(&mut self) {
+        self.last.clear();
+        self.reconnected.clear();
+    }
+
+    /// Marks the start of a new connection, after which the first record of each instrument
+    /// may be reported as a reset.
+    pub fn reconnected(&mut self) {
+        self.reconnected = self.last.keys().copied().collect();
+    }
+}
+
+enum State {
+    Idle,
+    Connecting(BoxFuture<'static, Result<(Option<Metadata>, Connection)>>),
+    Streaming(Connection),
+    Done,
+}
+
+/// Stream of the records of a live feed, reconnecting transparently when the connection drops.
+///
+/// Connection errors are logged and retried according to the `Backoff`. Connections that fail
+/// or close before delivering a record count as failed attempts, and the stream yields an
+/// error and ends once `Backoff::max_retries` consecutive attempts failed. Records that fail to
+/// decode, e.g. an unknown rtype with `UnknownRecordPolicy::Error`, are yielded as errors and
+/// the stream continues with the next record. A record length shorter than its header
+/// (`Error::Framing`) leaves no way to find the next record, so the connection is restarted.
+pub struct Subscriber {
+    /// Metadata of the last handshake, with `with_handshake(true)`.
+    pub metadata: Option<Metadata>,
+    endpoint: Endpoint,
+    backoff: Backoff,
+    subscription: Option<Subscription>,
+    handshake: bool,
+    reconnect_on_eof: bool,
+    reset_on_reconnect: bool,
+    policy: UnknownRecordPolicy,
+    tracker: SequenceTracker,
+    events: Vec<SequenceEvent>,
+    reconnects: u64,
+    /// Consecutive connection attempts that failed or delivered no record.
+    failures: u32,
+    state: State,
+}
+
+impl Subscriber {
+    /// Connects lazily, on the first poll of the stream.
+    pub fn new(endpoint: impl Into<Endpoint>) -> Self {
+        Self {
+            metadata: None,
+            endpoint: endpoint.into(),
+            backoff: Backoff::default(),
+            subscription: None,
+            handshake: false,
+            reconnect_on_eof: true,
+            reset_on_reconnect: false,
+            policy: UnknownRecordPolicy::default(),
+            tracker: SequenceTracker::new(),
+            events: Vec::new(),
+            reconnects: 0,
+            failures: 0,
+            state: State::Idle,
+        }
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Sends the subscription on every connection, as expected by `ReplayServer`.
+    pub fn with_subscription(mut self, subscription: Subscription) -> Self {
+        self.subscription = Some(subscription);
+        self
+    }
+
+    /// Expects the metadata header before the records of every connection.
+    pub fn with_handshake(mut self, handshake: bool) -> Self {
+        self.handshake = handshake;
+        self
+    }
+
+    /// Reconnects when the server closes the connection, `true` by default. Without it the
+    /// stream ends with the connection.
+    pub fn with_reconnect_on_eof(mut self, reconnect_on_eof: bool) -> Self {
+        self.reconnect_on_eof = reconnect_on_eof;
+        self
+    }
+
+    /// Forgets the sequence numbers seen so far on every reconnect, `false` by default. Records
+    /// resent after a reconnect are then no longer detected as duplicates.
+    pub fn with_reset_on_reconnect(mut self, reset_on_reconnect: bool) -> Self {
+        self.reset_on_reconnect = reset_on_reconnect;
+        self
+    }
+
+    /// Sets how far behind the last sequence of its instrument a first record after a
+    /// reconnect has to be to count as a server restart, see `SequenceEvent::Reset`.
+    /// `DEFAULT_RESET_THRESHOLD` by default.
+    pub fn with_reset_threshold(mut self, reset_threshold: u32) -> Self {
+        self.tracker = self.tracker.with_reset_threshold(reset_threshold);
+        self
+    }
+
+    /// Sets how records with an unknown rtype are handled, `UnknownRecordPolicy::Error` by default.
+    pub fn with_unknown_policy(mut self, policy: UnknownRecordPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn metadata(&self) -> Option<Metadata> {
+        self.metadata.clone()
+    }
+
+    /// Number of times the connection was re-established.
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects
+    }
+
+    /// Returns the gaps, duplicates and resets detected since the last call.
+    pub fn take_events(&mut self) -> Vec<SequenceEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Forgets the sequence numbers seen so far, e.g. once the feed is known to have restarted
+    /// its sequence numbers.
+    pub fn reset_sequences(&mut self) {
+        self.tracker.reset();
+    }
+
+    /// Starts a connection attempt, after the backoff delay if the previous attempts failed.
+    fn connect(&self) -> BoxFuture<'static, Result<(Option<Metadata>, Connection)>> {
+        let endpoint = self.endpoint.clone();
+        let delay = self
+            .failures
+            .checked_sub(1)
+            .map(|attempt| self.backoff.delay(attempt));
+        let subscription = self.subscription.clone();
+        let handshake = self.handshake;
+        let policy = self.policy;
+        async move {
+            if let Some(delay) = delay {
+                tokio::time::sleep(delay).await;
+            }
+            connect_once(&endpoint, subscription.as_ref(), handshake, policy).await
+        }
+        .boxed()
+    }
+
+    /// Counts a failed attempt and starts the next one, or returns `error` once
+    /// `Backoff::max_retries` is reached.
+    fn retry(&mut self, error: Error) -> Option<Error> {
+        if self
+            .backoff
+            .max_retries
+            .is_some_and(|max| self.failures >= max)
+        {
+            return Some(error);
+        }
+        tracing::warn!(
+            "Connection to {:?} failed, retrying in {:?}: {}",
+            self.endpoint,
+            self.backoff.delay(self.failures),
+            error
+        );
+        self.failures += 1;
+        self.state = State::Connecting(self.connect());
+        None
+    }
+
+    /// Reconnects after the connection failed or was closed by the server.
+    fn reconnect(&mut self, error: Error) -> Option<Error> {
+        if self.reset_on_reconnect {
+            self.tracker.reset();
+        } else {
+            self.tracker.reconnected();
+        }
+        let failed = self.retry(error);
+        if failed.is_none() {
+            self.reconnects += 1;
+        }
+        failed
+    }
+}
+
+async fn connect_once(
+    endpoint: &Endpoint,
+    subscription: Option<&Subscription>,
+    handshake: bool,
+    policy: UnknownRecordPolicy,
+) -> Result<(Option<Metadata>, Connection)> {
+    let mut transport = endpoint.connect().await?;
+    if let Some(subscription) = subscription {
+        subscription.write_to(&mut transport).await?;
+    }
+    let mut reader = BufReader::new(transport);
+    if subscription.is_some() && handshake {
+        Subscription::read_rejection(&mut reader).await?;
+    }
+    let (metadata, data_offset) = if handshake {
+        let mut metadata_decoder = AsyncMetadataDecoder::new(&mut reader);
+        let metadata = metadata_decoder.decode().await?;
+        if metadata.is_none() {
+            return Err(Error::Decode(
+                "Connection closed before the metadata handshake".to_string(),
+            ));
+        }
+        (metadata, metadata_decoder.data_offset())
+    } else {
+        (None, 0)
+    };
+    let mut decoder = AsyncRecordDecoder::new(reader).with_unknown_policy(policy);
+    decoder.set_position(data_offset);
+    Ok((metadata, decoder))
+}
+
+impl Stream for Subscriber {
+    type Item = Result<RecordEnum>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            let failed = match &mut this.state {
+                State::Idle => {
+                    this.state = State::Connecting(this.connect());
+                    continue;
+                }
+                State::Connecting(connecting) => match ready!(connecting.as_mut().poll(cx)) {
+                    Ok((metadata, connection)) => {
+                        if metadata.is_some() {
+                            this.metadata = metadata;
+                        }
+                        this.state = State::Streaming(connection);
+                        continue;
+                    }
+                    Err(e) => this.retry(e),
+                },
+                State::Streaming(connection) => match ready!(connection.poll_next_record(cx)) {
+                    Some(Ok(record)) => {
+                        this.failures = 0;
+                        match this.tracker.check(&record) {
+                            Some(event @ SequenceEvent::Duplicate { .. }) => {
+                                this.events.push(event);
+                                continue;
+                            }
+                            Some(event) => {
+                                tracing::warn!(
+                                    "Sequence irregularity on {:?}: {:?}",
+                                    this.endpoint,
+                                    event
+                                );
+                                this.events.push(event);
+                            }
+                            None => {}
+                        }
+                        return Poll::Ready(Some(Ok(record)));
+                    }
+                    Some(Err(e)) if e.kind() == io::ErrorKind::InvalidData => {
+                        match Error::from(e) {
+                            e @ Error::Framing { .. } => this.reconnect(e),
+                            e => return Poll::Ready(Some(Err(e))),
+                        }
+                    }
+                    Some(Err(e)) => this.reconnect(e.into()),
+                    None if this.reconnect_on_eof => this.reconnect(Error::Io(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Connection closed by the server",
+                    ))),
+                    None => {
+                        this.state = State::Done;
+                        continue;
+                    }
+                },
+                State::Done => return Poll::Ready(None),
+            };
+            if let Some(e) = failed {
+                this.state = State::Done;
+                return Poll::Ready(Some(Err(e)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::RecordEncoder;
+    use crate::enums::{Schema, Side};
+    use crate::records::{RecordHeader, TradeMsg};
+    use crate::replay::ReplayServer;
+    use crate::symbols::SymbolMap;
+    use futures::StreamExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    fn trade(instrument_id: u32, sequence: u32) -> RecordEnum {
+        RecordEnum::Trade(TradeMsg {
+            hd: RecordHeader::new::<TradeMsg>(instrument_id, sequence as u64),
+            price: 100,
+            size: 10,
+            action: 1,
+            side: Side::Bid.into(),
+            depth: 0,
+            flags: 0,
+            ts_recv: sequence as u64,
+            ts_in_delta: 0,
+            sequence,
+        })
+    }
+
+    fn encode(sequences: &[u32]) -> anyhow::Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        let mut encoder = RecordEncoder::new(&mut buffer);
+        for sequence in sequences {
+            let record = trade(1, *sequence);
+            encoder.encode_record(&record.to_record_ref())?;
+        }
+        Ok(buffer)
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_reconnect_gaps_duplicates() -> anyhow::Result<()> {
+        // The second connection resends 3 and 4 and misses 6.
+        let addr = serve(vec![encode(&[1, 2, 3, 4])?, encode(&[3, 4, 5, 7, 8])?]).await?;
+        let mut subscriber = Subscriber::new(addr.as_str());
+
+        // Test
+        let mut sequences = Vec::new();
+        while sequences.len() < 7 {
+            match subscriber.next().await.unwrap()? {
+                RecordEnum::Trade(msg) => sequences.push(msg.sequence),
+                record => panic!("Unexpected record {:?}", record),
+            }
+        }
+
+        // Validate
+        assert_eq!(sequences, vec![1, 2, 3, 4, 5, 7, 8]);
+        assert_eq!(subscriber.reconnects(), 1);
+        assert_eq!(
+            subscriber.take_events(),
+            vec![
+                SequenceEvent::Duplicate {
+                    instrument_id: 1,
+                    sequence: 3
+                },
+                SequenceEvent::Duplicate {
+                    instrument_id: 1,
+                    sequence: 4
+                },
+                SequenceEvent::Gap {
+                    instrument_id: 1,
+                    expected: 6,
+                    received: 7
+                },
+            ]
+        );
+        assert!(subscriber.take_events().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequence_tracker_wraps() {
+        let mut tracker = SequenceTracker::new();
+
+        // Test
+        let events: Vec<_> = [u32::MAX - 1, u32::MAX, 0, 2, 1]
+            .into_iter()
+            .map(|sequence| tracker.check(&trade(1, sequence)))
+            .collect();
+        let mut tracker = SequenceTracker::new();
+        tracker.check(&trade(2, u32::MAX - 1));
+        let wrapped_gap = tracker.check(&trade(2, 5));
+        let wrapped_duplicate = tracker.check(&trade(2, u32::MAX - 2));
+
+        // Validate
+        assert_eq!(
+            events,
+            vec![
+                None,
+                None,
+                None,
+                Some(SequenceEvent::Gap {
+                    instrument_id: 1,
+                    expected: 1,
+                    received: 2
+                }),
+                Some(SequenceEvent::Duplicate {
+                    instrument_id: 1,
+                    sequence: 1
+                }),
+            ]
+        );
+        assert_eq!(
+            wrapped_gap,
+            Some(SequenceEvent::Gap {
+                instrument_id: 2,
+                expected: u32::MAX,
+                received: 5
+            })
+        );
+        assert_eq!(
+            wrapped_duplicate,
+            Some(SequenceEvent::Duplicate {
+                instrument_id: 2,
+                sequence: u32::MAX - 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_sequence_tracker_reset_after_reconnect() {
+        let mut tracker = SequenceTracker::new().with_reset_threshold(10);
+
+        // Test
+        tracker.check(&trade(1, 100));
+        let before_reconnect = tracker.check(&trade(1, 1));
+        tracker.reconnected();
+        let resent = tracker.check(&trade(1, 95));
+        tracker.reconnected();
+        let restarted = tracker.check(&trade(1, 1));
+        let next = tracker.check(&trade(1, 2));
+
+        // Validate
+        assert_eq!(
+            before_reconnect,
+            Some(SequenceEvent::Duplicate {
+                instrument_id: 1,
+                sequence: 1
+            })
+        );
+        assert_eq!(
+            resent,
+            Some(SequenceEvent::Duplicate {
+                instrument_id: 1,
+                sequence: 95
+            })
+        );
+        assert_eq!(
+            restarted,
+            Some(SequenceEvent::Reset {
+                instrument_id: 1,
+                last: 100,
+                received: 1
+            })
+        );
+        assert_eq!(next, None);
+    }
+
+    /// Serves `connections` one after the other, then keeps the listener open without
+    /// accepting.
+    async fn serve(connections: Vec<Vec<u8>>) -> anyhow::Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            for bytes in connections {
+                let (mut stream, _) = listener.accept().await?;
+                stream.write_all(&bytes).await?;
+            }
+            std::future::pending::<anyhow::Result<()>>().await
+        });
+        Ok(addr.to_string())
+    }
+
+    fn fast_backoff(max_retries: Option<u32>) -> Backoff {
+        Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(5),
+            max_retries,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_reset_on_reconnect() -> anyhow::Result<()> {
+        // The server restarts its sequence numbers with the second connection.
+        let addr = serve(vec![encode(&[1, 2, 3])?, encode(&[1, 2])?]).await?;
+        let mut subscriber = Subscriber::new(addr.as_str())
+            .with_backoff(fast_backoff(None))
+            .with_reset_on_reconnect(true);
+
+        // Test
+        let mut sequences = Vec::new();
+        while sequences.len() < 5 {
+            match subscriber.next().await.unwrap()? {
+                RecordEnum::Trade(msg) => sequences.push(msg.sequence),
+                record => panic!("Unexpected record {:?}", record),
+            }
+        }
+
+        // Validate
+        assert_eq!(sequences, vec![1, 2, 3, 1, 2]);
+        assert!(subscriber.take_events().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_detects_restart() -> anyhow::Result<()> {
+        // The server restarts its sequence numbers with the second connection.
+        let addr = serve(vec![encode(&[1, 2, 3, 4, 5])?, encode(&[1, 2])?]).await?;
+        let mut subscriber = Subscriber::new(addr.as_str())
+            .with_backoff(fast_backoff(None))
+            .with_reset_threshold(2);
+
+        // Test
+        let mut sequences = Vec::new();
+        while sequences.len() < 7 {
+            match subscriber.next().await.unwrap()? {
+                RecordEnum::Trade(msg) => sequences.push(msg.sequence),
+                record => panic!("Unexpected record {:?}", record),
+            }
+        }
+
+        // Validate
+        assert_eq!(sequences, vec![1, 2, 3, 4, 5, 1, 2]);
+        assert_eq!(
+            subscriber.take_events(),
+            vec![SequenceEvent::Reset {
+                instrument_id: 1,
+                last: 5,
+                received: 1
+            }]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_gives_up_on_empty_connections() -> anyhow::Result<()> {
+        // Every connection is accepted and closed before sending a record.
+        let addr = serve(vec![Vec::new(); 3]).await?;
+        let mut subscriber = Subscriber::new(addr.as_str()).with_backoff(fast_backoff(Some(2)));
+
+        // Test
+        let first = tokio::time::timeout(Duration::from_secs(5), subscriber.next()).await?;
+        let second = subscriber.next().await;
+
+        // Validate
+        assert!(matches!(first, Some(Err(_))));
+        assert!(second.is_none());
+        assert_eq!(subscriber.reconnects(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_yields_decode_errors() -> anyhow::Result<()> {
+        let mut bytes = encode(&[1])?;
+        let mut unknown = encode(&[2])?;
+        unknown[1] = 0xFF;
+        bytes.extend(unknown);
+        bytes.extend(encode(&[3])?);
+        let addr = serve(vec![bytes]).await?;
+        let mut subscriber = Subscriber::new(addr.as_str()).with_reconnect_on_eof(false);
+
+        // Test
+        let results: Vec<_> = subscriber.by_ref().collect().await;
+
+        // Validate
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok());
+        let err = results[1].as_ref().unwrap_err().to_string();
+        assert!(err.contains("unknown record type"), "{}", err);
+        assert!(matches!(&results[2], Ok(RecordEnum::Trade(msg)) if msg.sequence == 3));
+        assert_eq!(subscriber.reconnects(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_reconnects_on_framing_error() -> anyhow::Result<()> {
+        // A zero length byte followed by bytes that are not at a record boundary.
+        let mut bytes = encode(&[1])?;
+        bytes.push(0);
+        bytes.extend(&encode(&[2])?[3..]);
+        let addr = serve(vec![bytes, encode(&[2, 3])?]).await?;
+        let mut subscriber = Subscriber::new(addr.as_str()).with_backoff(fast_backoff(None));
+
+        // Test
+        let mut sequences = Vec::new();
+        while sequences.len() < 3 {
+            match subscriber.next().await.unwrap()? {
+                RecordEnum::Trade(msg) => sequences.push(msg.sequence),
+                record => panic!("Unexpected record {:?}", record),
+            }
+        }
+
+        // Validate
+        assert_eq!(sequences, vec![1, 2, 3]);
+        assert_eq!(subscriber.reconnects(), 1);
+        assert!(subscriber.take_events().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_replay_server() -> anyhow::Result<()> {
+        let mut mappings = SymbolMap::new();
+        mappings.add_instrument("AAPL", 1);
+        mappings.add_instrument("TSLA", 2);
+        let metadata = Metadata::new(Schema::Trade, 0, 10, mappings);
+        let records = (1..=10).map(|i| trade(1 + i % 2, i)).collect();
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(ReplayServer::new(metadata, records).serve_tcp(listener));
+
+        // Test
+        let mut subscriber = Subscriber::new(addr.to_string().as_str())
+            .with_subscription(Subscription::all().with_ticker("AAPL"))
+            .with_handshake(true)
+            .with_reconnect_on_eof(false);
+        let mut received = Vec::new();
+        while let Some(record) = subscriber.next().await {
+            received.push(record?);
+        }
+        let rejected = Subscriber::new(addr.to_string().as_str())
+            .with_subscription(Subscription::all().with_ticker("MSFT"))
+            .with_handshake(true)
+            .with_backoff(fast_backoff(Some(0)))
+            .next()
+            .await;
+
+        // Validate
+        assert_eq!(received.len(), 5);
+        assert!(received.iter().all(|r| r.msg().header().instrument_id == 1));
+        assert_eq!(
+            subscriber
+                .metadata()
+                .unwrap()
+                .mappings
+                .get_instrument_ticker(1),
+            Some("AAPL".to_string())
+        );
+        // Trades of instrument 1 are every other sequence number.
+        assert_eq!(subscriber.take_events().len(), 4);
+        assert_eq!(subscriber.reconnects(), 0);
+        let err = rejected.unwrap().unwrap_err().to_string();
+        assert!(
+            err.contains("Subscription rejected: Unknown ticker MSFT"),
+            "{}",
+            err
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_gives_up() -> anyhow::Result<()> {
+        // Bind then drop to get a port nothing listens on.
+        let addr = TcpListener::bind("127.0.0.1:0").await?.local_addr()?;
+        let mut subscriber =
+            Subscriber::new(addr.to_string().as_str()).with_backoff(fast_backoff(Some(2)));
+
+        // Test
+        let first = subscriber.next().await;
+        let second = subscriber.next().await;
+
+        // Validate
+        assert!(matches!(first, Some(Err(_))));
+        assert!(second.is_none());
+        Ok(())
+    }
+}
@@ -129,6 +129,23 @@ impl SymbolMap {
         self.map.extend(other.map.clone());
     }
 
+    /// Merges another SymbolMap into this one, failing without changes if an instrument id is
+    /// mapped to a different ticker in each.
+    pub fn try_merge(&mut self, other: &SymbolMap) -> Result<()> {
+        for (id, ticker) in &other.map {
+            if let Some(existing) = self.map.get(id) {
+                if existing != ticker {
+                    return Err(Error::CustomError(format!(
+                        "Instrument id {} maps to both {} and {}",
+                        id, existing, ticker
+                    )));
+                }
+            }
+        }
+        self.merge(other);
+        Ok(())
+    }
+
     /// Binary encodes struct for response, shouldn't be used directly.
     pub fn serialize(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
@@ -145,31 +162,26 @@ impl SymbolMap {
 
     pub fn deserialize(bytes: &[u8], offset: &mut usize) -> io::Result<Self> {
         // Deserialize the length of the map (stored as a u32)
-        let map_len =
-            u32::from_le_bytes(bytes[*offset..*offset + 4].try_into().map_err(|_| {
-                io::Error::new(io::ErrorKind::InvalidData, "Failed to read map length")
-            })?) as usize;
-        *offset += 4;
+        let map_len = read_u32(bytes, offset, "Failed to read map length")? as usize;
 
-        let mut map = HashMap::with_capacity(map_len);
+        // Each entry is at least 8 bytes, so cap the allocation by what the buffer can hold.
+        let mut map = HashMap::with_capacity(map_len.min(bytes.len() / 8));
 
         // Deserialize each key-value pair in the map
         for _ in 0..map_len {
-            let key =
-                u32::from_le_bytes(bytes[*offset..*offset + 4].try_into().map_err(|_| {
-                    io::Error::new(io::ErrorKind::InvalidData, "Failed to read key")
-                })?);
-            *offset += 4;
+            let key = read_u32(bytes, offset, "Failed to read key")?;
 
             // Read the length of the value string (stored as u32)
-            let value_len =
-                u32::from_le_bytes(bytes[*offset..*offset + 4].try_into().map_err(|_| {
-                    io::Error::new(io::ErrorKind::InvalidData, "Failed to read value length")
-                })?) as usize;
-            *offset += 4;
+            let value_len = read_u32(bytes, offset, "Failed to read value length")? as usize;
 
             // Extract the string value of `value_len` bytes
-            let value = String::from_utf8(bytes[*offset..*offset + value_len].to_vec())
+            let value_bytes = offset
+                .checked_add(value_len)
+                .and_then(|end| bytes.get(*offset..end))
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "Failed to read value")
+                })?;
+            let value = String::from_utf8(value_bytes.to_vec())
                 .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
             *offset += value_len;
 
@@ -180,6 +192,15 @@ impl SymbolMap {
     }
 }
 
+/// Reads a little-endian u32 at `offset`, advancing it, without panicking on short input.
+fn read_u32(bytes: &[u8], offset: &mut usize, msg: &str) -> io::Result<u32> {
+    let slice = bytes
+        .get(*offset..*offset + 4)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, msg.to_string()))?;
+    *offset += 4;
+    Ok(u32::from_le_bytes(slice.try_into().unwrap()))
+}
+
 #[cfg(test)]
 mod tests {
     use time::macros::datetime;
@@ -296,4 +317,25 @@ mod tests {
         let ticker2 = symbol_map.get_instrument_ticker(2).unwrap();
         assert_eq!(&ticker2, tsla);
     }
+
+    #[test]
+    fn test_symbol_map_try_merge() {
+        let mut symbol_map = SymbolMap::new();
+        symbol_map.add_instrument("AAPL", 1);
+        let mut other = SymbolMap::new();
+        other.add_instrument("AAPL", 1);
+        other.add_instrument("TSLA", 2);
+        let mut conflict = SymbolMap::new();
+        conflict.add_instrument("MSFT", 2);
+        conflict.add_instrument("NVDA", 3);
+
+        // Test
+        symbol_map.try_merge(&other).unwrap();
+        let result = symbol_map.try_merge(&conflict);
+
+        // Validate
+        assert!(result.is_err());
+        assert_eq!(symbol_map.map.len(), 2);
+        assert_eq!(symbol_map.get_instrument_ticker(2).unwrap(), "TSLA");
+    }
 }
@@ -1,5 +1,5 @@
 # lib.pyi
-from typing import Dict, List, Optional
+from typing import Dict, Iterator, List, Optional
 from enum import Enum
 from typing import SupportsBytes
 import pandas
@@ -37,6 +37,8 @@ class Schema(Enum):
     TBBO: str
     BBO1_S: str
     BBO1_M: str
+    MBP10: str
+    MBO: str
     @classmethod
     def from_str(cls, value: str) -> "Schema": ...
 
@@ -46,6 +48,8 @@ class RType(Enum):
     TRADE: str
     TBBO: str
     BBO: str
+    MBP10: str
+    MBO: str
 
     @classmethod
     def from_int(cls, value: int) -> "RType": ...
@@ -225,6 +229,48 @@ class TradeMsg(RecordMsg):
     @property
     def sequence(self) -> int: ...
 
+class MboMsg(RecordMsg):
+    def __init__(
+        self,
+        instrument_id: int,
+        ts_event: int,
+        order_id: int,
+        price: int,
+        size: int,
+        action: str,
+        side: str,
+        flags: int,
+        channel_id: int,
+        ts_recv: int,
+        ts_in_delta: int,
+        sequence: int,
+    ) -> None: ...
+    @property
+    def order_id(self) -> int: ...
+    @property
+    def price(self) -> int: ...
+    @property
+    def pretty_price(self) -> float: ...
+    @property
+    def size(self) -> int: ...
+    @property
+    def action(self) -> int: ...
+    @property
+    def pretty_action(self) -> Action: ...
+    @property
+    def pretty_side(self) -> Side: ...
+    @property
+    def side(self) -> int: ...
+    @property
+    def flags(self) -> int: ...
+    @property
+    def channel_id(self) -> int: ...
+    @property
+    def ts_recv(self) -> int: ...
+    @property
+    def ts_in_delta(self) -> int: ...
+    @property
+    def sequence(self) -> int: ...
 
 class BboMsg(RecordMsg):
     def __init__(
@@ -304,6 +350,53 @@ class Mbp1Msg(RecordMsg):
     @property
     def levels(self) -> List[BidAskPair]: ...
 
+class Mbp10Msg(RecordMsg):
+    def __init__(
+        self,
+        instrument_id: int,
+        ts_event: int,
+        price: int,
+        size: int,
+        action: str,
+        side: str,
+        depth: int,
+        flags: int,
+        ts_recv: int,
+        ts_in_delta: int,
+        sequence: int,
+        discriminator: int,
+        levels: List[BidAskPair],
+    ) -> None: ...
+    @property
+    def price(self) -> int: ...
+    @property
+    def pretty_price(self) -> float: ...
+    @property
+    def size(self) -> int: ...
+    @property
+    def action(self) -> int: ...
+    @property
+    def pretty_action(self) -> Action: ...
+    @property
+    def pretty_side(self) -> Side: ...
+    @property
+    def side(self) -> int: ...
+    @property
+    def depth(self) -> int: ...
+    @property
+    def flags(self) -> int: ...
+    @property
+    def ts_recv(self) -> int: ...
+    @property
+    def ts_in_delta(self) -> int: ...
+    @property
+    def sequence(self) -> int: ...
+    @property
+    def discriminator(self) -> int: ...
+    @property
+    def levels(self) -> List[BidAskPair]: ...
+    def level(self, index: int) -> BidAskPair: ...
+
 class BufferStore(SupportsBytes):
     def __init__(self, data: bytes) -> None: ...
     def __bytes__(self) -> bytes: ...
@@ -316,6 +409,51 @@ class BufferStore(SupportsBytes):
     def decode_to_df(self, pretty_ts: bool, pretty_px: bool) -> pandas.DataFrame: ...
     def replay(self) -> Optional[RecordMsg]: ...
 
+class OhlcvBuilder:
+    def __init__(
+        self,
+        schema: Schema,
+        fill_empty: bool = False,
+        session_start: Optional[int] = None,
+        session_end: Optional[int] = None,
+    ) -> None: ...
+    def update(self, record: RecordMsg) -> List[OhlcvMsg]: ...
+    def flush(self) -> List[OhlcvMsg]: ...
+    def resample(self, records: List[RecordMsg]) -> List[OhlcvMsg]: ...
+
+class SequenceEvent:
+    class Gap(SequenceEvent):
+        instrument_id: int
+        expected: int
+        received: int
+    class Duplicate(SequenceEvent):
+        instrument_id: int
+        sequence: int
+    class Reset(SequenceEvent):
+        instrument_id: int
+        last: int
+        received: int
+
+class Subscriber(Iterator[RecordMsg]):
+    def __init__(
+        self,
+        address: str,
+        instrument_ids: Optional[List[int]] = None,
+        tickers: Optional[List[str]] = None,
+        handshake: bool = False,
+        reconnect_on_eof: bool = True,
+        max_retries: Optional[int] = None,
+        reset_on_reconnect: bool = False,
+    ) -> None: ...
+    @property
+    def metadata(self) -> Optional[Metadata]: ...
+    @property
+    def reconnects(self) -> int: ...
+    def take_events(self) -> List[SequenceEvent]: ...
+    def reset_sequences(self) -> None: ...
+    def __iter__(self) -> "Subscriber": ...
+    def __next__(self) -> RecordMsg: ...
+
 # -- Trading -- 
 
 class SignalInstructions:
@@ -2,13 +2,18 @@ use mbn::{
     backtest::{
         BacktestData, Parameters, SignalInstructions, Signals, StaticStats, TimeseriesStats, Trades,
     },
+    bars::OhlcvBuilder,
     enums::{Action, RType, Schema, Side},
     live::{AccountSummary, LiveData},
     metadata::Metadata,
     python::buffer::BufferStore,
     python::encode::PyRecordEncoder,
     python::records::RecordMsg,
-    records::{BboMsg, BidAskPair, Mbp1Msg, OhlcvMsg, RecordHeader, TbboMsg, TradeMsg},
+    python::subscriber::PySubscriber,
+    records::{
+        BboMsg, BidAskPair, MboMsg, Mbp10Msg, Mbp1Msg, OhlcvMsg, RecordHeader, TbboMsg, TradeMsg,
+    },
+    subscriber::SequenceEvent,
     symbols::SymbolMap,
 };
 use pyo3::{prelude::*, PyClass};
@@ -31,6 +36,8 @@ fn python_mbn(_py: Python<'_>, m: &Bound<PyModule>) -> PyResult<()> {
     checked_add_class::<RecordHeader>(m)?;
     checked_add_class::<OhlcvMsg>(m)?;
     checked_add_class::<Mbp1Msg>(m)?;
+    checked_add_class::<Mbp10Msg>(m)?;
+    checked_add_class::<MboMsg>(m)?;
     checked_add_class::<TradeMsg>(m)?;
     checked_add_class::<TbboMsg>(m)?;
     checked_add_class::<BboMsg>(m)?;
@@ -47,6 +54,9 @@ fn python_mbn(_py: Python<'_>, m: &Bound<PyModule>) -> PyResult<()> {
     checked_add_class::<LiveData>(m)?;
     checked_add_class::<AccountSummary>(m)?;
     checked_add_class::<PyRecordEncoder>(m)?;
+    checked_add_class::<OhlcvBuilder>(m)?;
+    checked_add_class::<PySubscriber>(m)?;
+    checked_add_class::<SequenceEvent>(m)?;
 
     Ok(())
 }
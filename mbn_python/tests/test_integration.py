@@ -1,3 +1,5 @@
+import socket
+import threading
 import unittest
 from mbn import (
     Side,
@@ -23,6 +25,9 @@ from mbn import (
     AccountSummary,
     LiveData,
     PyRecordEncoder,
+    OhlcvBuilder,
+    Subscriber,
+    SequenceEvent,
 )
 from pandas import pandas
 
@@ -1041,6 +1046,69 @@ class IntegrationTests(unittest.TestCase):
             self.assertTrue(record.ts_event > ts_event)
             record = buffer_obj.replay()
 
+    def test_ohlcv_builder(self):
+        second = 1_000_000_000
+        trades = [
+            TradeMsg(1, 10 * second, 10, 1, Action.TRADE, Side.ASK, 0, 0, 0, 0, 0),
+            TradeMsg(1, 10 * second + 5, 12, 2, Action.TRADE, Side.BID, 0, 0, 0, 0, 0),
+            TradeMsg(1, 11 * second, 11, 3, Action.TRADE, Side.ASK, 0, 0, 0, 0, 0),
+        ]
+
+        # Test
+        builder = OhlcvBuilder(Schema.OHLCV1_S)
+        bars = builder.resample(trades)
+
+        # Validate
+        self.assertEqual(len(bars), 2)
+        self.assertEqual(bars[0].ts_event, 10 * second)
+        self.assertEqual(bars[0].high, 12)
+        self.assertEqual(bars[0].volume, 3)
+        self.assertEqual(bars[1].close, 11)
+
+    def test_subscriber(self):
+        def encode(sequences):
+            encoder = PyRecordEncoder()
+            pair = BidAskPair(1, 2, 3, 4, 5, 6)
+            encoder.encode_records(
+                [
+                    Mbp1Msg(1, s, 100, 1, Action.ADD, Side.ASK, 0, 0, s, 0, s, 0, [pair])
+                    for s in sequences
+                ]
+            )
+            return bytes(encoder.get_encoded_data())
+
+        # The second connection resends 3 and misses 5.
+        connections = [encode([1, 2, 3]), encode([3, 4, 6])]
+        listener = socket.create_server(("127.0.0.1", 0))
+        port = listener.getsockname()[1]
+
+        def serve():
+            for data in connections:
+                conn, _ = listener.accept()
+                with conn:
+                    conn.sendall(data)
+
+        threading.Thread(target=serve, daemon=True).start()
+
+        # Test
+        subscriber = Subscriber(f"127.0.0.1:{port}", max_retries=3)
+        sequences = [next(subscriber).sequence for _ in range(5)]
+        events = subscriber.take_events()
+
+        # Validate
+        self.assertEqual(sequences, [1, 2, 3, 4, 6])
+        self.assertEqual(subscriber.reconnects, 1)
+        self.assertEqual(
+            events,
+            [
+                SequenceEvent.Duplicate(instrument_id=1, sequence=3),
+                SequenceEvent.Gap(instrument_id=1, expected=5, received=6),
+            ],
+        )
+
+        # Cleanup
+        listener.close()
+
 
 if __name__ == "__main__":
     unittest.main()